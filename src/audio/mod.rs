@@ -0,0 +1,144 @@
+use std::{
+    error::Error,
+    fmt,
+    process::Command,
+    str, thread,
+    time::{Duration, Instant},
+};
+
+use super::bluetooth::DeviceInfo;
+
+// Returns the name of the current system audio output device, using
+// SwitchAudioSource (`brew install switchaudio-osx`).
+pub fn current_output_device() -> Result<Option<String>, Box<dyn Error>> {
+    let output = Command::new(switch_audio_source_path())
+        .args(["-c", "-t", "output"])
+        .output()?;
+
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    Ok(parse_current_output(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+// Makes the named device the system audio output. A device that has only just
+// connected can take a few seconds to show up as an output, so this keeps trying
+// until the timeout elapses.
+pub fn set_output_device(name: &str, timeout: Duration) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+
+    loop {
+        let output = Command::new(switch_audio_source_path())
+            .args(set_output_args(name))
+            .output()?;
+
+        if output.status.success() {
+            return Ok(());
+        }
+
+        if started.elapsed() >= timeout {
+            return Err(Box::new(AudioError::new(&format!(
+                "Failed to set the audio output to {} : {}",
+                name,
+                str::from_utf8(&output.stderr).unwrap_or_default().trim()
+            ))));
+        }
+
+        thread::sleep(Duration::from_millis(500));
+    }
+}
+
+fn set_output_args(name: &str) -> Vec<&str> {
+    vec!["-t", "output", "-s", name]
+}
+
+pub fn parse_current_output(output: &str) -> Option<String> {
+    let name = output.trim();
+
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+// Flags the devices whose name matches the current audio output.
+pub fn mark_active_output(devices: &mut [DeviceInfo], output_name: &str) {
+    for device in devices {
+        device.active_output = device.name == output_name;
+    }
+}
+
+fn switch_audio_source_path() -> String {
+    match std::env::var("SWITCHAUDIOSOURCE_PATH") {
+        Ok(val) => format!("{}/SwitchAudioSource", val),
+        Err(_) => String::from("SwitchAudioSource"),
+    }
+}
+
+#[derive(Debug)]
+pub struct AudioError {
+    details: String,
+}
+
+impl AudioError {
+    fn new(msg: &str) -> AudioError {
+        AudioError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for AudioError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_current_output_trims_name() {
+        assert_eq!(
+            parse_current_output("AirPods Max\n"),
+            Some(String::from("AirPods Max"))
+        );
+        assert_eq!(parse_current_output("\n"), None);
+    }
+
+    #[test]
+    fn set_output_args_select_output_by_name() {
+        assert_eq!(
+            set_output_args("AirPods Pro"),
+            vec!["-t", "output", "-s", "AirPods Pro"]
+        );
+    }
+
+    #[test]
+    fn mark_active_output_flags_matching_device() {
+        let mut devices = vec![
+            DeviceInfo {
+                name: String::from("AirPods Max"),
+                connected: true,
+                ..Default::default()
+            },
+            DeviceInfo {
+                name: String::from("AirPods Pro"),
+                connected: true,
+                ..Default::default()
+            },
+        ];
+
+        mark_active_output(&mut devices, "AirPods Pro");
+
+        assert!(!devices[0].active_output);
+        assert!(devices[1].active_output);
+    }
+}
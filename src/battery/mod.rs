@@ -0,0 +1,212 @@
+use std::{collections::HashMap, error::Error, fmt, process::Command, str};
+
+use serde::{Deserialize, Serialize};
+
+use super::bluetooth::Address;
+
+// ioreg keys that hold a device's overall battery level, in order of preference.
+// AirPods report BatteryPercentCombined, Magic accessories BatteryPercent.
+const BATTERY_KEYS: &[&str] = &[
+    "BatteryPercentCombined",
+    "BatteryPercent",
+    "BatteryPercentSingle",
+];
+
+// A device's battery levels in percent, None when they aren't reported. Only
+// AirPods report each bud and the case.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatteryInfo {
+    pub left: Option<u8>,
+    pub right: Option<u8>,
+    pub case: Option<u8>,
+    pub combined: Option<u8>,
+}
+
+impl BatteryInfo {
+    // The overall level from the buds, the lower bud is the one that matters.
+    pub fn from_buds(left: Option<u8>, right: Option<u8>, case: Option<u8>) -> Self {
+        BatteryInfo {
+            left,
+            right,
+            case,
+            combined: match (left, right) {
+                (Some(left), Some(right)) => Some(left.min(right)),
+                (left, right) => left.or(right),
+            },
+        }
+    }
+
+    pub fn has_buds(&self) -> bool {
+        self.left.is_some() || self.right.is_some() || self.case.is_some()
+    }
+}
+
+// e.g. `L 80% · R 75% · Case 60%`, or just `80%` without per bud levels.
+impl fmt::Display for BatteryInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.has_buds() {
+            return match self.combined {
+                Some(combined) => write!(f, "{}%", combined),
+                None => Ok(()),
+            };
+        }
+
+        let levels = [("L", self.left), ("R", self.right), ("Case", self.case)]
+            .into_iter()
+            .filter_map(|(label, level)| Some(format!("{} {}%", label, level?)))
+            .collect::<Vec<String>>();
+
+        write!(f, "{}", levels.join(" · "))
+    }
+}
+
+// Reads the battery levels of every device ioreg knows about, keyed by address.
+pub fn battery_levels() -> Result<HashMap<Address, BatteryInfo>, Box<dyn Error>> {
+    let mut levels = HashMap::new();
+
+    for key in BATTERY_KEYS {
+        let output = Command::new("ioreg").args(ioreg_args(key)).output()?;
+
+        if !output.status.success() {
+            continue;
+        }
+
+        // Devices found by an earlier, preferred key keep that level
+        for (address, battery) in parse_ioreg(&String::from_utf8_lossy(&output.stdout)) {
+            levels.entry(address).or_insert(battery);
+        }
+    }
+
+    Ok(levels)
+}
+
+fn ioreg_args(key: &str) -> Vec<&str> {
+    vec!["-r", "-l", "-k", key]
+}
+
+// Parses `ioreg -r -l` output, where each `+-o` entry is followed by its
+// `"Key" = value` properties. Entries without an address or battery are skipped.
+pub fn parse_ioreg(output: &str) -> HashMap<Address, BatteryInfo> {
+    let mut levels = HashMap::new();
+    let mut properties = HashMap::new();
+
+    for line in output.lines().chain(["+-o"]) {
+        let line = line.trim_start_matches([' ', '|']).trim();
+
+        if line.starts_with("+-o") {
+            if let Some((address, battery)) = battery_entry(&properties) {
+                levels.insert(address, battery);
+            }
+            properties.clear();
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once(" = ") {
+            properties.insert(key.trim_matches('"'), value.trim().trim_matches('"'));
+        }
+    }
+
+    levels
+}
+
+fn battery_entry(properties: &HashMap<&str, &str>) -> Option<(Address, BatteryInfo)> {
+    let address = Address::new(properties.get("DeviceAddress")?);
+    let level = |key: &str| properties.get(key)?.parse::<u8>().ok();
+
+    let mut battery = BatteryInfo::from_buds(
+        level("BatteryPercentLeft"),
+        level("BatteryPercentRight"),
+        level("BatteryPercentCase"),
+    );
+    if let Some(combined) = BATTERY_KEYS.iter().find_map(|key| level(key)) {
+        battery.combined = Some(combined);
+    }
+
+    if battery.combined.is_none() && !battery.has_buds() {
+        return None;
+    }
+
+    Some((address, battery))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IOREG: &str = r#"+-o AppleDeviceManagementHIDEventService  <class AppleDeviceManagementHIDEventService, id 0x100000a3c, registered, matched, active, busy 0 (0 ms), retain 7>
+    {
+      "LowBatteryNotificationPercentage" = 10
+      "BatteryPercentCombined" = 75
+      "BatteryPercentLeft" = 80
+      "BatteryPercentRight" = 75
+      "BatteryPercentCase" = 60
+      "DeviceAddress" = "80-3b-5c-c2-b1-7f"
+      "Product" = "AirPods Pro"
+    }
+
++-o AppleDeviceManagementHIDEventService  <class AppleDeviceManagementHIDEventService, id 0x100000b2d, registered, matched, active, busy 0 (0 ms), retain 7>
+    {
+      "BatteryPercent" = 52
+      "DeviceAddress" = "f0:b3:ec:12:4a:9d"
+      "Product" = "Magic Keyboard"
+    }
+
++-o IOHIDInterface  <class IOHIDInterface, id 0x100000b40, registered, matched, active, busy 0 (0 ms), retain 6>
+    {
+      "BatteryPercent" = 90
+      "Product" = "Internal Trackpad"
+    }
+"#;
+
+    #[test]
+    fn parse_ioreg_maps_battery_levels_to_addresses() {
+        let levels = parse_ioreg(IOREG);
+
+        assert_eq!(levels.len(), 2);
+        assert_eq!(
+            levels[&Address::new("80:3B:5C:C2:B1:7F")],
+            BatteryInfo {
+                left: Some(80),
+                right: Some(75),
+                case: Some(60),
+                combined: Some(75),
+            }
+        );
+        assert_eq!(
+            levels[&Address::new("f0-b3-ec-12-4a-9d")],
+            BatteryInfo {
+                combined: Some(52),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn battery_info_shows_each_bud_and_the_case() {
+        assert_eq!(
+            BatteryInfo::from_buds(Some(80), Some(75), Some(60)).to_string(),
+            "L 80% · R 75% · Case 60%"
+        );
+        assert_eq!(
+            BatteryInfo::from_buds(Some(80), None, None).to_string(),
+            "L 80%"
+        );
+        assert_eq!(
+            BatteryInfo {
+                combined: Some(52),
+                ..Default::default()
+            }
+            .to_string(),
+            "52%"
+        );
+    }
+
+    #[test]
+    fn ioreg_args_look_up_entries_with_the_key() {
+        assert_eq!(
+            ioreg_args("BatteryPercentCombined"),
+            vec!["-r", "-l", "-k", "BatteryPercentCombined"]
+        );
+    }
+}
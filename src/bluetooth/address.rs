@@ -0,0 +1,164 @@
+use std::fmt;
+
+use super::{BluetoothError, ErrorKind};
+
+// A device address normalized to blueutil's lowercase, hyphen separated form,
+// so the same device compares equal whether it was written as
+// `80:3B:5C:C2:B1:7F`, `80-3b-5c-c2-b1-7f` or `803b5cc2b17f`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address(String);
+
+impl Address {
+    pub fn new(address: &str) -> Self {
+        let digits = address
+            .chars()
+            .filter(|x| *x != ':' && *x != '-')
+            .collect::<String>()
+            .to_lowercase();
+
+        // Anything that isn't six octets is kept as is (lowercased) rather than
+        // being mangled.
+        if digits.len() != 12 || !digits.chars().all(|x| x.is_ascii_alphanumeric()) {
+            return Address(address.to_lowercase());
+        }
+
+        let octets = digits
+            .as_bytes()
+            .chunks(2)
+            .map(|x| String::from_utf8_lossy(x).to_string())
+            .collect::<Vec<String>>();
+
+        Address(octets.join("-"))
+    }
+
+    // Only accepts MAC addresses, for device ids that can't be names, e.g. when
+    // pairing a device that isn't in the paired list yet.
+    pub fn parse(address: &str) -> Result<Self, BluetoothError> {
+        let parsed = Address::new(address);
+        if !parsed.is_mac() {
+            return Err(invalid_address(address));
+        }
+
+        Ok(parsed)
+    }
+
+    // Made up of hex digits and separators, so it was meant as an address
+    // rather than a device name even when it isn't a valid one.
+    pub fn looks_like_mac(address: &str) -> bool {
+        address.contains([':', '-'])
+            && address
+                .chars()
+                .all(|x| x.is_ascii_hexdigit() || x == ':' || x == '-')
+    }
+
+    // Rejects device ids meant as an address that aren't a valid one, and ids
+    // blueutil would read as an option. Anything else may be a name, which
+    // blueutil accepts as well.
+    pub fn validate_device_id(device_id: &str) -> Result<(), BluetoothError> {
+        let mistyped = Address::looks_like_mac(device_id) && !Address::new(device_id).is_mac();
+        if mistyped || device_id.trim().is_empty() || device_id.starts_with('-') {
+            return Err(invalid_address(device_id));
+        }
+
+        Ok(())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    // True when the value normalized to six hex octets, i.e. it looks like a real
+    // MAC address rather than a device name.
+    pub fn is_mac(&self) -> bool {
+        self.0.len() == 17
+            && self
+                .0
+                .split('-')
+                .all(|x| x.len() == 2 && x.chars().all(|c| c.is_ascii_hexdigit()))
+    }
+}
+
+pub(super) fn invalid_address(address: &str) -> BluetoothError {
+    BluetoothError::with_kind(
+        ErrorKind::InvalidAddress,
+        &format!("'{}' isn't a MAC address, e.g. 80-3b-5c-c2-b1-7f", address),
+    )
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<&str> for Address {
+    fn from(address: &str) -> Self {
+        Address::new(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_normalizes_separators_and_case() {
+        let expected = Address::new("80-3b-5c-c2-b1-7f");
+
+        assert_eq!(expected.as_str(), "80-3b-5c-c2-b1-7f");
+        assert_eq!(Address::new("80:3B:5C:C2:B1:7F"), expected);
+        assert_eq!(Address::new("80-3B-5C-C2-B1-7F"), expected);
+        assert_eq!(Address::new("803b5cc2b17f"), expected);
+    }
+
+    #[test]
+    fn address_parse_rejects_anything_but_mac_addresses() {
+        assert_eq!(
+            Address::parse("80:3B:5C:C2:B1:7F").unwrap().as_str(),
+            "80-3b-5c-c2-b1-7f"
+        );
+
+        for address in ["AirPods Pro", "80-3b-5c-c2-b1", "--power 0", ""] {
+            assert_eq!(
+                Address::parse(address).unwrap_err().kind(),
+                ErrorKind::InvalidAddress
+            );
+        }
+    }
+
+    #[test]
+    fn address_looks_like_mac() {
+        assert!(Address::looks_like_mac("80-3b-5c-c2-b1"));
+        assert!(!Address::looks_like_mac("AirPods-Pro"));
+        assert!(!Address::looks_like_mac("beef"));
+    }
+
+    #[test]
+    fn address_validate_device_id_allows_names() {
+        for device_id in ["80:3B:5C:C2:B1:7F", "AirPods Pro", "connected-address"] {
+            assert!(Address::validate_device_id(device_id).is_ok());
+        }
+
+        for device_id in ["80-3b-5c-c2-b1", "--power", " "] {
+            assert_eq!(
+                Address::validate_device_id(device_id).unwrap_err().kind(),
+                ErrorKind::InvalidAddress
+            );
+        }
+    }
+
+    #[test]
+    fn address_leaves_unrecognized_values_lowercased() {
+        assert_eq!(
+            Address::new("Connected-Address").as_str(),
+            "connected-address"
+        );
+    }
+
+    #[test]
+    fn address_is_mac() {
+        assert!(Address::new("80:3B:5C:C2:B1:7F").is_mac());
+        assert!(!Address::new("connected-address").is_mac());
+        assert!(!Address::new("MyHeadphones").is_mac());
+    }
+}
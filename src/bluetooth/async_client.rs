@@ -0,0 +1,460 @@
+use std::{
+    io,
+    path::PathBuf,
+    process::Stdio,
+    str,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use log::{trace, warn};
+use tokio::{process::Command, task::JoinSet, time};
+
+use super::{
+    audit, blueutil_missing, check_status, decode_output, parse_devices, read_version,
+    require_minimum_version, success_status, validate_device_ids, with_format, BluetoothClient,
+    BluetoothError, BlueutilVersion, Capability, ConnectResult, DeviceInfo, DeviceListOptions,
+    DryRunCommandRunner, ErrorKind, HistoryAction,
+};
+
+// The async counterpart of Client, so slow blueutil calls and operations on
+// several devices can run concurrently.
+#[async_trait]
+pub trait AsyncClient: Send + Sync {
+    async fn connect_to_device(&self, address: &str) -> Result<(), BluetoothError>;
+    async fn disconnect_from_device(&self, address: &str) -> Result<(), BluetoothError>;
+    async fn get_device_list(&self) -> Result<Vec<DeviceInfo>, BluetoothError>;
+    // Looks up a single device, returning None if it can't be found.
+    async fn get_device(&self, address: &str) -> Option<DeviceInfo>;
+}
+
+// Runs blueutil through tokio, killing it once the timeout passes. With dry run
+// set, commands that would change anything are printed instead.
+pub struct AsyncBlueutilClient {
+    blueutil_path: String,
+    timeout: Duration,
+    dry_run: bool,
+    // Where every command is logged like AuditCommandRunner does, nothing is
+    // logged without it
+    audit_log_path: Option<PathBuf>,
+    version: OnceLock<Option<BlueutilVersion>>,
+}
+
+impl AsyncBlueutilClient {
+    pub fn new(blueutil_path: Option<&str>, timeout: Duration, dry_run: bool) -> Self {
+        AsyncBlueutilClient {
+            blueutil_path: super::blueutil_path(blueutil_path),
+            timeout,
+            dry_run,
+            audit_log_path: None,
+            version: OnceLock::new(),
+        }
+    }
+
+    pub fn with_audit_log(mut self, path: PathBuf) -> Self {
+        self.audit_log_path = Some(path);
+        self
+    }
+
+    // Picks the output format the same way BlueutilClient does, reading the
+    // version once per client.
+    async fn json_format(&self) -> Result<bool, BluetoothError> {
+        let version = match self.version.get() {
+            Some(version) => *version,
+            None => {
+                let output = self.run_command(vec!["--version"]).await?;
+
+                *self.version.get_or_init(|| read_version(&output))
+            }
+        };
+
+        Ok(require_minimum_version(version)?.is_none_or(|x| x >= BlueutilVersion::JSON_FORMAT))
+    }
+
+    async fn run_command(&self, args: Vec<&str>) -> Result<std::process::Output, BluetoothError> {
+        validate_device_ids(&args)?;
+        let args = args.into_iter().map(String::from).collect::<Vec<String>>();
+        let started = Instant::now();
+        let result = self.spawn(&args).await;
+
+        if let Some(path) = &self.audit_log_path {
+            audit::record_command(path, &self.blueutil_path, &args, started.elapsed(), &result);
+        }
+
+        result
+    }
+
+    async fn spawn(&self, args: &[String]) -> Result<std::process::Output, BluetoothError> {
+        if self.dry_run && DryRunCommandRunner::is_mutating(args) {
+            eprintln!("Dry run : {} {}", self.blueutil_path, args.join(" "));
+
+            return Ok(std::process::Output {
+                status: success_status(),
+                stdout: Default::default(),
+                stderr: Default::default(),
+            });
+        }
+
+        let child = Command::new(&self.blueutil_path)
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Dropping the child when the timeout passes kills it
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::NotFound => blueutil_missing(&self.blueutil_path),
+                _ => BluetoothError::new(&err.to_string()),
+            })?;
+
+        match time::timeout(self.timeout, child.wait_with_output()).await {
+            Ok(output) => Ok(output?),
+            Err(_) => Err(BluetoothError::with_kind(
+                ErrorKind::Timeout,
+                &format!(
+                    "{} {} didn't finish within {}s and was killed",
+                    self.blueutil_path,
+                    args.join(" "),
+                    self.timeout.as_secs_f64()
+                ),
+            )),
+        }
+    }
+
+    async fn list_devices(
+        &self,
+        args: Vec<&str>,
+        error: &str,
+    ) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        let json = self.json_format().await?;
+        let output = self.run_command(with_format(json, args)).await?;
+
+        check_status(&output, ErrorKind::Other, error)?;
+
+        let results = decode_output(&output.stdout);
+        let (devices, diagnostics) = parse_devices(json, &results)?;
+        diagnostics.log();
+
+        Ok(devices)
+    }
+}
+
+#[async_trait]
+impl AsyncClient for AsyncBlueutilClient {
+    async fn connect_to_device(&self, address: &str) -> Result<(), BluetoothError> {
+        let output = self.run_command(vec!["--connect", address]).await?;
+
+        trace!("{:?}", &output.stdout);
+        trace!("{:?}", &output.stderr);
+
+        check_status(
+            &output,
+            ErrorKind::ConnectFailed,
+            &format!("Failed to connect to '{}'", address),
+        )
+    }
+
+    async fn disconnect_from_device(&self, address: &str) -> Result<(), BluetoothError> {
+        let output = self
+            .run_command(vec!["--disconnect", address, "--info", address])
+            .await?;
+
+        trace!("{:?}", &output.stdout);
+        trace!("{:?}", &output.stderr);
+
+        check_status(
+            &output,
+            ErrorKind::DisconnectFailed,
+            &format!("Failed to disconnect from '{}'", address),
+        )
+    }
+
+    async fn get_device_list(&self) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        self.list_devices(vec!["--paired"], "Failed to list paired devices")
+            .await
+    }
+
+    async fn get_device(&self, address: &str) -> Option<DeviceInfo> {
+        match self
+            .list_devices(
+                vec!["--info", address],
+                &format!("Failed to get device '{}'", address),
+            )
+            .await
+        {
+            Ok(devices) => devices.into_iter().next(),
+            Err(err) => {
+                warn!("Failed to get device '{}' : {}", address, err);
+                None
+            }
+        }
+    }
+}
+
+// The async API. Backends without an async client fall back to their sync
+// client, which blocks the task while it runs.
+impl BluetoothClient {
+    // Goes through the same cache and recent date lookup as get_device_list, so
+    // both list the same devices. Only the listing itself runs asynchronously.
+    pub async fn get_device_list_async(
+        &self,
+        options: DeviceListOptions,
+    ) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        let async_client = match &self.async_client {
+            Some(async_client) => async_client,
+            None => return self.get_device_list(options),
+        };
+
+        let cached = self.device_list_cache.as_ref().and_then(|x| x.get());
+        let devices = match cached {
+            Some(devices) => devices,
+            None => {
+                let devices = async_client.get_device_list().await?;
+                if let Some(cache) = &self.device_list_cache {
+                    cache.update(&devices);
+                }
+
+                devices
+            }
+        };
+
+        self.prepare_device_list(devices, options)
+    }
+
+    pub async fn connect_async(&self, address: &str) -> Result<(), BluetoothError> {
+        self.capabilities().require(Capability::Connect)?;
+
+        let result = match &self.async_client {
+            Some(async_client) => async_client.connect_to_device(address).await,
+            None => self.blueutil_client.connect_to_device(address),
+        };
+        self.invalidate_cache();
+        self.record_history(HistoryAction::Connect, address, &result);
+
+        result
+    }
+
+    // Connects every device at once, returning the results in the order of the
+    // addresses. A failure to connect one device doesn't stop the others.
+    pub async fn connect_many_async(&self, addresses: &[String]) -> Vec<ConnectResult> {
+        // connect_many reports the unsupported error for every device
+        let async_client = match &self.async_client {
+            Some(async_client) if self.capabilities().supports(Capability::Connect) => {
+                async_client.clone()
+            }
+            _ => return self.connect_many(addresses),
+        };
+
+        let mut tasks = JoinSet::new();
+        for (index, address) in addresses.iter().enumerate() {
+            let async_client = async_client.clone();
+            let address = address.clone();
+
+            tasks.spawn(async move { (index, async_client.connect_to_device(&address).await) });
+        }
+
+        let mut results = addresses
+            .iter()
+            .map(|address| -> ConnectResult {
+                (
+                    address.clone(),
+                    Err(BluetoothError::new("Connecting was cancelled")),
+                )
+            })
+            .collect::<Vec<ConnectResult>>();
+
+        while let Some(joined) = tasks.join_next().await {
+            match joined {
+                Ok((index, result)) => results[index].1 = result,
+                Err(err) => warn!("Connect task failed : {}", err),
+            }
+        }
+
+        self.invalidate_cache();
+        for (address, result) in &results {
+            self.record_history(HistoryAction::Connect, address, result);
+        }
+
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, os::unix::fs::PermissionsExt, path::PathBuf, process};
+
+    use super::*;
+
+    // A stand in for blueutil that runs the script.
+    fn fake_blueutil(name: &str, script: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("{}-{}", name, process::id()));
+        fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+
+        path
+    }
+
+    #[tokio::test]
+    async fn async_blueutil_client_lists_devices() {
+        let path = fake_blueutil(
+            "async-blueutil-list",
+            r#"echo '[{"address": "80-3b-5c-c2-b1-7f", "name": "AirPods Pro", "connected": true}]'"#,
+        );
+        let client = AsyncBlueutilClient::new(path.to_str(), Duration::from_secs(5), false);
+
+        let devices = client.get_device_list().await.unwrap();
+
+        assert_eq!(
+            devices,
+            vec![DeviceInfo {
+                name: String::from("AirPods Pro"),
+                address: String::from("80-3b-5c-c2-b1-7f"),
+                connected: true,
+                ..Default::default()
+            }]
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn async_blueutil_client_uses_text_output_for_old_versions() {
+        let path = fake_blueutil(
+            "async-blueutil-old",
+            r#"case "$*" in
+    --version) echo 2.1.0 ;;
+    --paired) echo 'address: 80-3b-5c-c2-b1-7f, connected (master, 0 dBm), not favourite, paired, name: "AirPods Pro", recent access date: -' ;;
+    *) exit 1 ;;
+esac"#,
+        );
+        let client = AsyncBlueutilClient::new(path.to_str(), Duration::from_secs(5), false);
+
+        let devices = client.get_device_list().await.unwrap();
+
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name, "AirPods Pro");
+        assert!(devices[0].connected);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn async_blueutil_client_writes_the_audit_log() {
+        let path = fake_blueutil("async-blueutil-audit", "echo '[]'");
+        let log_path = env::temp_dir().join(format!("async-audit-{}.log", process::id()));
+        let _ = fs::remove_file(&log_path);
+        let client = AsyncBlueutilClient::new(path.to_str(), Duration::from_secs(5), false)
+            .with_audit_log(log_path.clone());
+
+        client.get_device_list().await.unwrap();
+
+        let log = fs::read_to_string(&log_path).unwrap();
+        let lines = log.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains(" --version ") && lines[0].ends_with(" exit 0"));
+        assert!(lines[1].contains(" --format json --paired "));
+
+        fs::remove_file(path).unwrap();
+        fs::remove_file(log_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn get_device_list_async_uses_the_device_list_cache() {
+        let calls = env::temp_dir().join(format!("async-blueutil-calls-{}", process::id()));
+        let _ = fs::remove_file(&calls);
+        let path = fake_blueutil(
+            "async-blueutil-cache",
+            &format!(
+                r#"echo "$*" >> {}
+echo '[{{"address": "80-3b-5c-c2-b1-7f", "name": "AirPods Pro", "connected": true}}]'"#,
+                calls.display()
+            ),
+        );
+        let client = BluetoothClient {
+            async_client: Some(std::sync::Arc::new(AsyncBlueutilClient::new(
+                path.to_str(),
+                Duration::from_secs(5),
+                false,
+            ))),
+            device_list_cache: Some(super::super::DeviceListCache::new(
+                Duration::from_secs(30),
+                None,
+                Box::new(crate::clock::SystemClock),
+            )),
+            ..BluetoothClient::with_client(Box::new(super::super::FakeClient::with_devices(vec![])))
+        };
+
+        for _ in 0..2 {
+            let devices = client
+                .get_device_list_async(DeviceListOptions::new_default_all_devices())
+                .await
+                .unwrap();
+            assert_eq!(devices[0].address, "80-3b-5c-c2-b1-7f");
+        }
+
+        let calls_made = fs::read_to_string(&calls).unwrap();
+        assert_eq!(
+            calls_made
+                .lines()
+                .filter(|x| x.contains("--paired"))
+                .count(),
+            1
+        );
+
+        fs::remove_file(path).unwrap();
+        fs::remove_file(calls).unwrap();
+    }
+
+    #[tokio::test]
+    async fn async_blueutil_client_rejects_malformed_addresses() {
+        // Would succeed if it ran
+        let path = fake_blueutil("async-blueutil-invalid", "exit 0");
+        let client = AsyncBlueutilClient::new(path.to_str(), Duration::from_secs(5), false);
+
+        for address in ["80-3b-5c-c2-b1", "--power"] {
+            let err = client.connect_to_device(address).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidAddress);
+        }
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn async_blueutil_client_kills_hung_commands() {
+        let path = fake_blueutil("async-blueutil-hang", "sleep 5");
+        let client = AsyncBlueutilClient::new(path.to_str(), Duration::from_millis(50), false);
+
+        let err = client.get_device_list().await.unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::Timeout);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn connect_many_async_keeps_the_order_of_the_addresses() {
+        let path = fake_blueutil("async-blueutil-connect", "sleep 0.1");
+        let client = BluetoothClient {
+            async_client: Some(std::sync::Arc::new(AsyncBlueutilClient::new(
+                path.to_str(),
+                Duration::from_secs(5),
+                false,
+            ))),
+            ..BluetoothClient::with_client(Box::new(super::super::FakeClient::with_devices(vec![])))
+        };
+
+        let addresses = vec![String::from("a"), String::from("b"), String::from("c")];
+        let results = client.connect_many_async(&addresses).await;
+
+        assert_eq!(
+            results
+                .iter()
+                .map(|(address, result)| (address.as_str(), result.is_ok()))
+                .collect::<Vec<_>>(),
+            vec![("a", true), ("b", true), ("c", true)]
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+}
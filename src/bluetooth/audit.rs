@@ -0,0 +1,195 @@
+use std::{
+    env,
+    error::Error,
+    fmt,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::warn;
+
+use super::CommandRunner;
+
+pub const AUDIT_LOG_FILE: &str = "airpod-alfred.log";
+
+// Next to other apps' logs, so it shows up in Console.
+pub fn default_audit_log_path() -> Option<PathBuf> {
+    env::var("HOME").ok().map(|home| {
+        PathBuf::from(home)
+            .join("Library")
+            .join("Logs")
+            .join(AUDIT_LOG_FILE)
+    })
+}
+
+// Appends a line per command with its arguments, how long it took and how it
+// exited, to help track down intermittent blueutil failures. Failing to write
+// the log doesn't fail the command.
+pub(super) struct AuditCommandRunner {
+    pub(super) command_runner: Box<dyn CommandRunner>,
+    pub(super) path: PathBuf,
+}
+
+impl CommandRunner for AuditCommandRunner {
+    fn run_command(
+        &self,
+        command: &str,
+        args: Vec<String>,
+    ) -> Result<std::process::Output, Box<dyn Error>> {
+        let started = Instant::now();
+        let result = self.command_runner.run_command(command, args.clone());
+
+        record_command(&self.path, command, &args, started.elapsed(), &result);
+
+        result
+    }
+
+    // The commands run side by side, so each is logged with how long they all
+    // took together.
+    fn run_commands(
+        &self,
+        command: &str,
+        args: Vec<Vec<String>>,
+    ) -> Vec<Result<std::process::Output, Box<dyn Error>>> {
+        let started = Instant::now();
+        let results = self.command_runner.run_commands(command, args.clone());
+        let elapsed = started.elapsed();
+
+        for (args, result) in args.iter().zip(&results) {
+            record_command(&self.path, command, args, elapsed, result);
+        }
+
+        results
+    }
+}
+
+// Appends the command's line to the log at `path`, also used by the async
+// client, which runs its commands without a CommandRunner.
+pub(super) fn record_command<E: fmt::Display>(
+    path: &Path,
+    command: &str,
+    args: &[String],
+    elapsed: Duration,
+    result: &Result<std::process::Output, E>,
+) {
+    let line = audit_line(Utc::now(), command, args, elapsed, result);
+
+    if let Err(err) = append(path, &line) {
+        warn!("Failed to write the audit log {} : {}", path.display(), err);
+    }
+}
+
+fn append(path: &Path, line: &str) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)?;
+
+    Ok(())
+}
+
+// e.g. `2022-08-01T12:00:00Z blueutil --connect 80-3b-5c-c2-b1-7f 1520ms exit 0`
+fn audit_line<E: fmt::Display>(
+    timestamp: DateTime<Utc>,
+    command: &str,
+    args: &[String],
+    elapsed: Duration,
+    result: &Result<std::process::Output, E>,
+) -> String {
+    let outcome = match result {
+        Ok(output) => match output.status.code() {
+            Some(code) => format!("exit {}", code),
+            None => String::from("killed by a signal"),
+        },
+        Err(err) => format!("failed : {}", err),
+    };
+
+    format!(
+        "{} {} {} {}ms {}",
+        timestamp.to_rfc3339_opts(SecondsFormat::Secs, true),
+        command,
+        args.join(" "),
+        elapsed.as_millis(),
+        outcome
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::process::ExitStatusExt, process};
+
+    use chrono::TimeZone;
+
+    use super::super::{BluetoothError, MockCommandRunner};
+    use super::*;
+
+    #[test]
+    fn audit_line_records_the_command_and_outcome() {
+        let timestamp = Utc.with_ymd_and_hms(2022, 8, 1, 12, 0, 0).unwrap();
+        let args = vec![String::from("--connect"), String::from("address")];
+
+        assert_eq!(
+            audit_line(
+                timestamp,
+                "blueutil",
+                &args,
+                Duration::from_millis(1520),
+                &Ok::<_, BluetoothError>(std::process::Output {
+                    status: ExitStatusExt::from_raw(1 << 8),
+                    stdout: vec![],
+                    stderr: vec![],
+                }),
+            ),
+            "2022-08-01T12:00:00Z blueutil --connect address 1520ms exit 1"
+        );
+        assert_eq!(
+            audit_line(
+                timestamp,
+                "blueutil",
+                &args,
+                Duration::from_secs(20),
+                &Err::<std::process::Output, _>(BluetoothError::new("timed out")),
+            ),
+            "2022-08-01T12:00:00Z blueutil --connect address 20000ms failed : timed out"
+        );
+    }
+
+    #[test]
+    fn audit_command_runner_appends_every_command() {
+        let path = env::temp_dir().join(format!("audit-{}.log", process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut mock = MockCommandRunner::default();
+        mock.expect_run_command().times(2).returning(|_, _| {
+            Ok(std::process::Output {
+                status: ExitStatusExt::from_raw(0),
+                stdout: vec![],
+                stderr: vec![],
+            })
+        });
+        let runner = AuditCommandRunner {
+            command_runner: Box::new(mock),
+            path: path.clone(),
+        };
+
+        runner
+            .run_command("blueutil", vec![String::from("--paired")])
+            .unwrap();
+        runner
+            .run_command("blueutil", vec![String::from("--power")])
+            .unwrap();
+
+        let log = fs::read_to_string(&path).unwrap();
+        let lines = log.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains(" blueutil --paired ") && lines[0].ends_with(" exit 0"));
+        assert!(lines[1].contains(" blueutil --power "));
+
+        fs::remove_file(path).unwrap();
+    }
+}
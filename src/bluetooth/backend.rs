@@ -0,0 +1,250 @@
+use chrono::Utc;
+use clap::ArgEnum;
+use serde::{Deserialize, Serialize};
+
+use super::audit::AuditCommandRunner;
+#[cfg(all(target_os = "linux", feature = "bluez"))]
+use super::bluez::BluezClient;
+use super::profiler::ProfilerClient;
+#[cfg(all(windows, feature = "winrt"))]
+use super::winrt::WinRtClient;
+#[cfg(target_os = "macos")]
+use super::IoBluetoothClient;
+use super::{
+    BluetoothClientOptions, BluetoothError, BlueutilClient, Client, CommandRunner,
+    DefaultCommandRunner, DryRunCommandRunner, ErrorKind, FakeClient, DEFAULT_COMMAND_TIMEOUT,
+};
+
+// What the client talks to Bluetooth through.
+#[derive(Debug, Clone, Copy, Default, PartialEq, ArgEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Blueutil,
+    // Calls IOBluetooth directly, so blueutil doesn't need to be installed
+    #[cfg(target_os = "macos")]
+    #[clap(name = "iobluetooth")]
+    IoBluetooth,
+    // Talks to BlueZ through bluetoothctl, for Linux desktops
+    #[cfg(all(target_os = "linux", feature = "bluez"))]
+    Bluez,
+    // Uses the WinRT Bluetooth APIs, for PowerToys Run or Flow Launcher
+    #[cfg(all(windows, feature = "winrt"))]
+    Windows,
+    // Reads `system_profiler SPBluetoothDataType`, which can only list devices
+    Profiler,
+    // Canned in-memory devices, for demos and developing the workflow
+    Fake,
+}
+
+impl Backend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Backend::Blueutil => "blueutil",
+            #[cfg(target_os = "macos")]
+            Backend::IoBluetooth => "iobluetooth",
+            #[cfg(all(target_os = "linux", feature = "bluez"))]
+            Backend::Bluez => "bluez",
+            #[cfg(all(windows, feature = "winrt"))]
+            Backend::Windows => "windows",
+            Backend::Profiler => "profiler",
+            Backend::Fake => "fake",
+        }
+    }
+}
+
+// Builds the client for the backend in the options.
+pub(super) fn create_client(options: BluetoothClientOptions) -> Box<dyn Client> {
+    match options.backend {
+        Backend::Blueutil => {
+            let mut command_runner = command_runner(&options);
+            if options.dry_run {
+                command_runner = Box::new(DryRunCommandRunner { command_runner });
+            }
+
+            Box::new(BlueutilClient::new(command_runner, options.blueutil_path))
+        }
+        #[cfg(target_os = "macos")]
+        Backend::IoBluetooth => Box::new(IoBluetoothClient::new(options.dry_run)),
+        #[cfg(all(target_os = "linux", feature = "bluez"))]
+        Backend::Bluez => Box::new(BluezClient::new(command_runner(&options), options.dry_run)),
+        #[cfg(all(windows, feature = "winrt"))]
+        Backend::Windows => Box::new(WinRtClient::new(options.dry_run)),
+        // Read only, so there's nothing for a dry run to skip
+        Backend::Profiler => Box::new(ProfilerClient::new(command_runner(&options))),
+        Backend::Fake => Box::new(FakeClient::new(Utc::now())),
+    }
+}
+
+// Runs the backend's commands, logging them when there's an audit log.
+fn command_runner(options: &BluetoothClientOptions) -> Box<dyn CommandRunner> {
+    let command_runner: Box<dyn CommandRunner> = Box::new(DefaultCommandRunner {
+        timeout: options.command_timeout.unwrap_or(DEFAULT_COMMAND_TIMEOUT),
+    });
+
+    match &options.audit_log_path {
+        Some(path) => Box::new(AuditCommandRunner {
+            command_runner,
+            path: path.clone(),
+        }),
+        None => command_runner,
+    }
+}
+
+// The async counterpart of the backend's client, None for backends that only
+// have a sync client.
+#[cfg(feature = "async")]
+pub(super) fn create_async_client(
+    options: &BluetoothClientOptions,
+) -> Option<std::sync::Arc<dyn super::AsyncClient>> {
+    match options.backend {
+        Backend::Blueutil => {
+            let mut client = super::AsyncBlueutilClient::new(
+                options.blueutil_path.as_deref(),
+                options.command_timeout.unwrap_or(DEFAULT_COMMAND_TIMEOUT),
+                options.dry_run,
+            );
+            if let Some(path) = &options.audit_log_path {
+                client = client.with_audit_log(path.clone());
+            }
+
+            Some(std::sync::Arc::new(client))
+        }
+        _ => None,
+    }
+}
+
+// Operations not every backend supports. Listing devices and reading their
+// state is supported by all of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capability {
+    Connect,
+    Pair,
+    Favourite,
+    SetPower,
+    RecentDevices,
+    Discover,
+}
+
+impl Capability {
+    pub const ALL: &'static [Capability] = &[
+        Capability::Connect,
+        Capability::Pair,
+        Capability::Favourite,
+        Capability::SetPower,
+        Capability::RecentDevices,
+        Capability::Discover,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Capability::Connect => "connect",
+            Capability::Pair => "pair",
+            Capability::Favourite => "favourite",
+            Capability::SetPower => "set-power",
+            Capability::RecentDevices => "recent-devices",
+            Capability::Discover => "discover",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Capability::Connect => "Connecting and disconnecting devices",
+            Capability::Pair => "Pairing and unpairing devices",
+            Capability::Favourite => "Changing favourites",
+            Capability::SetPower => "Turning Bluetooth on or off",
+            Capability::RecentDevices => "Listing recently used devices",
+            Capability::Discover => "Discovering nearby devices",
+        }
+    }
+
+    pub(super) fn unsupported(&self, backend: Backend) -> BluetoothError {
+        BluetoothError::with_kind(
+            ErrorKind::Unsupported,
+            &format!(
+                "{} isn't supported by the {} backend",
+                self.description(),
+                backend.as_str()
+            ),
+        )
+    }
+}
+
+// What a backend can do, so unsupported operations fail up front with a clear
+// error instead of however the backend happens to fail.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Capabilities {
+    pub backend: Backend,
+    pub connect: bool,
+    pub pair: bool,
+    pub favourite: bool,
+    pub set_power: bool,
+    pub recent_devices: bool,
+    pub discover: bool,
+}
+
+impl Capabilities {
+    pub fn all(backend: Backend) -> Self {
+        Capabilities {
+            backend,
+            connect: true,
+            pair: true,
+            favourite: true,
+            set_power: true,
+            recent_devices: true,
+            discover: true,
+        }
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Connect => self.connect,
+            Capability::Pair => self.pair,
+            Capability::Favourite => self.favourite,
+            Capability::SetPower => self.set_power,
+            Capability::RecentDevices => self.recent_devices,
+            Capability::Discover => self.discover,
+        }
+    }
+
+    pub fn require(&self, capability: Capability) -> Result<(), BluetoothError> {
+        if self.supports(capability) {
+            Ok(())
+        } else {
+            Err(capability.unsupported(self.backend))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capabilities_require_reports_the_backend() {
+        let capabilities = Capabilities {
+            connect: false,
+            ..Capabilities::all(Backend::Profiler)
+        };
+
+        assert!(capabilities.require(Capability::Pair).is_ok());
+
+        let err = capabilities.require(Capability::Connect).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+        assert_eq!(
+            err.to_string(),
+            "Connecting and disconnecting devices isn't supported by the profiler backend"
+        );
+    }
+
+    #[test]
+    fn backend_reads_from_config_names() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            backend: Backend,
+        }
+
+        let wrapper: Wrapper = toml::from_str("backend = \"profiler\"").unwrap();
+        assert_eq!(wrapper.backend, Backend::Profiler);
+    }
+}
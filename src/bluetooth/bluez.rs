@@ -0,0 +1,328 @@
+use std::{collections::HashSet, str};
+
+use log::{trace, warn};
+
+use super::{
+    check_status, decode_output, Address, Backend, BluetoothError, Capabilities, Capability,
+    Client, CommandRunner, DeviceClass, DeviceInfo, ErrorKind,
+};
+
+const BLUETOOTHCTL: &str = "bluetoothctl";
+
+// Talks to BlueZ through `bluetoothctl`, so the CLI works on Linux desktops,
+// e.g. from rofi or wofi with `--format plain`. Trusted devices stand in for
+// favourites. With dry run set, changes are printed instead of made.
+pub struct BluezClient {
+    command_runner: Box<dyn CommandRunner>,
+    dry_run: bool,
+}
+
+impl BluezClient {
+    pub(super) fn new(command_runner: Box<dyn CommandRunner>, dry_run: bool) -> Self {
+        BluezClient {
+            command_runner,
+            dry_run,
+        }
+    }
+
+    fn run_command(&self, args: Vec<&str>) -> Result<std::process::Output, BluetoothError> {
+        self.command_runner
+            .run_command(BLUETOOTHCTL, args.into_iter().map(String::from).collect())
+            .map_err(BluetoothError::from)
+    }
+
+    // Runs a command that changes something, failing with the message when
+    // bluetoothctl doesn't exit cleanly.
+    fn run_change(&self, args: Vec<&str>, error: &str) -> Result<(), BluetoothError> {
+        if self.dry_run {
+            eprintln!("Dry run : {} {}", BLUETOOTHCTL, args.join(" "));
+            return Ok(());
+        }
+
+        let output = self.run_command(args)?;
+
+        trace!("{:?}", &output.stdout);
+        trace!("{:?}", &output.stderr);
+
+        check_status(&output, ErrorKind::Other, error)
+    }
+
+    fn list_devices(&self, kind: &str) -> Result<Vec<(String, String)>, BluetoothError> {
+        let output = self.run_command(vec!["devices", kind])?;
+
+        check_status(
+            &output,
+            ErrorKind::Other,
+            &format!("Failed to list {} devices", kind.to_lowercase()),
+        )?;
+
+        Ok(parse_devices(&decode_output(&output.stdout)))
+    }
+}
+
+// bluetoothctl only accepts uppercase, colon separated addresses.
+fn bluez_address(address: &str) -> String {
+    Address::new(address)
+        .as_str()
+        .replace('-', ":")
+        .to_uppercase()
+}
+
+// Parses `bluetoothctl devices` lines, e.g. `Device 80:3B:5C:C2:B1:7F AirPods Pro`.
+fn parse_devices(output: &str) -> Vec<(String, String)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.trim().splitn(3, ' ');
+            match (parts.next(), parts.next()) {
+                (Some("Device"), Some(address)) => Some((
+                    address.to_string(),
+                    parts.next().unwrap_or_default().to_string(),
+                )),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// Parses `bluetoothctl info`, which is a `Key: value` line per property.
+fn parse_info(address: &str, output: &str) -> DeviceInfo {
+    let mut device = DeviceInfo {
+        address: address.to_string(),
+        ..Default::default()
+    };
+
+    for line in output.lines() {
+        let (key, value) = match line.trim().split_once(": ") {
+            Some(property) => property,
+            None => continue,
+        };
+
+        match key {
+            "Name" => device.name = value.to_string(),
+            "Connected" => device.connected = value == "yes",
+            "Paired" => device.paired = value == "yes",
+            "Trusted" => device.favourite = value == "yes",
+            // e.g. `0x50 (80)`
+            "Battery Percentage" => device.battery = parenthesized(value).parse().ok(),
+            // `-60` or `0xffffffc4 (-60)` depending on the version
+            "RSSI" => device.rssi = parenthesized(value).parse().ok(),
+            // e.g. `0x00240418`
+            "Class" => {
+                device.device_class = u32::from_str_radix(value.trim_start_matches("0x"), 16)
+                    .ok()
+                    .map(DeviceClass::from_class_of_device)
+            }
+            _ => {}
+        }
+    }
+
+    device
+}
+
+// The value in parentheses, or the whole value when there are none.
+fn parenthesized(value: &str) -> &str {
+    value
+        .split_once('(')
+        .map_or(value, |(_, x)| x.trim_end_matches(')'))
+}
+
+impl Client for BluezClient {
+    fn connect_to_device(&self, address: &str) -> Result<(), BluetoothError> {
+        self.run_change(
+            vec!["connect", &bluez_address(address)],
+            &format!("Failed to connect to '{}'", address),
+        )
+    }
+
+    fn disconnect_from_device(&self, address: &str) -> Result<(), BluetoothError> {
+        self.run_change(
+            vec!["disconnect", &bluez_address(address)],
+            &format!("Failed to disconnect from '{}'", address),
+        )
+    }
+
+    fn get_device_list(&self) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        let connected = self
+            .list_devices("Connected")?
+            .into_iter()
+            .map(|(address, _)| address)
+            .collect::<HashSet<String>>();
+        let trusted = self
+            .list_devices("Trusted")?
+            .into_iter()
+            .map(|(address, _)| address)
+            .collect::<HashSet<String>>();
+
+        Ok(self
+            .list_devices("Paired")?
+            .into_iter()
+            .map(|(address, name)| DeviceInfo {
+                name,
+                connected: connected.contains(&address),
+                favourite: trusted.contains(&address),
+                paired: true,
+                address,
+                ..Default::default()
+            })
+            .collect())
+    }
+
+    fn get_recent_devices(&self) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        Err(Capability::RecentDevices.unsupported(Backend::Bluez))
+    }
+
+    fn get_device(&self, address: &str) -> Option<DeviceInfo> {
+        let address = bluez_address(address);
+        let output = self.run_command(vec!["info", &address]).ok()?;
+
+        if !output.status.success() {
+            trace!("{:?}", &output.stderr);
+            return None;
+        }
+
+        Some(parse_info(&address, &decode_output(&output.stdout)))
+    }
+
+    fn pair_device(&self, address: &str, pin: Option<String>) -> Result<(), BluetoothError> {
+        // PINs are entered through an agent, which a single command can't register
+        if pin.is_some() {
+            warn!("bluetoothctl can't be given a PIN, it's ignored");
+        }
+
+        self.run_change(
+            vec!["pair", &bluez_address(address)],
+            &format!("Failed to pair with device '{}'", address),
+        )
+    }
+
+    fn unpair_device(&self, address: &str) -> Result<(), BluetoothError> {
+        self.run_change(
+            vec!["remove", &bluez_address(address)],
+            &format!("Failed to unpair device '{}'", address),
+        )
+    }
+
+    fn set_favourite(&self, address: &str, favourite: bool) -> Result<(), BluetoothError> {
+        self.run_change(
+            vec![
+                if favourite { "trust" } else { "untrust" },
+                &bluez_address(address),
+            ],
+            &format!("Failed to update favourites for device '{}'", address),
+        )
+    }
+
+    fn is_powered(&self) -> Result<bool, BluetoothError> {
+        let output = self.run_command(vec!["show"])?;
+
+        check_status(
+            &output,
+            ErrorKind::Other,
+            "Failed to get the Bluetooth power state",
+        )?;
+
+        Ok(decode_output(&output.stdout)
+            .lines()
+            .any(|x| x.trim() == "Powered: yes"))
+    }
+
+    fn set_power(&self, on: bool) -> Result<(), BluetoothError> {
+        self.run_change(
+            vec!["power", if on { "on" } else { "off" }],
+            "Failed to set the Bluetooth power state",
+        )
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            recent_devices: false,
+            // `bluetoothctl scan` only stops when it's interrupted
+            discover: false,
+            ..Capabilities::all(Backend::Bluez)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+
+    use super::super::MockCommandRunner;
+    use super::*;
+
+    fn output(stdout: &str) -> std::process::Output {
+        std::process::Output {
+            status: ExitStatusExt::from_raw(0),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: vec![],
+        }
+    }
+
+    #[test]
+    fn bluez_client_lists_paired_devices_with_their_state() {
+        let mut mock = MockCommandRunner::new();
+        mock.expect_run_command()
+            .returning(|_, args| match args[1].as_str() {
+                "Paired" => Ok(output(
+                    "Device 80:3B:5C:C2:B1:7F AirPods Pro\nDevice F0:B3:EC:12:4A:9D Magic Keyboard\n",
+                )),
+                "Connected" => Ok(output("Device 80:3B:5C:C2:B1:7F AirPods Pro\n")),
+                _ => Ok(output("Device F0:B3:EC:12:4A:9D Magic Keyboard\n")),
+            });
+
+        let devices = BluezClient::new(Box::new(mock), false)
+            .get_device_list()
+            .unwrap();
+
+        assert_eq!(
+            devices,
+            vec![
+                DeviceInfo {
+                    name: String::from("AirPods Pro"),
+                    address: String::from("80:3B:5C:C2:B1:7F"),
+                    connected: true,
+                    paired: true,
+                    ..Default::default()
+                },
+                DeviceInfo {
+                    name: String::from("Magic Keyboard"),
+                    address: String::from("F0:B3:EC:12:4A:9D"),
+                    favourite: true,
+                    paired: true,
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn bluez_client_gets_device_info_by_any_address_form() {
+        let mut mock = MockCommandRunner::new();
+        mock.expect_run_command()
+            .withf(|command, args| command == "bluetoothctl" && *args == ["info", "80:3B:5C:C2:B1:7F"])
+            .returning(|_, _| {
+                Ok(output(
+                    "Device 80:3B:5C:C2:B1:7F (public)\n\tName: AirPods Pro\n\tPaired: yes\n\tTrusted: no\n\tConnected: yes\n\tClass: 0x00240418\n\tBattery Percentage: 0x50 (80)\n\tRSSI: 0xffffffc4 (-60)\n",
+                ))
+            });
+
+        let device = BluezClient::new(Box::new(mock), false)
+            .get_device("80-3b-5c-c2-b1-7f")
+            .unwrap();
+
+        assert_eq!(
+            device,
+            DeviceInfo {
+                name: String::from("AirPods Pro"),
+                address: String::from("80:3B:5C:C2:B1:7F"),
+                connected: true,
+                battery: Some(80),
+                rssi: Some(-60),
+                device_class: Some(DeviceClass::Audio),
+                paired: true,
+                ..Default::default()
+            }
+        );
+    }
+}
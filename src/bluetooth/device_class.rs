@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+
+// Broad kinds of device, from the Bluetooth class of device.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DeviceClass {
+    // Headphones and headsets
+    Audio,
+    Computer,
+    Phone,
+    // Keyboards, mice and trackpads
+    Peripheral,
+    Other,
+}
+
+// Major classes from the Bluetooth assigned numbers
+const MAJOR_COMPUTER: u32 = 0x01;
+const MAJOR_PHONE: u32 = 0x02;
+const MAJOR_AUDIO_VIDEO: u32 = 0x04;
+const MAJOR_PERIPHERAL: u32 = 0x05;
+
+// Audio/video minor classes that are worn
+const MINOR_HEADSET: u32 = 0x01;
+const MINOR_HANDS_FREE: u32 = 0x02;
+const MINOR_HEADPHONES: u32 = 0x06;
+
+impl DeviceClass {
+    // From the 24 bit class of device, e.g. 0x240418 for headphones.
+    pub fn from_class_of_device(class_of_device: u32) -> Self {
+        let major = (class_of_device >> 8) & 0x1f;
+        let minor = (class_of_device >> 2) & 0x3f;
+
+        match major {
+            MAJOR_AUDIO_VIDEO
+                if [MINOR_HEADSET, MINOR_HANDS_FREE, MINOR_HEADPHONES].contains(&minor) =>
+            {
+                DeviceClass::Audio
+            }
+            MAJOR_COMPUTER => DeviceClass::Computer,
+            MAJOR_PHONE => DeviceClass::Phone,
+            MAJOR_PERIPHERAL => DeviceClass::Peripheral,
+            _ => DeviceClass::Other,
+        }
+    }
+
+    // From system_profiler's device_minorType, e.g. "Headphones".
+    pub fn from_minor_type(minor_type: &str) -> Self {
+        match minor_type.to_lowercase().as_str() {
+            "headphones" | "headset" => DeviceClass::Audio,
+            "computer" | "desktop" | "laptop" => DeviceClass::Computer,
+            "phone" | "smartphone" | "cellular" => DeviceClass::Phone,
+            "keyboard" | "mouse" | "trackpad" => DeviceClass::Peripheral,
+            _ => DeviceClass::Other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_class_from_class_of_device() {
+        // AirPods
+        assert_eq!(
+            DeviceClass::from_class_of_device(0x240418),
+            DeviceClass::Audio
+        );
+        // A hands-free headset
+        assert_eq!(
+            DeviceClass::from_class_of_device(0x200408),
+            DeviceClass::Audio
+        );
+        // A loudspeaker is audio/video but isn't worn
+        assert_eq!(
+            DeviceClass::from_class_of_device(0x240414),
+            DeviceClass::Other
+        );
+        // Magic Keyboard
+        assert_eq!(
+            DeviceClass::from_class_of_device(0x000540),
+            DeviceClass::Peripheral
+        );
+        assert_eq!(
+            DeviceClass::from_class_of_device(0x7a020c),
+            DeviceClass::Phone
+        );
+    }
+
+    #[test]
+    fn device_class_from_minor_type() {
+        assert_eq!(
+            DeviceClass::from_minor_type("Headphones"),
+            DeviceClass::Audio
+        );
+        assert_eq!(
+            DeviceClass::from_minor_type("Keyboard"),
+            DeviceClass::Peripheral
+        );
+        assert_eq!(DeviceClass::from_minor_type("Speaker"), DeviceClass::Other);
+    }
+}
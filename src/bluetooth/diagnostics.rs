@@ -0,0 +1,106 @@
+use log::warn;
+use std::fmt;
+use thiserror::Error;
+
+// A line of blueutil's text output and the part of it that couldn't be read.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("Couldn't read the {field} from '{line}'")]
+pub struct ParseError {
+    pub line: String,
+    pub field: &'static str,
+}
+
+impl ParseError {
+    pub fn new(line: &str, field: &'static str) -> Self {
+        ParseError {
+            line: line.to_string(),
+            field,
+        }
+    }
+}
+
+// An entry in blueutil's output that couldn't be parsed into a device, with
+// what was wrong with it.
+#[derive(Debug, PartialEq)]
+pub struct ParseFailure {
+    pub index: usize,
+    pub entry: String,
+    pub reason: String,
+}
+
+impl fmt::Display for ParseFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "entry {} ({}) : {}", self.index, self.reason, self.entry)
+    }
+}
+
+// Collects the entries that failed to parse while reading blueutil output, so
+// parser issues can be traced back to the exact output that caused them.
+#[derive(Debug, Default, PartialEq)]
+pub struct ParseDiagnostics {
+    pub failures: Vec<ParseFailure>,
+}
+
+impl ParseDiagnostics {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn record_failure(&mut self, index: usize, entry: &str, reason: &str) {
+        self.failures.push(ParseFailure {
+            index,
+            entry: entry.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+
+    pub fn record_error(&mut self, index: usize, err: &ParseError) {
+        self.record_failure(
+            index,
+            &err.line,
+            &format!("couldn't read the {}", err.field),
+        );
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    // Failures are reported at warn level so they show up with -v, which is
+    // what bug reports about unrecognized output need.
+    pub fn log(&self) {
+        for failure in &self.failures {
+            warn!("Failed to parse blueutil output {}", failure);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_diagnostics_records_failures_in_order() {
+        let mut diagnostics = ParseDiagnostics::new();
+        assert!(diagnostics.is_empty());
+
+        diagnostics.record_failure(2, "{}", "missing field `address`");
+        diagnostics.record_error(5, &ParseError::new("address: -", "name"));
+
+        assert_eq!(
+            diagnostics.failures,
+            vec![
+                ParseFailure {
+                    index: 2,
+                    entry: String::from("{}"),
+                    reason: String::from("missing field `address`"),
+                },
+                ParseFailure {
+                    index: 5,
+                    entry: String::from("address: -"),
+                    reason: String::from("couldn't read the name"),
+                },
+            ]
+        );
+    }
+}
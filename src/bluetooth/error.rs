@@ -0,0 +1,155 @@
+use std::{error::Error, io, str};
+
+use thiserror::Error;
+
+// What went wrong, so callers like main can react without matching on messages.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Other,
+    DeviceNotFound,
+    BlueutilMissing,
+    ConnectFailed,
+    DisconnectFailed,
+    Timeout,
+    // The backend can't perform the operation
+    Unsupported,
+    // A device id that should have been a MAC address isn't one
+    InvalidAddress,
+}
+
+// Everything that can go wrong talking to a backend, so callers can match on
+// what happened and get at the details, like a command's stderr.
+#[derive(Debug, Error)]
+pub enum BluetoothError {
+    #[error("{0}")]
+    DeviceNotFound(String),
+    // blueutil is missing or too old, or there's no Bluetooth radio
+    #[error("{0}")]
+    BackendUnavailable(String),
+    // A command exited with an error, the kind says what it was doing
+    #[error("{}", with_stderr(.message, .stderr))]
+    CommandFailed {
+        kind: ErrorKind,
+        message: String,
+        // None when the command was killed by a signal
+        status: Option<i32>,
+        stderr: String,
+    },
+    // A backend's output couldn't be read
+    #[error("{0}")]
+    ParseError(String),
+    #[error("{0}")]
+    Timeout(String),
+    // The backend can't perform the operation
+    #[error("{0}")]
+    Unsupported(String),
+    #[error("{0}")]
+    InvalidAddress(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+fn with_stderr(message: &str, stderr: &str) -> String {
+    match stderr.trim() {
+        "" => message.to_string(),
+        stderr => format!("{} : {}", message, stderr),
+    }
+}
+
+impl BluetoothError {
+    pub(super) fn new(msg: &str) -> BluetoothError {
+        BluetoothError::Other(msg.to_string())
+    }
+
+    pub fn with_kind(kind: ErrorKind, msg: &str) -> BluetoothError {
+        let msg = msg.to_string();
+
+        match kind {
+            ErrorKind::Other => BluetoothError::Other(msg),
+            ErrorKind::DeviceNotFound => BluetoothError::DeviceNotFound(msg),
+            ErrorKind::BlueutilMissing => BluetoothError::BackendUnavailable(msg),
+            ErrorKind::ConnectFailed | ErrorKind::DisconnectFailed => {
+                BluetoothError::CommandFailed {
+                    kind,
+                    message: msg,
+                    status: None,
+                    stderr: String::new(),
+                }
+            }
+            ErrorKind::Timeout => BluetoothError::Timeout(msg),
+            ErrorKind::Unsupported => BluetoothError::Unsupported(msg),
+            ErrorKind::InvalidAddress => BluetoothError::InvalidAddress(msg),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            BluetoothError::DeviceNotFound(_) => ErrorKind::DeviceNotFound,
+            BluetoothError::BackendUnavailable(_) => ErrorKind::BlueutilMissing,
+            BluetoothError::CommandFailed { kind, .. } => *kind,
+            BluetoothError::Timeout(_) => ErrorKind::Timeout,
+            BluetoothError::Unsupported(_) => ErrorKind::Unsupported,
+            BluetoothError::InvalidAddress(_) => ErrorKind::InvalidAddress,
+            BluetoothError::ParseError(_) | BluetoothError::Other(_) => ErrorKind::Other,
+        }
+    }
+}
+
+// Keeps errors that were already BluetoothErrors as they were.
+impl From<Box<dyn Error>> for BluetoothError {
+    fn from(err: Box<dyn Error>) -> Self {
+        match err.downcast::<BluetoothError>() {
+            Ok(err) => *err,
+            Err(err) => BluetoothError::new(&err.to_string()),
+        }
+    }
+}
+
+impl From<io::Error> for BluetoothError {
+    fn from(err: io::Error) -> Self {
+        BluetoothError::new(&err.to_string())
+    }
+}
+
+impl From<str::Utf8Error> for BluetoothError {
+    fn from(err: str::Utf8Error) -> Self {
+        BluetoothError::ParseError(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for BluetoothError {
+    fn from(err: serde_json::Error) -> Self {
+        BluetoothError::ParseError(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bluetooth_error_command_failed_shows_stderr() {
+        let err = BluetoothError::CommandFailed {
+            kind: ErrorKind::ConnectFailed,
+            message: String::from("Failed to connect to 'address'"),
+            status: Some(1),
+            stderr: String::from("Error: timed out\n"),
+        };
+
+        assert_eq!(err.kind(), ErrorKind::ConnectFailed);
+        assert_eq!(
+            err.to_string(),
+            "Failed to connect to 'address' : Error: timed out"
+        );
+    }
+
+    #[test]
+    fn bluetooth_error_keeps_its_variant_through_a_box() {
+        let err: Box<dyn Error> = Box::new(BluetoothError::Timeout(String::from("timed out")));
+
+        assert!(matches!(
+            BluetoothError::from(err),
+            BluetoothError::Timeout(_)
+        ));
+    }
+}
@@ -0,0 +1,166 @@
+use std::{collections::HashMap, ops::ControlFlow, thread, time::Duration};
+
+use chrono::{DateTime, Utc};
+use log::warn;
+
+use super::{Address, BluetoothClient, ConnectionState, DeviceInfo, DeviceListOptions};
+use crate::clock::Clock;
+
+// A device connecting or disconnecting.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceEvent {
+    pub name: String,
+    pub address: String,
+    pub state: ConnectionState,
+    pub timestamp: DateTime<Utc>,
+}
+
+// Tracks the last seen connection state of each device so polls can be turned
+// into connect/disconnect events.
+#[derive(Debug, Default)]
+pub struct ConnectionWatcher {
+    states: HashMap<Address, ConnectionState>,
+}
+
+impl ConnectionWatcher {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Records the devices' current states, returning an event for every device
+    // whose state changed since the last update. Devices seen for the first time
+    // are treated as previously disconnected.
+    pub fn update(&mut self, devices: &[DeviceInfo], now: DateTime<Utc>) -> Vec<DeviceEvent> {
+        devices
+            .iter()
+            .filter_map(|device| {
+                let state = ConnectionState::from_connected(device.connected);
+                let previous = self
+                    .states
+                    .insert(Address::new(&device.address), state)
+                    .unwrap_or(ConnectionState::Disconnected);
+
+                if previous == state {
+                    return None;
+                }
+
+                Some(DeviceEvent {
+                    name: device.name.clone(),
+                    address: device.address.clone(),
+                    state,
+                    timestamp: now,
+                })
+            })
+            .collect()
+    }
+}
+
+impl BluetoothClient {
+    // Polls the device list every poll interval, calling `on_event` for every
+    // connection change until it returns ControlFlow::Break. Devices that are
+    // already connected are reported on the first poll.
+    pub fn subscribe<F>(&self, clock: &dyn Clock, poll_interval: Duration, mut on_event: F)
+    where
+        F: FnMut(&DeviceEvent) -> ControlFlow<()>,
+    {
+        let mut watcher = ConnectionWatcher::new();
+
+        loop {
+            match self.get_device_list(DeviceListOptions::new_default_all_devices()) {
+                Ok(devices) => {
+                    for event in watcher.update(&devices, clock.now()) {
+                        if on_event(&event).is_break() {
+                            return;
+                        }
+                    }
+                }
+                Err(err) => warn!("Failed to list devices : {}", err),
+            }
+
+            thread::sleep(poll_interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::super::FakeClient;
+    use super::*;
+    use crate::clock::FixedClock;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2022, 8, 1, 12, 0, 0).unwrap()
+    }
+
+    fn device(name: &str, connected: bool) -> DeviceInfo {
+        DeviceInfo {
+            name: String::from(name),
+            address: format!("{}-address", name),
+            connected,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn watcher_reports_initially_connected_devices() {
+        let mut watcher = ConnectionWatcher::new();
+
+        let events = watcher.update(&[device("a", true), device("b", false)], now());
+
+        assert_eq!(
+            events,
+            vec![DeviceEvent {
+                name: String::from("a"),
+                address: String::from("a-address"),
+                state: ConnectionState::Connected,
+                timestamp: now(),
+            }]
+        );
+    }
+
+    #[test]
+    fn watcher_reports_only_changes() {
+        let mut watcher = ConnectionWatcher::new();
+        watcher.update(&[device("a", true), device("b", false)], now());
+
+        assert!(watcher
+            .update(&[device("a", true), device("b", false)], now())
+            .is_empty());
+
+        let events = watcher.update(&[device("a", false), device("b", true)], now());
+        let states = events
+            .iter()
+            .map(|x| (x.name.as_str(), x.state))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            states,
+            vec![
+                ("a", ConnectionState::Disconnected),
+                ("b", ConnectionState::Connected)
+            ]
+        );
+    }
+
+    #[test]
+    fn bluetooth_client_subscribe_stops_when_the_callback_breaks() {
+        let client = BluetoothClient::with_client(Box::new(FakeClient::with_devices(vec![
+            device("a", true),
+            device("b", true),
+            device("c", false),
+        ])));
+
+        let mut events = vec![];
+        client.subscribe(&FixedClock(now()), Duration::ZERO, |event| {
+            events.push(event.name.clone());
+            if events.len() == 2 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+
+        assert_eq!(events, vec!["a", "b"]);
+    }
+}
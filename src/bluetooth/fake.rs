@@ -0,0 +1,347 @@
+use std::{
+    cmp::Reverse,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard,
+    },
+    thread, time,
+};
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::battery::BatteryInfo;
+
+use super::{
+    Address, Backend, BluetoothError, Capabilities, Client, DeviceClass, DeviceInfo, ErrorKind,
+};
+
+// Operations that failures can be scripted for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FakeOperation {
+    Connect,
+    Disconnect,
+    List,
+}
+
+// What a scripted operation does instead of succeeding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FakeFailure {
+    Error(ErrorKind),
+    // Reports success without changing anything, like connecting AirPods that
+    // are in their case. Lists come back empty.
+    Ignored,
+}
+
+// An in-memory client with a canned set of devices, so the Alfred output can be
+// developed and screenshotted without touching real Bluetooth hardware. Changes
+// only last for the lifetime of the process. With the testing feature, latency
+// and failures can be scripted too.
+pub struct FakeClient {
+    devices: Mutex<Vec<DeviceInfo>>,
+    // Unpaired devices that discover finds, pairing one moves it to devices
+    nearby: Mutex<Vec<DeviceInfo>>,
+    powered: AtomicBool,
+    latency: time::Duration,
+    // Used up in order by the first operation of each kind
+    failures: Mutex<Vec<(FakeOperation, FakeFailure)>>,
+}
+
+impl FakeClient {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        FakeClient {
+            nearby: Mutex::new(canned_nearby_devices()),
+            ..Self::with_devices(canned_devices(now))
+        }
+    }
+
+    pub fn with_devices(devices: Vec<DeviceInfo>) -> Self {
+        FakeClient {
+            devices: Mutex::new(devices),
+            nearby: Mutex::new(vec![]),
+            powered: AtomicBool::new(true),
+            latency: time::Duration::ZERO,
+            failures: Mutex::new(vec![]),
+        }
+    }
+
+    // Waits out the latency, returning the failure scripted for the operation.
+    fn begin(&self, operation: FakeOperation) -> Option<FakeFailure> {
+        thread::sleep(self.latency);
+
+        let mut failures = lock(&self.failures);
+        let index = failures.iter().position(|(x, _)| *x == operation)?;
+
+        Some(failures.remove(index).1)
+    }
+
+    fn change_device<F>(
+        &self,
+        operation: FakeOperation,
+        address: &str,
+        update: F,
+    ) -> Result<(), BluetoothError>
+    where
+        F: FnOnce(&mut DeviceInfo),
+    {
+        match self.begin(operation) {
+            Some(FakeFailure::Error(kind)) => Err(scripted_error(kind)),
+            Some(FakeFailure::Ignored) => Ok(()),
+            None => self.update_device(address, update),
+        }
+    }
+
+    fn update_device<F>(&self, address: &str, update: F) -> Result<(), BluetoothError>
+    where
+        F: FnOnce(&mut DeviceInfo),
+    {
+        let address = Address::new(address);
+        let mut devices = lock(&self.devices);
+
+        match devices
+            .iter_mut()
+            .find(|x| Address::new(&x.address) == address)
+        {
+            Some(device) => {
+                update(device);
+                Ok(())
+            }
+            None => Err(BluetoothError::with_kind(
+                ErrorKind::DeviceNotFound,
+                &format!("Device '{}' not found", address),
+            )),
+        }
+    }
+}
+
+// A panic while a lock was held can't leave the devices half changed, so a
+// poisoned lock is still used.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn scripted_error(kind: ErrorKind) -> BluetoothError {
+    BluetoothError::with_kind(kind, "Scripted failure")
+}
+
+// Scripting for tests, so full flows can run against the fake backend.
+#[cfg(feature = "testing")]
+impl FakeClient {
+    // Every operation sleeps for the latency first, like a slow radio.
+    pub fn with_latency(mut self, latency: time::Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    // The next operation of the kind fails instead of succeeding.
+    pub fn fail_next(&self, operation: FakeOperation, failure: FakeFailure) {
+        lock(&self.failures).push((operation, failure));
+    }
+
+    // Changes a device's state as if it happened outside of the client, e.g.
+    // AirPods connecting to another device.
+    pub fn set_connected(&self, address: &str, connected: bool) -> Result<(), BluetoothError> {
+        self.update_device(address, |x| x.connected = connected)
+    }
+
+    pub fn add_device(&self, device: DeviceInfo) {
+        lock(&self.devices).push(device);
+    }
+}
+
+fn canned_devices(now: DateTime<Utc>) -> Vec<DeviceInfo> {
+    vec![
+        DeviceInfo {
+            name: String::from("AirPods Pro"),
+            address: String::from("80-3b-5c-c2-b1-7f"),
+            connected: true,
+            last_used: Some(now - Duration::minutes(5)),
+            battery: Some(80),
+            battery_levels: Some(BatteryInfo::from_buds(Some(80), Some(85), Some(60))),
+            rssi: Some(-52),
+            device_class: Some(DeviceClass::Audio),
+            favourite: true,
+            paired: true,
+            ..Default::default()
+        },
+        DeviceInfo {
+            name: String::from("AirPods Max"),
+            address: String::from("a4-c6-f0-1d-52-e9"),
+            last_used: Some(now - Duration::days(2)),
+            device_class: Some(DeviceClass::Audio),
+            paired: true,
+            ..Default::default()
+        },
+        DeviceInfo {
+            name: String::from("Work AirPods"),
+            address: String::from("3c-06-30-8a-77-10"),
+            last_used: Some(now - Duration::weeks(3)),
+            device_class: Some(DeviceClass::Audio),
+            paired: true,
+            ..Default::default()
+        },
+        DeviceInfo {
+            name: String::from("Magic Keyboard"),
+            address: String::from("f0-b3-ec-12-4a-9d"),
+            connected: true,
+            last_used: Some(now - Duration::hours(1)),
+            device_class: Some(DeviceClass::Peripheral),
+            paired: true,
+            ..Default::default()
+        },
+    ]
+}
+
+fn canned_nearby_devices() -> Vec<DeviceInfo> {
+    vec![DeviceInfo {
+        name: String::from("AirPods (3rd generation)"),
+        address: String::from("6c-4a-85-3e-90-c1"),
+        rssi: Some(-61),
+        device_class: Some(DeviceClass::Audio),
+        ..Default::default()
+    }]
+}
+
+impl Client for FakeClient {
+    fn connect_to_device(&self, address: &str) -> Result<(), BluetoothError> {
+        self.change_device(FakeOperation::Connect, address, |x| x.connected = true)
+    }
+
+    fn disconnect_from_device(&self, address: &str) -> Result<(), BluetoothError> {
+        self.change_device(FakeOperation::Disconnect, address, |x| x.connected = false)
+    }
+
+    fn get_device_list(&self) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        match self.begin(FakeOperation::List) {
+            Some(FakeFailure::Error(kind)) => Err(scripted_error(kind)),
+            Some(FakeFailure::Ignored) => Ok(vec![]),
+            None => Ok(lock(&self.devices).clone()),
+        }
+    }
+
+    fn get_recent_devices(&self) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        let mut devices = lock(&self.devices).clone();
+        devices.sort_by_key(|x| Reverse(x.last_used));
+
+        Ok(devices)
+    }
+
+    fn get_device(&self, address: &str) -> Option<DeviceInfo> {
+        let address = Address::new(address);
+
+        lock(&self.devices)
+            .iter()
+            .find(|x| Address::new(&x.address) == address)
+            .cloned()
+    }
+
+    fn discover(&self, _duration: time::Duration) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        Ok(lock(&self.nearby).clone())
+    }
+
+    fn pair_device(&self, address: &str, _pin: Option<String>) -> Result<(), BluetoothError> {
+        let address = Address::new(address);
+        let mut nearby = lock(&self.nearby);
+
+        if let Some(index) = nearby
+            .iter()
+            .position(|x| Address::new(&x.address) == address)
+        {
+            let device = nearby.remove(index);
+            lock(&self.devices).push(DeviceInfo {
+                paired: true,
+                ..device
+            });
+            return Ok(());
+        }
+
+        self.update_device(address.as_str(), |x| x.paired = true)
+    }
+
+    fn unpair_device(&self, address: &str) -> Result<(), BluetoothError> {
+        self.update_device(address, |x| {
+            x.paired = false;
+            x.connected = false;
+        })
+    }
+
+    fn set_favourite(&self, address: &str, favourite: bool) -> Result<(), BluetoothError> {
+        self.update_device(address, |x| x.favourite = favourite)
+    }
+
+    fn is_powered(&self) -> Result<bool, BluetoothError> {
+        Ok(self.powered.load(Ordering::Relaxed))
+    }
+
+    fn set_power(&self, on: bool) -> Result<(), BluetoothError> {
+        self.powered.store(on, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::all(Backend::Fake)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn client() -> FakeClient {
+        FakeClient::new(Utc.with_ymd_and_hms(2022, 8, 1, 12, 0, 0).unwrap())
+    }
+
+    #[test]
+    fn fake_client_connects_and_disconnects_canned_devices() {
+        let client = client();
+
+        client.connect_to_device("A4:C6:F0:1D:52:E9").unwrap();
+        assert!(client.get_device("a4-c6-f0-1d-52-e9").unwrap().connected);
+
+        client.disconnect_from_device("a4-c6-f0-1d-52-e9").unwrap();
+        assert!(!client.get_device("a4-c6-f0-1d-52-e9").unwrap().connected);
+    }
+
+    #[test]
+    fn fake_client_errors_for_unknown_devices() {
+        let err = client().connect_to_device("00-00-00-00-00-00").unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::DeviceNotFound);
+    }
+
+    #[test]
+    fn fake_client_pairs_discovered_devices() {
+        let client = client();
+
+        let nearby = client.discover(time::Duration::from_secs(3)).unwrap();
+        assert_eq!(nearby.len(), 1);
+
+        client.pair_device(&nearby[0].address, None).unwrap();
+        assert!(client.get_device(&nearby[0].address).unwrap().paired);
+        assert!(client
+            .discover(time::Duration::from_secs(3))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn fake_client_lists_recent_devices_most_recent_first() {
+        let names = client()
+            .get_recent_devices()
+            .unwrap()
+            .into_iter()
+            .map(|x| x.name)
+            .collect::<Vec<String>>();
+
+        assert_eq!(
+            names,
+            vec![
+                "AirPods Pro",
+                "Magic Keyboard",
+                "AirPods Max",
+                "Work AirPods"
+            ]
+        );
+    }
+}
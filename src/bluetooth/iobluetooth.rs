@@ -0,0 +1,259 @@
+use std::{
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_int},
+};
+
+use chrono::{TimeZone, Utc};
+use objc::{
+    class, msg_send,
+    rc::autoreleasepool,
+    runtime::{Object, BOOL, NO},
+    sel, sel_impl,
+};
+
+use super::{
+    valid_rssi, Backend, BluetoothError, Capabilities, Capability, Client, DeviceClass, DeviceInfo,
+    ErrorKind,
+};
+
+// IOReturn value for success
+const K_IO_RETURN_SUCCESS: c_int = 0;
+
+#[link(name = "Foundation", kind = "framework")]
+extern "C" {}
+
+#[link(name = "IOBluetooth", kind = "framework")]
+extern "C" {
+    // Not in the public headers, but what blueutil uses for the power state
+    fn IOBluetoothPreferenceGetControllerPowerState() -> c_int;
+    fn IOBluetoothPreferenceSetControllerPowerState(state: c_int);
+}
+
+// Talks to IOBluetooth directly, so the workflow works without blueutil
+// installed. With dry run set, changes are printed instead of made.
+pub struct IoBluetoothClient {
+    dry_run: bool,
+}
+
+impl IoBluetoothClient {
+    pub fn new(dry_run: bool) -> Self {
+        IoBluetoothClient { dry_run }
+    }
+
+    // Returns true when the change should be skipped.
+    fn skip_for_dry_run(&self, action: &str, address: &str) -> bool {
+        if self.dry_run {
+            eprintln!("Dry run : {} {}", action, address);
+        }
+
+        self.dry_run
+    }
+
+    fn with_device<T, F>(&self, address: &str, f: F) -> Result<T, BluetoothError>
+    where
+        F: FnOnce(*mut Object) -> Result<T, BluetoothError>,
+    {
+        autoreleasepool(|| match device_with_address(address)? {
+            Some(device) => f(device),
+            None => Err(BluetoothError::with_kind(
+                ErrorKind::DeviceNotFound,
+                &format!("Device '{}' not found", address),
+            )),
+        })
+    }
+}
+
+impl Client for IoBluetoothClient {
+    fn connect_to_device(&self, address: &str) -> Result<(), BluetoothError> {
+        if self.skip_for_dry_run("connect", address) {
+            return Ok(());
+        }
+
+        self.with_device(address, |device| {
+            let result: c_int = unsafe { msg_send![device, openConnection] };
+            check_io_return(result, &format!("Failed to connect to '{}'", address))
+        })
+    }
+
+    fn disconnect_from_device(&self, address: &str) -> Result<(), BluetoothError> {
+        if self.skip_for_dry_run("disconnect", address) {
+            return Ok(());
+        }
+
+        self.with_device(address, |device| {
+            let result: c_int = unsafe { msg_send![device, closeConnection] };
+            check_io_return(result, &format!("Failed to disconnect from '{}'", address))
+        })
+    }
+
+    fn get_device_list(&self) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        autoreleasepool(|| {
+            let devices: *mut Object =
+                unsafe { msg_send![class!(IOBluetoothDevice), pairedDevices] };
+            Ok(devices_from_array(devices))
+        })
+    }
+
+    fn get_recent_devices(&self) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        autoreleasepool(|| {
+            // 0 returns every recent device
+            let devices: *mut Object =
+                unsafe { msg_send![class!(IOBluetoothDevice), recentDevices: 0usize] };
+            Ok(devices_from_array(devices))
+        })
+    }
+
+    fn get_device(&self, address: &str) -> Option<DeviceInfo> {
+        self.with_device(address, |device| Ok(device_info(device)))
+            .ok()
+    }
+
+    fn pair_device(&self, _address: &str, _pin: Option<String>) -> Result<(), BluetoothError> {
+        // IOBluetoothDevicePair needs a run loop and delegate, which this
+        // synchronous client can't provide.
+        Err(Capability::Pair.unsupported(Backend::IoBluetooth))
+    }
+
+    fn unpair_device(&self, _address: &str) -> Result<(), BluetoothError> {
+        Err(Capability::Pair.unsupported(Backend::IoBluetooth))
+    }
+
+    fn set_favourite(&self, address: &str, favourite: bool) -> Result<(), BluetoothError> {
+        let action = if favourite {
+            "add-favourite"
+        } else {
+            "remove-favourite"
+        };
+        if self.skip_for_dry_run(action, address) {
+            return Ok(());
+        }
+
+        self.with_device(address, |device| {
+            let result: c_int = unsafe {
+                if favourite {
+                    msg_send![device, addToFavorites]
+                } else {
+                    msg_send![device, removeFromFavorites]
+                }
+            };
+            check_io_return(
+                result,
+                &format!("Failed to update favourites for device '{}'", address),
+            )
+        })
+    }
+
+    fn is_powered(&self) -> Result<bool, BluetoothError> {
+        Ok(unsafe { IOBluetoothPreferenceGetControllerPowerState() } != 0)
+    }
+
+    fn set_power(&self, on: bool) -> Result<(), BluetoothError> {
+        if self.skip_for_dry_run("power", if on { "1" } else { "0" }) {
+            return Ok(());
+        }
+
+        unsafe { IOBluetoothPreferenceSetControllerPowerState(on as c_int) };
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            pair: false,
+            discover: false,
+            ..Capabilities::all(Backend::IoBluetooth)
+        }
+    }
+}
+
+fn check_io_return(result: c_int, message: &str) -> Result<(), BluetoothError> {
+    if result == K_IO_RETURN_SUCCESS {
+        Ok(())
+    } else {
+        Err(BluetoothError::new(&format!(
+            "{} : IOReturn {:#x}",
+            message, result
+        )))
+    }
+}
+
+fn device_with_address(address: &str) -> Result<Option<*mut Object>, BluetoothError> {
+    let address = to_nsstring(address)?;
+    let device: *mut Object =
+        unsafe { msg_send![class!(IOBluetoothDevice), deviceWithAddressString: address] };
+
+    Ok(if device.is_null() { None } else { Some(device) })
+}
+
+fn devices_from_array(devices: *mut Object) -> Vec<DeviceInfo> {
+    if devices.is_null() {
+        return vec![];
+    }
+
+    let count: usize = unsafe { msg_send![devices, count] };
+
+    (0..count)
+        .map(|index| {
+            let device: *mut Object = unsafe { msg_send![devices, objectAtIndex: index] };
+            device_info(device)
+        })
+        .collect()
+}
+
+fn device_info(device: *mut Object) -> DeviceInfo {
+    unsafe {
+        let address: *mut Object = msg_send![device, addressString];
+        let name: *mut Object = msg_send![device, name];
+        let connected: BOOL = msg_send![device, isConnected];
+        let paired: BOOL = msg_send![device, isPaired];
+        let favourite: BOOL = msg_send![device, isFavorite];
+        let recent_access_date: *mut Object = msg_send![device, recentAccessDate];
+        let class_of_device: u32 = msg_send![device, classOfDevice];
+        // Only meaningful while connected
+        let rssi = if connected != NO {
+            let rssi: i8 = msg_send![device, RSSI];
+            valid_rssi(Some(rssi as i16))
+        } else {
+            None
+        };
+
+        let last_used = if recent_access_date.is_null() {
+            None
+        } else {
+            let seconds: f64 = msg_send![recent_access_date, timeIntervalSince1970];
+            Utc.timestamp_opt(seconds as i64, 0).single()
+        };
+
+        DeviceInfo {
+            name: from_nsstring(name),
+            address: from_nsstring(address),
+            connected: connected != NO,
+            last_used,
+            favourite: favourite != NO,
+            paired: paired != NO,
+            rssi,
+            device_class: Some(DeviceClass::from_class_of_device(class_of_device)),
+            ..Default::default()
+        }
+    }
+}
+
+fn to_nsstring(value: &str) -> Result<*mut Object, BluetoothError> {
+    let value = CString::new(value).map_err(|err| BluetoothError::new(&err.to_string()))?;
+
+    Ok(unsafe { msg_send![class!(NSString), stringWithUTF8String: value.as_ptr()] })
+}
+
+fn from_nsstring(value: *mut Object) -> String {
+    if value.is_null() {
+        return String::new();
+    }
+
+    unsafe {
+        let bytes: *const c_char = msg_send![value, UTF8String];
+        if bytes.is_null() {
+            return String::new();
+        }
+
+        CStr::from_ptr(bytes).to_string_lossy().into_owned()
+    }
+}
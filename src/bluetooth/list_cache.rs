@@ -0,0 +1,164 @@
+use std::{path::PathBuf, sync::Mutex, time::Duration};
+
+use log::warn;
+
+use super::{BluetoothError, DeviceInfo};
+use crate::cache::DeviceCache;
+use crate::clock::Clock;
+
+// Keeps the backend's device list for a short while, so repeated lists, like
+// one per Alfred keystroke, don't each run the backend. The list is kept on
+// disk when there's a path so it's shared between runs.
+pub(super) struct DeviceListCache {
+    ttl: Duration,
+    path: Option<PathBuf>,
+    clock: Box<dyn Clock + Send + Sync>,
+    cache: Mutex<DeviceCache>,
+}
+
+impl DeviceListCache {
+    // A cache that can't be loaded starts out empty.
+    pub(super) fn new(
+        ttl: Duration,
+        path: Option<PathBuf>,
+        clock: Box<dyn Clock + Send + Sync>,
+    ) -> Self {
+        let cache = match &path {
+            Some(path) => DeviceCache::load(path).unwrap_or_else(|err| {
+                warn!("Failed to load the device cache : {}", err);
+                DeviceCache::new()
+            }),
+            None => DeviceCache::new(),
+        };
+
+        DeviceListCache {
+            ttl,
+            path,
+            clock,
+            cache: Mutex::new(cache),
+        }
+    }
+
+    // The cached devices, or the fetched ones when the cache has expired.
+    pub(super) fn get_or_fetch<F>(&self, fetch: F) -> Result<Vec<DeviceInfo>, BluetoothError>
+    where
+        F: FnOnce() -> Result<Vec<DeviceInfo>, BluetoothError>,
+    {
+        if let Some(devices) = self.get() {
+            return Ok(devices);
+        }
+
+        let devices = fetch()?;
+        self.update(&devices);
+
+        Ok(devices)
+    }
+
+    // The cached devices, None when the cache has expired. The cache isn't locked
+    // while fetching, so the async API can fetch and then update it too.
+    pub(super) fn get(&self) -> Option<Vec<DeviceInfo>> {
+        let cache = self.cache.lock().unwrap_or_else(|err| err.into_inner());
+
+        (!cache.is_expired(self.ttl, self.clock.as_ref())).then(|| cache.devices())
+    }
+
+    pub(super) fn update(&self, devices: &[DeviceInfo]) {
+        let mut cache = self.cache.lock().unwrap_or_else(|err| err.into_inner());
+
+        cache.update(devices, self.clock.as_ref());
+        self.store(&cache);
+    }
+
+    // Called after anything that changes a device, so the next list is fresh.
+    pub(super) fn invalidate(&self) {
+        let mut cache = self.cache.lock().unwrap_or_else(|err| err.into_inner());
+
+        cache.invalidate();
+        self.store(&cache);
+    }
+
+    // Failing to store the cache only costs the next run a query.
+    fn store(&self, cache: &DeviceCache) {
+        if let Some(path) = &self.path {
+            if let Err(err) = cache.store(path) {
+                warn!("Failed to store the device cache : {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, env, fs, process};
+
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::clock::FixedClock;
+
+    fn clock() -> Box<FixedClock> {
+        Box::new(FixedClock(
+            Utc.with_ymd_and_hms(2022, 8, 1, 12, 0, 0).unwrap(),
+        ))
+    }
+
+    fn devices() -> Vec<DeviceInfo> {
+        vec![DeviceInfo {
+            name: String::from("AirPods Pro"),
+            address: String::from("80-3b-5c-c2-b1-7f"),
+            connected: true,
+            favourite: true,
+            paired: true,
+            ..Default::default()
+        }]
+    }
+
+    #[test]
+    fn device_list_cache_fetches_once_until_invalidated() {
+        let cache = DeviceListCache::new(Duration::from_secs(30), None, clock());
+        let fetches = Cell::new(0);
+        let fetch = || {
+            fetches.set(fetches.get() + 1);
+            Ok(devices())
+        };
+
+        assert_eq!(cache.get_or_fetch(fetch).unwrap(), devices());
+        assert_eq!(cache.get_or_fetch(fetch).unwrap(), devices());
+        assert_eq!(fetches.get(), 1);
+
+        cache.invalidate();
+        cache.get_or_fetch(fetch).unwrap();
+        assert_eq!(fetches.get(), 2);
+    }
+
+    #[test]
+    fn device_list_cache_get_only_returns_updated_devices() {
+        let cache = DeviceListCache::new(Duration::from_secs(30), None, clock());
+        assert_eq!(cache.get(), None);
+
+        cache.update(&devices());
+        assert_eq!(cache.get(), Some(devices()));
+
+        cache.invalidate();
+        assert_eq!(cache.get(), None);
+    }
+
+    #[test]
+    fn device_list_cache_is_shared_through_its_file() {
+        let path = env::temp_dir()
+            .join(format!("airpod-alfred-list-cache-test-{}", process::id()))
+            .join("devices.json");
+
+        DeviceListCache::new(Duration::from_secs(30), Some(path.clone()), clock())
+            .get_or_fetch(|| Ok(devices()))
+            .unwrap();
+
+        let cache = DeviceListCache::new(Duration::from_secs(30), Some(path.clone()), clock());
+        let cached = cache
+            .get_or_fetch(|| Err(BluetoothError::new("not cached")))
+            .unwrap();
+        assert_eq!(cached, devices());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
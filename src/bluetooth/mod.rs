@@ -1,59 +1,405 @@
-use std::{error::Error, fmt, process::Command, str};
+mod address;
+#[cfg(feature = "async")]
+mod async_client;
+mod audit;
+mod backend;
+#[cfg(all(target_os = "linux", feature = "bluez"))]
+mod bluez;
+mod device_class;
+mod diagnostics;
+mod error;
+mod events;
+mod fake;
+#[cfg(target_os = "macos")]
+mod iobluetooth;
+mod list_cache;
+mod profiler;
+mod version;
+#[cfg(all(windows, feature = "winrt"))]
+mod winrt;
 
-use log::trace;
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::HashMap,
+    error::Error,
+    io::{self, Read},
+    path::PathBuf,
+    process::{Command, ExitStatus, Stdio},
+    str,
+    sync::OnceLock,
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{trace, warn};
 
 use mockall::*;
 
-use lazy_static::lazy_static;
+use regex::{Regex, RegexBuilder};
 
-use regex::Regex;
-#[derive(Debug, PartialEq)]
+use chrono::{DateTime, Utc};
+
+use clap::ArgEnum;
+
+use serde::{Deserialize, Serialize};
+
+use super::battery::{self, BatteryInfo};
+use super::clock::SystemClock;
+use super::history::{History, HistoryAction, HistoryEntry};
+use list_cache::DeviceListCache;
+
+pub use address::Address;
+#[cfg(feature = "async")]
+pub use async_client::{AsyncBlueutilClient, AsyncClient};
+pub use audit::default_audit_log_path;
+pub use backend::{Backend, Capabilities, Capability};
+pub use device_class::DeviceClass;
+pub use diagnostics::{ParseDiagnostics, ParseError, ParseFailure};
+pub use error::{BluetoothError, ErrorKind};
+pub use events::{ConnectionWatcher, DeviceEvent};
+pub use fake::{FakeClient, FakeFailure, FakeOperation};
+#[cfg(target_os = "macos")]
+pub use iobluetooth::IoBluetoothClient;
+pub use version::BlueutilVersion;
+
+// Fields missing when deserializing, e.g. from an older cache file, are left at
+// their defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct DeviceInfo {
     pub name: String,
     pub address: String,
     pub connected: bool,
+    // Parsed from blueutil's "recent access date"
+    pub last_used: Option<DateTime<Utc>>,
+    // Battery percentage, when known
+    pub battery: Option<u8>,
+    // Each bud and the case for AirPods, when the backend reports them
+    pub battery_levels: Option<BatteryInfo>,
+    // Signal strength in dBm, only known while connected
+    pub rssi: Option<i16>,
+    // None when the backend doesn't report it, like blueutil
+    pub device_class: Option<DeviceClass>,
+    // Whether the device is the current system audio output
+    pub active_output: bool,
+    pub favourite: bool,
+    pub paired: bool,
+}
+
+// A device as reported by `blueutil --format json`. Everything but the address
+// is optional so a device with missing details is still listed.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlueutilDevice {
+    address: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    connected: bool,
+    #[serde(default)]
+    favourite: bool,
+    #[serde(default)]
+    paired: bool,
+    #[serde(default)]
+    recent_access_date: Option<String>,
+    // Only present for connected devices
+    #[serde(default, rename = "RSSI")]
+    rssi: Option<i16>,
+}
+
+// RSSI value IOBluetooth reports when it isn't available
+const RSSI_UNAVAILABLE: i16 = 127;
+
+// Drops the placeholder for an unavailable RSSI.
+fn valid_rssi(rssi: Option<i16>) -> Option<i16> {
+    rssi.filter(|x| *x != RSSI_UNAVAILABLE)
 }
 
-impl DeviceInfo {
-    fn from_raw_str(data: &str) -> DeviceInfo {
-        lazy_static! {
-            static ref RE: Regex =
-                Regex::new(r#"^address: ([a-zA-Z0-9_-]{17}),.*name: "([^"]*)""#).unwrap();
+impl From<BlueutilDevice> for DeviceInfo {
+    fn from(device: BlueutilDevice) -> Self {
+        DeviceInfo {
+            name: device.name.unwrap_or_default(),
+            address: device.address,
+            connected: device.connected,
+            last_used: device
+                .recent_access_date
+                .as_deref()
+                .and_then(parse_recent_access_date),
+            favourite: device.favourite,
+            paired: device.paired,
+            rssi: valid_rssi(device.rssi),
+            ..Default::default()
         }
+    }
+}
 
-        assert!(
-            RE.is_match(&data),
-            "Regex to match Bluetooth info shouldn't fail. Failed to match : {}",
-            data
-        );
+// blueutil writes ISO 8601 dates, older versions used "2022-08-01 12:00:10 +0000".
+fn parse_recent_access_date(date: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(date)
+        .or_else(|_| DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S %z"))
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
 
-        let mut name: String = Default::default();
-        let mut address: String = Default::default();
-        let connected: bool = !data.contains("not connected");
-        for cap in RE.captures_iter(data) {
-            name = cap.get(2).map_or("", |m| m.as_str()).to_string();
-            address = cap.get(1).map_or("", |m| m.as_str()).to_string();
-            break;
+// Parses the JSON output of `blueutil --format json`, which is an array of
+// devices for listings and a single device for `--info`. Entries that can't be
+// parsed are recorded instead of failing the whole listing.
+pub fn parse_device_list(
+    output: &str,
+) -> Result<(Vec<DeviceInfo>, ParseDiagnostics), serde_json::Error> {
+    let mut diagnostics = ParseDiagnostics::new();
+
+    if output.trim().is_empty() {
+        return Ok((vec![], diagnostics));
+    }
+
+    let entries = match serde_json::from_str(output)? {
+        serde_json::Value::Array(entries) => entries,
+        entry => vec![entry],
+    };
+
+    let mut devices = vec![];
+    for (index, entry) in entries.into_iter().enumerate() {
+        match serde_json::from_value::<BlueutilDevice>(entry.clone()) {
+            Ok(device) => devices.push(device.into()),
+            Err(err) => diagnostics.record_failure(index, &entry.to_string(), &err.to_string()),
         }
+    }
 
-        return DeviceInfo {
-            name,
-            address,
-            connected,
-        };
+    Ok((devices, diagnostics))
+}
+
+// The paired list as the backend printed it, along with what was parsed from it,
+// for tracking down parser issues.
+#[derive(Debug)]
+pub struct RawDeviceList {
+    pub output: String,
+    pub devices: Vec<DeviceInfo>,
+    pub diagnostics: ParseDiagnostics,
+}
+
+// Parses blueutil's default text format, which releases without `--format json`
+// only have. It's a line per device, e.g. `address: 80-3b-5c-c2-b1-7f,
+// connected (master, -56 dBm), not favourite, paired, name: "AirPods Pro",
+// recent access date: 2022-08-01 12:10:10 +0000`.
+pub fn parse_default_device_list(output: &str) -> (Vec<DeviceInfo>, ParseDiagnostics) {
+    let mut diagnostics = ParseDiagnostics::new();
+
+    let mut devices = vec![];
+    // Indices are line numbers in the output, counting blank lines too
+    for (index, line) in output
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+    {
+        match parse_default_device(line) {
+            Ok(device) => devices.push(device),
+            Err(err) => diagnostics.record_error(index, &err),
+        }
     }
+
+    (devices, diagnostics)
+}
+
+fn parse_default_device(line: &str) -> Result<DeviceInfo, ParseError> {
+    let (address, rest) = line
+        .trim()
+        .strip_prefix("address: ")
+        .and_then(|x| x.split_once(", "))
+        .ok_or_else(|| ParseError::new(line, "address"))?;
+    // Names can contain commas and quotes, so they're split off from both ends
+    let (flags, rest) = rest
+        .split_once("name: ")
+        .ok_or_else(|| ParseError::new(line, "name"))?;
+    let (name, recent_access_date) = match rest.rsplit_once(", recent access date: ") {
+        Some((name, date)) => (name, Some(date)),
+        None => (rest, None),
+    };
+    let flags = flags.split(", ").map(str::trim).collect::<Vec<&str>>();
+
+    Ok(DeviceInfo {
+        name: name
+            .strip_prefix('"')
+            .and_then(|x| x.strip_suffix('"'))
+            .unwrap_or_default()
+            .to_string(),
+        address: address.to_string(),
+        connected: flags
+            .first()
+            .filter(|x| !x.is_empty())
+            .ok_or_else(|| ParseError::new(line, "connection state"))?
+            .starts_with("connected"),
+        last_used: recent_access_date.and_then(parse_recent_access_date),
+        // e.g. `-56 dBm)` from `connected (master, -56 dBm)`
+        rssi: valid_rssi(
+            flags
+                .iter()
+                .find_map(|x| x.strip_suffix(" dBm)")?.parse().ok()),
+        ),
+        favourite: flags.contains(&"favourite"),
+        paired: flags.contains(&"paired"),
+        ..Default::default()
+    })
 }
 
 #[derive(Debug, PartialEq)]
 pub enum DeviceFilters {
     AllDevices,
     SpecificAddresses { addresses: Vec<String> },
+    // Case insensitive pattern searched for in the device name
     Regex { value: String },
+    ConnectionState(ConnectionState),
+    // Devices without a known class, e.g. everything from blueutil, count as
+    // audio devices so headphones that aren't AirPods aren't hidden
+    DeviceClass(DeviceClass),
+    // Devices matching every filter, or every device when there are none
+    All(Vec<DeviceFilters>),
+    // Devices matching any of the filters, or no devices when there are none
+    Any(Vec<DeviceFilters>),
+    Not(Box<DeviceFilters>),
+    // Drops devices matching any of the values, which are addresses or
+    // otherwise name patterns like Regex
+    Exclude { values: Vec<String> },
+}
+
+type DeviceMatcher = Box<dyn Fn(&DeviceInfo) -> bool>;
+
+impl DeviceFilters {
+    // Builds the filter once, so patterns aren't compiled again for every device.
+    fn matcher(&self) -> Result<DeviceMatcher, BluetoothError> {
+        Ok(match self {
+            DeviceFilters::AllDevices => Box::new(|_| true),
+            DeviceFilters::SpecificAddresses { addresses } => {
+                let addresses = addresses
+                    .iter()
+                    .map(|x| Address::new(x))
+                    .collect::<Vec<Address>>();
+
+                Box::new(move |x| addresses.contains(&Address::new(&x.address)))
+            }
+            DeviceFilters::Regex { value } => {
+                let regex = name_regex(value)?;
+
+                Box::new(move |x| regex.is_match(&x.name))
+            }
+            DeviceFilters::ConnectionState(state) => {
+                let state = *state;
+
+                Box::new(move |x| ConnectionState::from_connected(x.connected) == state)
+            }
+            DeviceFilters::DeviceClass(class) => {
+                let class = *class;
+
+                Box::new(move |x| match x.device_class {
+                    Some(device_class) => device_class == class,
+                    None => class == DeviceClass::Audio,
+                })
+            }
+            DeviceFilters::All(filters) => {
+                let matchers = filters
+                    .iter()
+                    .map(DeviceFilters::matcher)
+                    .collect::<Result<Vec<DeviceMatcher>, BluetoothError>>()?;
+
+                Box::new(move |x| matchers.iter().all(|matcher| matcher(x)))
+            }
+            DeviceFilters::Any(filters) => {
+                let matchers = filters
+                    .iter()
+                    .map(DeviceFilters::matcher)
+                    .collect::<Result<Vec<DeviceMatcher>, BluetoothError>>()?;
+
+                Box::new(move |x| matchers.iter().any(|matcher| matcher(x)))
+            }
+            DeviceFilters::Not(filter) => {
+                let matcher = filter.matcher()?;
+
+                Box::new(move |x| !matcher(x))
+            }
+            DeviceFilters::Exclude { values } => {
+                let excluded = values
+                    .iter()
+                    .map(|x| {
+                        if Address::new(x).is_mac() {
+                            DeviceFilters::SpecificAddresses {
+                                addresses: vec![x.clone()],
+                            }
+                        } else {
+                            DeviceFilters::Regex { value: x.clone() }
+                        }
+                    })
+                    .collect();
+
+                DeviceFilters::Not(Box::new(DeviceFilters::Any(excluded))).matcher()?
+            }
+        })
+    }
+}
+
+fn name_regex(pattern: &str) -> Result<Regex, BluetoothError> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|err| {
+            BluetoothError::new(&format!("Invalid filter pattern '{}' : {}", pattern, err))
+        })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ArgEnum)]
+pub enum SortKey {
+    // The previously selected device
+    Previous,
+    Connected,
+    Favourite,
+    // Case insensitive, alphabetical
+    Name,
+    // Most recently used first, devices without a recent access date last
+    Recent,
+}
+
+// The previous device, then connected devices, then favourites.
+pub const DEFAULT_SORT_ORDER: &[SortKey] =
+    &[SortKey::Previous, SortKey::Connected, SortKey::Favourite];
+
+// Named presets for the sort order. The previous device is always kept on top.
+#[derive(Debug, Clone, Copy, PartialEq, ArgEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Name,
+    Recent,
+    Connected,
+    // Favourites first, then the most recently used
+    Frecency,
+}
+
+impl SortOrder {
+    pub fn sort_keys(&self) -> Vec<SortKey> {
+        match self {
+            SortOrder::Name => vec![SortKey::Previous, SortKey::Name],
+            SortOrder::Recent => vec![SortKey::Previous, SortKey::Recent, SortKey::Name],
+            SortOrder::Connected => DEFAULT_SORT_ORDER.to_vec(),
+            SortOrder::Frecency => vec![
+                SortKey::Previous,
+                SortKey::Favourite,
+                SortKey::Recent,
+                SortKey::Name,
+            ],
+        }
+    }
 }
 
 pub struct DeviceListOptions {
     filters: DeviceFilters,
     previous_address: Option<String>,
+    sort_order: Vec<SortKey>,
+    details: bool,
+}
+
+impl Default for DeviceListOptions {
+    fn default() -> Self {
+        Self::new_default_all_devices()
+    }
 }
 
 impl DeviceListOptions {
@@ -61,562 +407,4696 @@ impl DeviceListOptions {
         DeviceListOptions {
             filters,
             previous_address,
+            sort_order: DEFAULT_SORT_ORDER.to_vec(),
+            details: false,
         }
     }
 
-    fn new_default_all_devices() -> Self {
-        return DeviceListOptions {
-            filters: DeviceFilters::AllDevices,
-            previous_address: None,
-        };
+    // Queries each connected device for details the list leaves out, like
+    // battery and RSSI. The queries run side by side where the backend can.
+    pub fn with_details(mut self) -> Self {
+        self.details = true;
+        self
     }
-}
-
-pub struct BluetoothClient {
-    blueutil_client: Box<dyn Client>,
-}
 
-impl BluetoothClient {
-    pub fn new() -> Self {
-        BluetoothClient {
-            blueutil_client: Box::new(BlueutilClient::new()),
-        }
+    // Earlier keys take precedence, later keys break ties.
+    pub fn with_sort_order(mut self, sort_order: Vec<SortKey>) -> Self {
+        self.sort_order = sort_order;
+        self
     }
 
-    pub fn connect_to_device(&self, address: &str) -> Result<(), Box<dyn Error>> {
-        self.blueutil_client.connect_to_device(address)
+    pub fn with_sort(self, sort: SortOrder) -> Self {
+        self.with_sort_order(sort.sort_keys())
     }
 
-    pub fn disconnect_from_device(&self, address: &str) -> Result<(), Box<dyn Error>> {
-        self.blueutil_client.disconnect_from_device(address)
+    pub fn new_default_all_devices() -> Self {
+        DeviceListOptions::new(DeviceFilters::AllDevices, None)
     }
+}
 
-    // bool indicates that the device was connected to.
-    pub fn toggle_connected_status(&self, address: &str) -> Result<bool, Box<dyn Error>> {
-        let device = self.get_device_info(address)?;
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionState {
+    Connected,
+    Disconnected,
+}
 
-        if device.connected {
-            self.disconnect_from_device(address)?;
-            Ok(false)
+impl ConnectionState {
+    pub fn from_connected(connected: bool) -> Self {
+        if connected {
+            ConnectionState::Connected
         } else {
-            self.connect_to_device(address)?;
-            Ok(true)
+            ConnectionState::Disconnected
         }
     }
 
-    pub fn get_device_list(&self, options: DeviceListOptions) -> Vec<DeviceInfo> {
-        let mut devices = self.blueutil_client.get_device_list();
-        devices = self.get_filtered_devices(devices, options.filters);
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionState::Connected => "connected",
+            ConnectionState::Disconnected => "disconnected",
+        }
+    }
+}
 
-        devices.sort_by(|a, b| b.connected.cmp(&a.connected));
+// Breakdown of a connect operation, for reliability reporting.
+#[derive(Debug, PartialEq)]
+pub struct ConnectStats {
+    pub attempts: u32,
+    pub succeeded: bool,
+    pub total_ms: u128,
+    // None when the state couldn't be determined after the last attempt
+    pub final_state: Option<ConnectionState>,
+    pub error: Option<String>,
+}
 
-        // Move last used device to the top
-        if let Some(previous_address) = options.previous_address {
-            devices.sort_by_key(|a| a.address.to_lowercase() != previous_address.to_lowercase());
-        }
+// The address of a device and whether connecting to it worked.
+pub type ConnectResult = (String, Result<(), BluetoothError>);
 
-        devices
-    }
+// Outcome of disconnecting a single device as part of disconnecting everything.
+#[derive(Debug, PartialEq)]
+pub struct DisconnectResult {
+    pub device: DeviceInfo,
+    pub error: Option<String>,
+}
 
-    pub fn print_devices(&self) {
-        let parsed_devices = self.get_device_list(DeviceListOptions::new_default_all_devices());
+#[derive(Debug, PartialEq)]
+pub enum KeepaliveStatus {
+    StillConnected,
+    Reconnected,
+}
 
-        for parsed_device in parsed_devices {
-            println!("{:#?}", parsed_device);
-        }
-    }
+const VERIFY_POLL_INTERVAL: Duration = Duration::from_millis(500);
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(20);
 
-    pub fn is_device_connected(&self, address: &str) -> Result<bool, BluetoothClientError> {
-        let device = self.get_device_info(address)?;
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
 
-        Ok(device.connected)
-    }
+// How many devices connect_many connects at once, Bluetooth radios struggle to
+// page more than a few devices at a time.
+const CONNECT_POOL_SIZE: usize = 4;
 
-    fn get_filtered_devices(
-        &self,
-        devices: Vec<DeviceInfo>,
-        filters: DeviceFilters,
-    ) -> Vec<DeviceInfo> {
-        match filters {
-            DeviceFilters::AllDevices => devices,
-            DeviceFilters::SpecificAddresses { addresses } => devices
-                .into_iter()
-                .filter(|x| addresses.contains(&x.address.to_lowercase()))
-                .collect(),
-            DeviceFilters::Regex { value } => devices
-                .into_iter()
-                .filter(|x| x.name.to_lowercase().contains(&value))
-                .collect(),
+// How many connection attempts to make and how long to wait between them. The
+// delay doubles after every failed attempt, up to MAX_RETRY_DELAY.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(retries: u32, delay: Duration) -> Self {
+        RetryPolicy {
+            max_attempts: retries + 1,
+            delay,
         }
     }
 
-    fn get_device_info(&self, address: &str) -> Result<DeviceInfo, BluetoothClientError> {
-        let device_list_options = DeviceListOptions::new(
-            DeviceFilters::SpecificAddresses {
-                addresses: vec![address.to_string()],
-            },
-            None,
-        );
-        let mut filtered_device = self
-            .get_device_list(device_list_options)
-            .into_iter()
-            .filter(|x| x.address.to_lowercase() == address.to_lowercase())
-            .collect::<Vec<DeviceInfo>>();
+    pub fn no_retries() -> Self {
+        RetryPolicy::new(0, Duration::ZERO)
+    }
 
-        if filtered_device.len() == 0 {
-            Err(BluetoothClientError::new(&format!(
-                "Could not find device id : '{}'",
-                address
-            )))
-        } else {
-            Ok(filtered_device.remove(0))
-        }
+    // Delay before the given retry, where the first retry is 1.
+    pub fn delay_before_retry(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+
+        self.delay
+            .checked_mul(factor)
+            .unwrap_or(MAX_RETRY_DELAY)
+            .min(MAX_RETRY_DELAY)
     }
 }
 
-#[derive(Debug)]
-pub struct BluetoothClientError {
-    details: String,
+// How a connect or disconnect is made.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnectOptions {
+    // Blocks until the backend confirms the change, failing with
+    // ErrorKind::Timeout when it doesn't happen in time. None returns as soon
+    // as the change is requested.
+    pub wait: Option<Duration>,
+    // Polls the device once the change is made, failing with
+    // ErrorKind::ConnectFailed or DisconnectFailed when it doesn't report the
+    // change in time. AirPods in their case don't connect even though blueutil
+    // succeeds. Disconnects are retried once before failing.
+    pub verify: Option<Duration>,
+    // Turns the radio on first when it's off, only used when connecting
+    pub power_on: bool,
 }
 
-impl BluetoothClientError {
-    fn new(msg: &str) -> BluetoothClientError {
-        BluetoothClientError {
-            details: msg.to_string(),
+impl ConnectOptions {
+    pub fn wait(timeout: Duration) -> Self {
+        ConnectOptions {
+            wait: Some(timeout),
+            ..Default::default()
         }
     }
-}
 
-impl fmt::Display for BluetoothClientError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.details)
+    pub fn with_verify(mut self, timeout: Duration) -> Self {
+        self.verify = Some(timeout);
+        self
     }
-}
 
-impl Error for BluetoothClientError {
-    fn description(&self) -> &str {
-        &self.details
+    pub fn with_power_on(mut self) -> Self {
+        self.power_on = true;
+        self
     }
 }
 
-pub trait Client {
-    fn connect_to_device(&self, address: &str) -> Result<(), Box<dyn Error>>;
-    fn disconnect_from_device(&self, address: &str) -> Result<(), Box<dyn Error>>;
-    fn get_device_list(&self) -> Vec<DeviceInfo>;
+pub struct BluetoothClient {
+    blueutil_client: Box<dyn Client>,
+    history: Option<History>,
+    device_list_cache: Option<DeviceListCache>,
+    fallback_client: Option<Box<dyn Client>>,
+    // Used by the async API when the backend has an async counterpart
+    #[cfg(feature = "async")]
+    async_client: Option<std::sync::Arc<dyn AsyncClient>>,
 }
 
-struct BlueutilClient {
-    command_runner: Box<dyn CommandRunner>,
+impl Default for BluetoothClient {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-#[automock]
-impl Client for BlueutilClient {
-    fn connect_to_device(&self, address: &str) -> Result<(), Box<dyn Error>> {
-        let output = self.run_command(vec!["--connect", &address]);
-
-        trace!("{:?}", &output.stdout);
-        trace!("{:?}", &output.stderr);
+#[derive(Debug, Default)]
+pub struct BluetoothClientOptions {
+    pub backend: Backend,
+    // Path to the blueutil binary, BLUEUTIL_PATH takes precedence over it
+    pub blueutil_path: Option<String>,
+    // Prints the blueutil commands that would change anything instead of running
+    // them. Queries still run so the output stays realistic.
+    pub dry_run: bool,
+    // Where connects and disconnects are recorded, nothing is recorded without it
+    pub history_path: Option<PathBuf>,
+    // How long a blueutil command can run before it's killed, defaults to
+    // DEFAULT_COMMAND_TIMEOUT
+    pub command_timeout: Option<Duration>,
+    // How long the device list is cached for, nothing is cached without it
+    pub cache_ttl: Option<Duration>,
+    // Where the cached device list is kept between runs, it's only kept in
+    // memory without it
+    pub cache_path: Option<PathBuf>,
+    // Where every backend command is logged, nothing is logged without it
+    pub audit_log_path: Option<PathBuf>,
+    // Lists devices with this backend when the main one fails, e.g. when
+    // blueutil is missing, see get_device_list_or_fallback
+    pub fallback_backend: Option<Backend>,
+}
 
-        Ok(())
+impl BluetoothClient {
+    pub fn new() -> Self {
+        Self::with_options(Default::default())
     }
 
-    fn disconnect_from_device(&self, address: &str) -> Result<(), Box<dyn Error>> {
-        let output = self.run_command(vec!["--disconnect", &address, "--info", &address]);
+    pub fn with_options(options: BluetoothClientOptions) -> Self {
+        #[cfg(feature = "async")]
+        let async_client = backend::create_async_client(&options);
+        let history = options.history_path.clone().map(History::new);
+        let device_list_cache = options.cache_ttl.map(|ttl| {
+            DeviceListCache::new(ttl, options.cache_path.clone(), Box::new(SystemClock))
+        });
+        let fallback_client = options
+            .fallback_backend
+            .filter(|x| *x != options.backend)
+            .map(|backend| {
+                backend::create_client(BluetoothClientOptions {
+                    backend,
+                    blueutil_path: options.blueutil_path.clone(),
+                    dry_run: options.dry_run,
+                    command_timeout: options.command_timeout,
+                    audit_log_path: options.audit_log_path.clone(),
+                    ..Default::default()
+                })
+            });
 
-        trace!("{:?}", &output.stdout);
-        trace!("{:?}", &output.stderr);
+        BluetoothClient {
+            history,
+            device_list_cache,
+            fallback_client,
+            #[cfg(feature = "async")]
+            async_client,
+            ..Self::with_client(backend::create_client(options))
+        }
+    }
 
-        Ok(())
+    // Uses the given client instead of one of the built in backends, e.g. for
+    // other platforms or for tests.
+    pub fn with_client(blueutil_client: Box<dyn Client>) -> Self {
+        BluetoothClient {
+            blueutil_client,
+            history: None,
+            device_list_cache: None,
+            fallback_client: None,
+            #[cfg(feature = "async")]
+            async_client: None,
+        }
     }
 
-    fn get_device_list(&self) -> Vec<DeviceInfo> {
-        let output = self.run_command(vec!["--paired"]);
+    // What the backend supports, unsupported operations fail with
+    // ErrorKind::Unsupported.
+    pub fn capabilities(&self) -> Capabilities {
+        self.blueutil_client.capabilities()
+    }
 
-        let results = str::from_utf8(&output.stdout).unwrap();
+    // Bypasses the cache, so it shows what the backend prints right now.
+    pub fn raw_device_list(&self) -> Result<RawDeviceList, BluetoothError> {
+        self.blueutil_client.raw_device_list()
+    }
 
-        results
-            .split("\n")
-            .filter(|x| x.len() > 0)
-            .map(|x| DeviceInfo::from_raw_str(x))
-            .collect()
+    pub fn connect_to_device(&self, address: &str) -> Result<(), BluetoothError> {
+        self.connect_with_options(address, &Default::default())
     }
-}
 
-impl BlueutilClient {
-    fn new() -> Self {
-        BlueutilClient {
-            command_runner: Box::new(DefaultCommandRunner {}),
+    pub fn connect_with_options(
+        &self,
+        address: &str,
+        options: &ConnectOptions,
+    ) -> Result<(), BluetoothError> {
+        self.capabilities().require(Capability::Connect)?;
+        if options.power_on {
+            self.ensure_powered()?;
         }
-    }
 
-    fn run_command(&self, args: Vec<&str>) -> std::process::Output {
-        self.command_runner.run_command(
-            &self.get_blueutil_path(),
-            args.into_iter().map(|x| x.to_string()).collect(),
-        )
+        let result = self
+            .blueutil_client
+            .connect_to_device(address)
+            .and_then(|_| self.wait_for_state(address, ConnectionState::Connected, options))
+            .and_then(|_| self.verify_state(address, ConnectionState::Connected, options));
+        self.invalidate_cache();
+        self.record_history(HistoryAction::Connect, address, &result);
+
+        result
     }
 
-    fn get_blueutil_path(&self) -> String {
-        match std::env::var("BLUEUTIL_PATH") {
-            Ok(val) => format!("{}/blueutil", val),
-            Err(_) => String::from("blueutil"),
-        }
+    pub fn disconnect_from_device(&self, address: &str) -> Result<(), BluetoothError> {
+        self.disconnect_with_options(address, &Default::default())
     }
-}
 
-//
-#[automock]
-trait CommandRunner {
-    fn run_command(&self, command: &str, args: Vec<String>) -> std::process::Output;
-}
+    pub fn disconnect_with_options(
+        &self,
+        address: &str,
+        options: &ConnectOptions,
+    ) -> Result<(), BluetoothError> {
+        self.capabilities().require(Capability::Connect)?;
 
-struct DefaultCommandRunner {}
-impl CommandRunner for DefaultCommandRunner {
-    fn run_command(&self, command: &str, args: Vec<String>) -> std::process::Output {
-        Command::new(command)
-            .args(args)
-            .output()
-            .expect("There was an error running blueutil")
+        let result = self
+            .blueutil_client
+            .disconnect_from_device(address)
+            .and_then(|_| self.wait_for_state(address, ConnectionState::Disconnected, options))
+            .and_then(|_| self.verify_disconnected(address, options));
+        self.invalidate_cache();
+        self.record_history(HistoryAction::Disconnect, address, &result);
+
+        result
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::os::unix::process::ExitStatusExt;
+    fn wait_for_state(
+        &self,
+        address: &str,
+        state: ConnectionState,
+        options: &ConnectOptions,
+    ) -> Result<(), BluetoothError> {
+        match options.wait {
+            Some(timeout) => self.blueutil_client.wait_for_state(address, state, timeout),
+            None => Ok(()),
+        }
+    }
 
-    use super::*;
+    // Unlike wait_for_state this asks for the device's state itself, so it
+    // catches changes the backend reported but that didn't happen.
+    fn verify_state(
+        &self,
+        address: &str,
+        state: ConnectionState,
+        options: &ConnectOptions,
+    ) -> Result<(), BluetoothError> {
+        let timeout = match options.verify {
+            Some(timeout) => timeout,
+            None => return Ok(()),
+        };
 
-    #[test]
-    fn device_info_parses_raw_str() {
-        let valid_str_not_connected = r#"address: 5c-2e-fg-da-a3-43, not connected, not favourite, paired, name: "AirPods Pro", recent access date: 2022-08-01 12:00:10 +0000"#;
-        let valid_str_connected = r#"address: 80-3b-5c-c2-b1-7f, connected (master, 0 dBm), not favourite, paired, name: "AirPods Max", recent access date: 2022-08-01 12:10:10 +0000"#;
+        let deadline = Instant::now() + timeout;
+        loop {
+            if ConnectionState::from_connected(self.get_device_info(address)?.connected) == state {
+                return Ok(());
+            }
 
-        let valid_device_not_connected = DeviceInfo::from_raw_str(valid_str_not_connected);
-        assert_eq!(valid_device_not_connected.name, "AirPods Pro");
-        assert_eq!(valid_device_not_connected.address, "5c-2e-fg-da-a3-43");
-        assert_eq!(valid_device_not_connected.connected, false);
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(state_not_verified(address, state));
+            }
 
-        let valid_device_connected = DeviceInfo::from_raw_str(valid_str_connected);
-        assert_eq!(valid_device_connected.name, "AirPods Max");
-        assert_eq!(valid_device_connected.address, "80-3b-5c-c2-b1-7f");
-        assert_eq!(valid_device_connected.connected, true);
+            thread::sleep(VERIFY_POLL_INTERVAL.min(deadline - now));
+        }
     }
 
-    #[test]
-    #[should_panic]
-    fn device_info_panics_for_invalid_str() {
-        let invalid_str = "address: 5c-2e-fg-da-a3-43";
-        DeviceInfo::from_raw_str(invalid_str);
+    // Devices sometimes ignore a disconnect, e.g. while they're being handed off
+    // from another Apple device, so it's tried once more before failing.
+    fn verify_disconnected(
+        &self,
+        address: &str,
+        options: &ConnectOptions,
+    ) -> Result<(), BluetoothError> {
+        match self.verify_state(address, ConnectionState::Disconnected, options) {
+            Err(err) if err.kind() == ErrorKind::DisconnectFailed => {
+                warn!("{}, trying again", err);
+                self.blueutil_client.disconnect_from_device(address)?;
+                self.verify_state(address, ConnectionState::Disconnected, options)
+            }
+            result => result,
+        }
     }
 
-    #[test]
-    fn dev_device_list_options_constructor() {
-        let result = DeviceListOptions::new(DeviceFilters::AllDevices, Some(String::from("1234")));
+    // Even failed changes may have changed something, so they invalidate too.
+    fn invalidate_cache(&self) {
+        if let Some(cache) = &self.device_list_cache {
+            cache.invalidate();
+        }
+    }
 
-        assert_eq!(result.filters, DeviceFilters::AllDevices);
-        assert_eq!(result.previous_address, Some(String::from("1234")))
+    // Failing to record history shouldn't fail the connect or disconnect itself.
+    fn record_history(
+        &self,
+        action: HistoryAction,
+        address: &str,
+        result: &Result<(), BluetoothError>,
+    ) {
+        if let Some(history) = &self.history {
+            let entry = HistoryEntry::new(Utc::now(), action, address, result);
+            if let Err(err) = history.record(&entry) {
+                warn!("Failed to record history : {}", err);
+            }
+        }
     }
 
-    #[test]
-    fn dev_device_list_default_all_devices() {
-        let result = DeviceListOptions::new_default_all_devices();
+    pub fn pair_device(&self, address: &str, pin: Option<String>) -> Result<(), BluetoothError> {
+        self.capabilities().require(Capability::Pair)?;
+        let result = self.blueutil_client.pair_device(address, pin);
+        self.invalidate_cache();
 
-        assert_eq!(result.filters, DeviceFilters::AllDevices);
-        assert_eq!(result.previous_address, None);
+        result
     }
 
-    #[test]
-    fn bluetooth_client_print_devices_retrieves_device_list() {
-        let mut mock = MockBlueutilClient::default();
-        mock.expect_get_device_list().times(1).returning(|| vec![]);
-
-        let client = BluetoothClient {
-            blueutil_client: Box::new(mock),
-        };
+    // Nearby devices that aren't paired yet, e.g. AirPods in pairing mode. Blocks
+    // for the duration while the inquiry runs.
+    pub fn discover(&self, duration: Duration) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        self.capabilities().require(Capability::Discover)?;
 
-        client.print_devices();
+        Ok(self
+            .blueutil_client
+            .discover(duration)?
+            .into_iter()
+            .filter(|x| !x.paired)
+            .collect())
     }
 
-    #[test]
-    fn bluetooth_client_connect_to_device() {
-        let mut mock = MockBlueutilClient::default();
-        mock.expect_connect_to_device()
-            .times(1)
-            .with(predicate::eq("address"))
-            .returning(|_| Ok(()));
+    pub fn unpair_device(&self, address: &str) -> Result<(), BluetoothError> {
+        self.capabilities().require(Capability::Pair)?;
+        let result = self.blueutil_client.unpair_device(address);
+        self.invalidate_cache();
 
-        let client = BluetoothClient {
-            blueutil_client: Box::new(mock),
-        };
+        result
+    }
 
-        client.connect_to_device("address").unwrap();
+    pub fn set_favourite(&self, address: &str, favourite: bool) -> Result<(), BluetoothError> {
+        self.capabilities().require(Capability::Favourite)?;
+        let result = self.blueutil_client.set_favourite(address, favourite);
+        self.invalidate_cache();
+
+        result
     }
 
-    #[test]
-    fn bluetooth_client_disconnect_from_device() {
-        let mut mock = MockBlueutilClient::default();
-        mock.expect_disconnect_from_device()
-            .times(1)
-            .with(predicate::eq("address"))
-            .returning(|_| Ok(()));
+    pub fn is_powered(&self) -> Result<bool, BluetoothError> {
+        self.blueutil_client.is_powered()
+    }
 
-        let client = BluetoothClient {
-            blueutil_client: Box::new(mock),
-        };
+    pub fn set_power(&self, on: bool) -> Result<(), BluetoothError> {
+        self.capabilities().require(Capability::SetPower)?;
+        let result = self.blueutil_client.set_power(on);
+        self.invalidate_cache();
 
-        client.disconnect_from_device("address").unwrap();
+        result
     }
 
-    #[test]
-    fn bluetooth_client_toggle_connected_status_disconnects_a_connected_device() {
-        let mut mock = MockBlueutilClient::default();
-        mock_blueutil_client_device_list(&mut mock);
+    // Battery levels from ioreg, falling back to what the backend reports for
+    // devices ioreg doesn't know, like on other platforms.
+    pub fn get_battery(&self, address: &str) -> Option<BatteryInfo> {
+        let mut levels = battery::battery_levels().unwrap_or_else(|err| {
+            warn!("Failed to read battery levels : {}", err);
+            Default::default()
+        });
 
-        mock.expect_connect_to_device()
-            .times(1)
-            .returning(|_| Ok(()));
-        mock.expect_disconnect_from_device()
-            .times(0)
-            .returning(|_| Ok(()));
+        levels.remove(&Address::new(address)).or_else(|| {
+            let device = self.blueutil_client.get_device(address)?;
+            match device.battery_levels {
+                Some(levels) => Some(levels),
+                None => Some(BatteryInfo {
+                    combined: Some(device.battery?),
+                    ..Default::default()
+                }),
+            }
+        })
+    }
 
-        let client = BluetoothClient {
-            blueutil_client: Box::new(mock),
-        };
+    // Turns the radio on when it's off, returning whether it had to.
+    pub fn ensure_powered(&self) -> Result<bool, BluetoothError> {
+        if self.is_powered()? {
+            return Ok(false);
+        }
 
-        client
-            .toggle_connected_status("disconnected-address")
-            .unwrap();
+        self.set_power(true)?;
+        Ok(true)
     }
 
-    #[test]
-    fn bluetooth_client_toggle_connected_status_connects_a_disconnected_device() {
-        let mut mock = MockBlueutilClient::default();
-        mock_blueutil_client_device_list(&mut mock);
+    // Returns whether the radio is powered on afterwards.
+    pub fn toggle_power(&self) -> Result<bool, BluetoothError> {
+        let on = !self.is_powered()?;
+        self.set_power(on)?;
 
-        mock.expect_connect_to_device()
-            .times(0)
+        Ok(on)
+    }
+
+    // bool indicates that the device was connected to.
+    pub fn toggle_connected_status(&self, address: &str) -> Result<bool, BluetoothError> {
+        let device = self.get_device_info(address)?;
+
+        if device.connected {
+            self.disconnect_from_device(address)?;
+            Ok(false)
+        } else {
+            self.connect_to_device(address)?;
+            Ok(true)
+        }
+    }
+
+    // Toggle that verifies the change, retrying connecting according to the
+    // policy. When `ensure_connected` is set an already connected device is left
+    // connected.
+    pub fn toggle_with_retries(
+        &self,
+        address: &str,
+        ensure_connected: bool,
+        retry_policy: &RetryPolicy,
+        timeout: Duration,
+    ) -> Result<bool, BluetoothError> {
+        // Otherwise it would be reported as a failed connect
+        self.capabilities().require(Capability::Connect)?;
+        let device = self.get_device_info(address)?;
+
+        if device.connected {
+            if !ensure_connected {
+                self.disconnect_and_verify(address, timeout)?;
+            }
+
+            return Ok(ensure_connected);
+        }
+
+        match self
+            .connect_with_stats(address, retry_policy, timeout)
+            .error
+        {
+            Some(err) => Err(BluetoothError::with_kind(ErrorKind::ConnectFailed, &err)),
+            None => Ok(true),
+        }
+    }
+
+    // Disconnects whichever of the two devices is connected and connects the
+    // other, connecting `a` when neither is connected. Returns the address that
+    // was connected.
+    pub fn switch_devices(&self, a: &str, b: &str) -> Result<String, BluetoothError> {
+        let (from, to) = if self.get_device_info(a)?.connected {
+            (Some(a), b)
+        } else if self.get_device_info(b)?.connected {
+            (Some(b), a)
+        } else {
+            (None, a)
+        };
+
+        if let Some(from) = from {
+            self.disconnect_from_device(from)?;
+        }
+        self.connect_to_device(to)?;
+
+        Ok(to.to_string())
+    }
+
+    // Like toggle, but leaves an already connected device connected. Always
+    // returns true since the device is connected afterwards.
+    pub fn ensure_connected(&self, address: &str) -> Result<bool, BluetoothError> {
+        let device = self.get_device_info(address)?;
+
+        if !device.connected {
+            self.connect_to_device(address)?;
+        }
+
+        Ok(true)
+    }
+
+    // Errors when the device list couldn't be retrieved, as opposed to returning an
+    // empty list when there are no matching devices.
+    pub fn get_device_list(
+        &self,
+        options: DeviceListOptions,
+    ) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        let devices = self.fetch_device_list()?;
+
+        self.prepare_device_list(devices, options)
+    }
+
+    // Like get_device_list, but lists the devices with the fallback backend when
+    // the main one fails. The main backend's error comes back with the devices
+    // so callers can say the list is degraded. When the fallback fails too the
+    // main backend's error is returned, as it's the one worth fixing.
+    pub fn get_device_list_or_fallback(
+        &self,
+        options: DeviceListOptions,
+    ) -> Result<(Vec<DeviceInfo>, Option<BluetoothError>), BluetoothError> {
+        let err = match self.fetch_device_list() {
+            Ok(devices) => return Ok((self.prepare_device_list(devices, options)?, None)),
+            Err(err) => err,
+        };
+        let fallback_client = match &self.fallback_client {
+            Some(fallback_client) => fallback_client,
+            None => return Err(err),
+        };
+
+        let fallback_backend = fallback_client.capabilities().backend.as_str();
+        match fallback_client.get_device_list() {
+            Ok(devices) => {
+                warn!(
+                    "Listing devices with {} instead : {}",
+                    fallback_backend, err
+                );
+                // Recent dates and details would come from the failing backend
+                Ok((self.filter_and_sort(devices, options)?, Some(err)))
+            }
+            Err(fallback_err) => {
+                warn!(
+                    "Failed to list devices with {} too : {}",
+                    fallback_backend, fallback_err
+                );
+                Err(err)
+            }
+        }
+    }
+
+    fn fetch_device_list(&self) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        match &self.device_list_cache {
+            Some(cache) => cache.get_or_fetch(|| self.blueutil_client.get_device_list()),
+            None => self.blueutil_client.get_device_list(),
+        }
+    }
+
+    fn prepare_device_list(
+        &self,
+        mut devices: Vec<DeviceInfo>,
+        options: DeviceListOptions,
+    ) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        let details = options.details;
+
+        if options.sort_order.contains(&SortKey::Recent) {
+            self.add_recent_access_dates(&mut devices);
+        }
+
+        let mut devices = self.filter_and_sort(devices, options)?;
+        if details {
+            self.add_details(&mut devices);
+        }
+
+        Ok(devices)
+    }
+
+    // Fills in when devices were last used from the recent list, for backends or
+    // blueutil versions that leave it out of the paired list.
+    fn add_recent_access_dates(&self, devices: &mut [DeviceInfo]) {
+        if devices.iter().all(|x| x.last_used.is_some())
+            || !self.capabilities().supports(Capability::RecentDevices)
+        {
+            return;
+        }
+
+        let recent = match self.blueutil_client.get_recent_devices() {
+            Ok(recent) => recent,
+            Err(err) => {
+                warn!("Failed to list recent devices : {}", err);
+                return;
+            }
+        };
+        let last_used = recent
+            .into_iter()
+            .filter_map(|x| Some((Address::new(&x.address), x.last_used?)))
+            .collect::<HashMap<Address, DateTime<Utc>>>();
+
+        for device in devices.iter_mut().filter(|x| x.last_used.is_none()) {
+            device.last_used = last_used.get(&Address::new(&device.address)).copied();
+        }
+    }
+
+    // Devices that can't be queried keep what the list had for them.
+    fn add_details(&self, devices: &mut [DeviceInfo]) {
+        let mut connected = devices
+            .iter_mut()
+            .filter(|x| x.connected)
+            .collect::<Vec<&mut DeviceInfo>>();
+        let addresses = connected
+            .iter()
+            .map(|x| x.address.clone())
+            .collect::<Vec<String>>();
+
+        for (device, details) in connected
+            .iter_mut()
+            .zip(self.blueutil_client.get_devices(&addresses))
+        {
+            match details {
+                Some(details) => {
+                    device.battery = details.battery.or(device.battery);
+                    device.rssi = details.rssi.or(device.rssi);
+                }
+                None => warn!("Failed to get details for '{}'", device.address),
+            }
+        }
+
+        if connected.is_empty() {
+            return;
+        }
+
+        let mut levels = battery::battery_levels().unwrap_or_else(|err| {
+            warn!("Failed to read battery levels : {}", err);
+            Default::default()
+        });
+        for device in connected {
+            if let Some(levels) = levels.remove(&Address::new(&device.address)) {
+                device.battery = levels.combined.or(device.battery);
+                device.battery_levels = Some(levels);
+            }
+        }
+    }
+
+    fn filter_and_sort(
+        &self,
+        devices: Vec<DeviceInfo>,
+        options: DeviceListOptions,
+    ) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        let mut devices = self.get_filtered_devices(devices, options.filters)?;
+
+        devices.sort_by(|a, b| {
+            compare_devices(
+                a,
+                b,
+                &options.sort_order,
+                options.previous_address.as_deref(),
+            )
+        });
+
+        Ok(devices)
+    }
+
+    // Connects the devices CONNECT_POOL_SIZE at a time where the backend can
+    // connect side by side, returning the results in the order of the addresses.
+    // A failure to connect one device doesn't stop the others from being connected.
+    pub fn connect_many(&self, addresses: &[String]) -> Vec<ConnectResult> {
+        let results = match self.capabilities().require(Capability::Connect) {
+            Ok(_) => addresses
+                .chunks(CONNECT_POOL_SIZE)
+                .flat_map(|x| self.blueutil_client.connect_to_devices(x))
+                .collect(),
+            Err(_) => addresses
+                .iter()
+                .map(|_| Err(Capability::Connect.unsupported(self.capabilities().backend)))
+                .collect::<Vec<_>>(),
+        };
+        self.invalidate_cache();
+
+        addresses
+            .iter()
+            .cloned()
+            .zip(results)
+            .inspect(|(address, result)| {
+                self.record_history(HistoryAction::Connect, address, result)
+            })
+            .collect()
+    }
+
+    // Disconnects every connected device matching the filters. A failure to
+    // disconnect one device doesn't stop the others from being disconnected.
+    pub fn disconnect_all(
+        &self,
+        filters: DeviceFilters,
+    ) -> Result<Vec<DisconnectResult>, BluetoothError> {
+        let devices = self.get_device_list(DeviceListOptions::new(filters, None))?;
+
+        Ok(devices
+            .into_iter()
+            .filter(|x| x.connected)
+            .map(|device| {
+                let error = self
+                    .disconnect_from_device(&device.address)
+                    .err()
+                    .map(|err| err.to_string());
+
+                DisconnectResult { device, error }
+            })
+            .collect())
+    }
+
+    // Recently used devices matching the filters, kept in blueutil's most recent
+    // first order rather than being sorted.
+    pub fn get_recent_device_list(
+        &self,
+        filters: DeviceFilters,
+    ) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        self.capabilities().require(Capability::RecentDevices)?;
+
+        let devices = self.blueutil_client.get_recent_devices()?;
+
+        self.get_filtered_devices(devices, filters)
+    }
+
+    // Connects the most recently used device matching the filters, returning it.
+    pub fn connect_most_recent(
+        &self,
+        filters: DeviceFilters,
+    ) -> Result<DeviceInfo, BluetoothError> {
+        let devices = self.get_device_list(DeviceListOptions::new(filters, None))?;
+
+        let device = devices
+            .into_iter()
+            .filter(|x| x.last_used.is_some())
+            .max_by_key(|x| x.last_used)
+            .ok_or_else(|| BluetoothError::new("No recently used devices found"))?;
+
+        self.connect_to_device(&device.address)?;
+
+        Ok(device)
+    }
+
+    pub fn print_devices(&self) -> Result<(), BluetoothError> {
+        let parsed_devices = self.get_device_list(DeviceListOptions::new_default_all_devices())?;
+
+        for parsed_device in parsed_devices {
+            println!("{:#?}", parsed_device);
+        }
+
+        Ok(())
+    }
+
+    // A single --is-connected query, looking the device up when the backend
+    // can't answer it that way.
+    pub fn is_device_connected(&self, address: &str) -> Result<bool, BluetoothError> {
+        if let Some(connected) = self.blueutil_client.is_connected(address) {
+            return Ok(connected);
+        }
+
+        let device = self.get_device_info(address)?;
+
+        Ok(device.connected)
+    }
+
+    // Connects to the device and then polls until it reports as connected or the
+    // timeout elapses. A zero timeout checks exactly once.
+    pub fn connect_and_verify(
+        &self,
+        address: &str,
+        timeout: Duration,
+    ) -> Result<(), BluetoothError> {
+        self.connect_with_options(address, &ConnectOptions::default().with_verify(timeout))
+    }
+
+    // Disconnects from the device and then polls until it reports as
+    // disconnected, disconnecting once more if it doesn't in time.
+    pub fn disconnect_and_verify(
+        &self,
+        address: &str,
+        timeout: Duration,
+    ) -> Result<(), BluetoothError> {
+        self.disconnect_with_options(address, &ConnectOptions::default().with_verify(timeout))
+    }
+
+    // Makes verified connection attempts until one succeeds or the retry policy
+    // is exhausted, recording how it went.
+    pub fn connect_with_stats(
+        &self,
+        address: &str,
+        retry_policy: &RetryPolicy,
+        timeout: Duration,
+    ) -> ConnectStats {
+        let start = Instant::now();
+        let mut attempts = 0;
+        let mut error = None;
+
+        while attempts < retry_policy.max_attempts.max(1) {
+            if attempts > 0 {
+                thread::sleep(retry_policy.delay_before_retry(attempts));
+            }
+
+            attempts += 1;
+
+            match self.connect_and_verify(address, timeout) {
+                Ok(_) => {
+                    error = None;
+                    break;
+                }
+                Err(err) => {
+                    warn!(
+                        "Connect attempt {} to {} failed : {}",
+                        attempts, address, err
+                    );
+                    error = Some(err.to_string());
+                }
+            }
+        }
+
+        let succeeded = error.is_none();
+        let final_state = if succeeded {
+            Some(ConnectionState::Connected)
+        } else {
+            self.is_device_connected(address)
+                .ok()
+                .map(ConnectionState::from_connected)
+        };
+
+        ConnectStats {
+            attempts,
+            succeeded,
+            total_ms: start.elapsed().as_millis(),
+            final_state,
+            error,
+        }
+    }
+
+    // Checks a held connection, reconnecting if the device has dropped.
+    pub fn keepalive_check(
+        &self,
+        address: &str,
+        timeout: Duration,
+    ) -> Result<KeepaliveStatus, BluetoothError> {
+        if self.is_device_connected(address)? {
+            Ok(KeepaliveStatus::StillConnected)
+        } else {
+            self.connect_and_verify(address, timeout)?;
+            Ok(KeepaliveStatus::Reconnected)
+        }
+    }
+
+    // Connects and then re-checks the connection every interval, reconnecting as
+    // needed. Runs until the process is interrupted.
+    pub fn keepalive(
+        &self,
+        address: &str,
+        interval: Duration,
+        timeout: Duration,
+    ) -> Result<(), BluetoothError> {
+        self.connect_and_verify(address, timeout)?;
+
+        loop {
+            thread::sleep(interval);
+
+            match self.keepalive_check(address, timeout) {
+                Ok(KeepaliveStatus::StillConnected) => trace!("{} is still connected", address),
+                Ok(KeepaliveStatus::Reconnected) => warn!("Reconnected to {}", address),
+                Err(err) => warn!("Failed to keep {} connected : {}", address, err),
+            }
+        }
+    }
+
+    fn get_filtered_devices(
+        &self,
+        devices: Vec<DeviceInfo>,
+        filters: DeviceFilters,
+    ) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        let matcher = filters.matcher()?;
+
+        Ok(devices.into_iter().filter(|x| matcher(x)).collect())
+    }
+
+    // Looks up a device by MAC address or name, see resolve_device_id.
+    pub fn get_device(&self, address_or_name: &str) -> Result<DeviceInfo, BluetoothError> {
+        let address = self.resolve_device_id(address_or_name)?;
+
+        self.get_device_info(&address)
+    }
+
+    // A single --info query, so looking up one device doesn't list every paired
+    // device. The paired list is only used when --info can't find the device.
+    fn get_device_info(&self, address: &str) -> Result<DeviceInfo, BluetoothError> {
+        if let Some(device) = self.blueutil_client.get_device(address) {
+            return Ok(device);
+        }
+
+        let device_list_options = DeviceListOptions::new(
+            DeviceFilters::SpecificAddresses {
+                addresses: vec![address.to_string()],
+            },
+            None,
+        );
+        let listed_device = self
+            .get_device_list(device_list_options)?
+            .into_iter()
+            .find(|x| Address::new(&x.address) == Address::new(address));
+
+        match listed_device {
+            Some(device) => {
+                warn!("{} is in --paired but wasn't found by --info", address);
+                Ok(device)
+            }
+            None => Err(BluetoothError::with_kind(
+                ErrorKind::DeviceNotFound,
+                &format!("Could not find device id : '{}'", address),
+            )),
+        }
+    }
+
+    // Resolves a device id that may be a MAC address or a device name to an
+    // address. MAC addresses are returned as is without querying blueutil. Names
+    // are matched exactly first and then case-insensitively, erroring if more
+    // than one device matches.
+    pub fn resolve_device_id(&self, device_id: &str) -> Result<String, BluetoothError> {
+        let address = Address::new(device_id);
+        if address.is_mac() {
+            return Ok(device_id.to_string());
+        }
+
+        let devices = self.get_device_list(DeviceListOptions::new_default_all_devices())?;
+
+        if let Some(device) = devices.iter().find(|x| Address::new(&x.address) == address) {
+            return Ok(device.address.clone());
+        }
+
+        let exact_matches = devices
+            .iter()
+            .filter(|x| x.name == device_id)
+            .collect::<Vec<&DeviceInfo>>();
+        let matches = if exact_matches.is_empty() {
+            devices
+                .iter()
+                .filter(|x| x.name.to_lowercase() == device_id.to_lowercase())
+                .collect::<Vec<&DeviceInfo>>()
+        } else {
+            exact_matches
+        };
+
+        match matches.as_slice() {
+            // Not a name, so the address was mistyped
+            [] if Address::looks_like_mac(device_id) => Err(address::invalid_address(device_id)),
+            [] => Err(BluetoothError::with_kind(
+                ErrorKind::DeviceNotFound,
+                &format!("Could not find device id : '{}'", device_id),
+            )),
+            [device] => Ok(device.address.clone()),
+            _ => Err(BluetoothError::new(&format!(
+                "'{}' matches multiple devices, use an address instead : {}",
+                device_id,
+                matches
+                    .iter()
+                    .map(|x| x.address.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            ))),
+        }
+    }
+}
+
+// Compares devices by each sort key in turn until one of them differs.
+fn compare_devices(
+    a: &DeviceInfo,
+    b: &DeviceInfo,
+    sort_order: &[SortKey],
+    previous_address: Option<&str>,
+) -> Ordering {
+    let is_previous = |device: &DeviceInfo| {
+        previous_address.is_some_and(|x| Address::new(&device.address) == Address::new(x))
+    };
+
+    sort_order
+        .iter()
+        .map(|key| match key {
+            SortKey::Previous => is_previous(b).cmp(&is_previous(a)),
+            SortKey::Connected => b.connected.cmp(&a.connected),
+            SortKey::Favourite => b.favourite.cmp(&a.favourite),
+            SortKey::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            SortKey::Recent => b.last_used.cmp(&a.last_used),
+        })
+        .find(|x| *x != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+// A Bluetooth backend. Implement it to plug another backend into
+// BluetoothClient::with_client. Backends are shared across threads, e.g. by the
+// daemon, so they need to be Send and Sync.
+pub trait Client: Send + Sync {
+    fn connect_to_device(&self, address: &str) -> Result<(), BluetoothError>;
+    fn disconnect_from_device(&self, address: &str) -> Result<(), BluetoothError>;
+    fn get_device_list(&self) -> Result<Vec<DeviceInfo>, BluetoothError>;
+    // Recently used devices, most recent first.
+    fn get_recent_devices(&self) -> Result<Vec<DeviceInfo>, BluetoothError>;
+    // Looks up a single device, returning None if it can't be found.
+    fn get_device(&self, address: &str) -> Option<DeviceInfo>;
+    // The unparsed paired list, for backends that read a command's output.
+    fn raw_device_list(&self) -> Result<RawDeviceList, BluetoothError> {
+        Err(BluetoothError::with_kind(
+            ErrorKind::Unsupported,
+            &format!(
+                "The {} backend has no raw output to show",
+                self.capabilities().backend.as_str()
+            ),
+        ))
+    }
+    // Whether the device is connected, None when the backend can't tell without
+    // looking the device up. Backends with a quicker check override this.
+    fn is_connected(&self, _address: &str) -> Option<bool> {
+        None
+    }
+    // Connects several devices, returning the results in the order of the
+    // addresses. Backends that can connect side by side override this.
+    fn connect_to_devices(&self, addresses: &[String]) -> Vec<Result<(), BluetoothError>> {
+        addresses
+            .iter()
+            .map(|x| self.connect_to_device(x))
+            .collect()
+    }
+    // Looks up several devices, in the order of the addresses. Backends that can
+    // query devices side by side override this.
+    fn get_devices(&self, addresses: &[String]) -> Vec<Option<DeviceInfo>> {
+        addresses.iter().map(|x| self.get_device(x)).collect()
+    }
+    // Devices in range, paired or not, found by an inquiry lasting the duration.
+    fn discover(&self, _duration: Duration) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        Err(Capability::Discover.unsupported(self.capabilities().backend))
+    }
+    // Pairs with a device, using the PIN if the device asks for one.
+    fn pair_device(&self, address: &str, pin: Option<String>) -> Result<(), BluetoothError>;
+    // Removes the device from the paired list.
+    fn unpair_device(&self, address: &str) -> Result<(), BluetoothError>;
+    // Adds the device to, or removes it from, the favourites.
+    fn set_favourite(&self, address: &str, favourite: bool) -> Result<(), BluetoothError>;
+    // Whether the Bluetooth radio is powered on.
+    fn is_powered(&self) -> Result<bool, BluetoothError>;
+    fn set_power(&self, on: bool) -> Result<(), BluetoothError>;
+    // Blocks until the device reaches the state, failing with ErrorKind::Timeout
+    // once the timeout passes. Backends that can't wait on a change poll for it.
+    fn wait_for_state(
+        &self,
+        address: &str,
+        state: ConnectionState,
+        timeout: Duration,
+    ) -> Result<(), BluetoothError> {
+        poll_for_state(self, address, state, timeout)
+    }
+    // Everything blueutil can do, backends that can do less override this.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::all(Backend::Blueutil)
+    }
+}
+
+fn poll_for_state<C: Client + ?Sized>(
+    client: &C,
+    address: &str,
+    state: ConnectionState,
+    timeout: Duration,
+) -> Result<(), BluetoothError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let device = client.get_device(address).ok_or_else(|| {
+            BluetoothError::with_kind(
+                ErrorKind::DeviceNotFound,
+                &format!("Device '{}' not found", address),
+            )
+        })?;
+        if ConnectionState::from_connected(device.connected) == state {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(did_not_reach_state(address, state));
+        }
+
+        thread::sleep(VERIFY_POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+// The change was made but the device never reported being in the state.
+fn state_not_verified(address: &str, state: ConnectionState) -> BluetoothError {
+    match state {
+        ConnectionState::Connected => BluetoothError::with_kind(
+            ErrorKind::ConnectFailed,
+            &format!("Device '{}' did not connect", address),
+        ),
+        ConnectionState::Disconnected => BluetoothError::with_kind(
+            ErrorKind::DisconnectFailed,
+            &format!("Device '{}' did not disconnect", address),
+        ),
+    }
+}
+
+fn did_not_reach_state(address: &str, state: ConnectionState) -> BluetoothError {
+    BluetoothError::with_kind(
+        ErrorKind::Timeout,
+        &match state {
+            ConnectionState::Connected => format!("Device '{}' did not connect", address),
+            ConnectionState::Disconnected => format!("Device '{}' did not disconnect", address),
+        },
+    )
+}
+
+struct BlueutilClient {
+    command_runner: Box<dyn CommandRunner>,
+    blueutil_path: Option<String>,
+    // Detected on first use, None when it couldn't be read
+    version: OnceLock<Option<BlueutilVersion>>,
+}
+
+#[automock]
+impl Client for BlueutilClient {
+    fn connect_to_device(&self, address: &str) -> Result<(), BluetoothError> {
+        let output = self.run_command(vec!["--connect", &address])?;
+
+        trace!("{:?}", &output.stdout);
+        trace!("{:?}", &output.stderr);
+
+        check_status(
+            &output,
+            ErrorKind::ConnectFailed,
+            &format!("Failed to connect to '{}'", address),
+        )
+    }
+
+    fn disconnect_from_device(&self, address: &str) -> Result<(), BluetoothError> {
+        let output = self.run_command(vec!["--disconnect", &address, "--info", &address])?;
+
+        trace!("{:?}", &output.stdout);
+        trace!("{:?}", &output.stderr);
+
+        check_status(
+            &output,
+            ErrorKind::DisconnectFailed,
+            &format!("Failed to disconnect from '{}'", address),
+        )
+    }
+
+    // blueutil waits on the change itself, exiting with an error on timeout. It
+    // only takes whole seconds, and is still killed after the command timeout.
+    fn wait_for_state(
+        &self,
+        address: &str,
+        state: ConnectionState,
+        timeout: Duration,
+    ) -> Result<(), BluetoothError> {
+        if !self.supports(BlueutilVersion::WAIT)? {
+            return poll_for_state(self, address, state, timeout);
+        }
+
+        let wait_arg = match state {
+            ConnectionState::Connected => "--wait-connect",
+            ConnectionState::Disconnected => "--wait-disconnect",
+        };
+        let seconds = timeout.as_secs_f64().ceil().max(1.0).to_string();
+        let output = self.run_command(vec![wait_arg, address, &seconds])?;
+
+        trace!("{:?}", &output.stdout);
+        trace!("{:?}", &output.stderr);
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(did_not_reach_state(address, state))
+        }
+    }
+
+    fn pair_device(&self, address: &str, pin: Option<String>) -> Result<(), BluetoothError> {
+        let mut args = vec!["--pair", address];
+        if let Some(pin) = &pin {
+            args.push(pin);
+        }
+
+        let output = self.run_command(args)?;
+
+        trace!("{:?}", &output.stdout);
+        trace!("{:?}", &output.stderr);
+
+        if output.status.success() {
+            return Ok(());
+        }
+
+        // The hint says more than blueutil's own message
+        let stderr = String::from_utf8_lossy(&output.stderr).to_lowercase();
+        if pin.is_none() && stderr.contains("pin") {
+            return Err(BluetoothError::new(&format!(
+                "Device '{}' requires a PIN to pair, pass it with --pin",
+                address
+            )));
+        }
+
+        check_status(
+            &output,
+            ErrorKind::Other,
+            &format!("Failed to pair with device '{}'", address),
+        )
+    }
+
+    fn unpair_device(&self, address: &str) -> Result<(), BluetoothError> {
+        let output = self.run_command(vec!["--unpair", address])?;
+
+        trace!("{:?}", &output.stdout);
+        trace!("{:?}", &output.stderr);
+
+        check_status(
+            &output,
+            ErrorKind::Other,
+            &format!("Failed to unpair device '{}'", address),
+        )
+    }
+
+    fn set_favourite(&self, address: &str, favourite: bool) -> Result<(), BluetoothError> {
+        let arg = if favourite {
+            "--add-favourite"
+        } else {
+            "--remove-favourite"
+        };
+        let output = self.run_command(vec![arg, address])?;
+
+        trace!("{:?}", &output.stdout);
+        trace!("{:?}", &output.stderr);
+
+        check_status(
+            &output,
+            ErrorKind::Other,
+            &format!("Failed to update favourites for device '{}'", address),
+        )
+    }
+
+    fn is_powered(&self) -> Result<bool, BluetoothError> {
+        let output = self.run_command(vec!["--power"])?;
+
+        check_status(
+            &output,
+            ErrorKind::Other,
+            "Failed to get the Bluetooth power state",
+        )?;
+
+        match decode_output(&output.stdout).trim() {
+            "1" => Ok(true),
+            "0" => Ok(false),
+            other => Err(BluetoothError::new(&format!(
+                "Unexpected Bluetooth power state : '{}'",
+                other
+            ))),
+        }
+    }
+
+    fn set_power(&self, on: bool) -> Result<(), BluetoothError> {
+        let output = self.run_command(vec!["--power", if on { "1" } else { "0" }])?;
+
+        trace!("{:?}", &output.stdout);
+        trace!("{:?}", &output.stderr);
+
+        check_status(
+            &output,
+            ErrorKind::Other,
+            "Failed to set the Bluetooth power state",
+        )
+    }
+
+    fn get_device_list(&self) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        self.list_devices(vec!["--paired"], "Failed to list paired devices")
+    }
+
+    fn get_recent_devices(&self) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        self.list_devices(vec!["--recent"], "Failed to list recent devices")
+    }
+
+    fn discover(&self, duration: Duration) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        // blueutil takes whole seconds
+        let seconds = duration.as_secs_f64().ceil().max(1.0).to_string();
+
+        self.list_devices(
+            vec!["--inquiry", &seconds],
+            "Failed to discover nearby devices",
+        )
+    }
+
+    fn get_device(&self, address: &str) -> Option<DeviceInfo> {
+        let json = self.json_format().ok()?;
+
+        parse_device_info(
+            self.run_command(with_format(json, vec!["--info", address])),
+            json,
+        )
+    }
+
+    fn raw_device_list(&self) -> Result<RawDeviceList, BluetoothError> {
+        self.list_raw(vec!["--paired"], "Failed to list paired devices")
+    }
+
+    // Unknown devices make blueutil exit with an error, they're left to the
+    // fallback to report.
+    fn is_connected(&self, address: &str) -> Option<bool> {
+        let output = self.run_command(vec!["--is-connected", address]).ok()?;
+
+        if !output.status.success() {
+            trace!("{:?}", &output.stderr);
+            return None;
+        }
+
+        match decode_output(&output.stdout).trim() {
+            "1" => Some(true),
+            "0" => Some(false),
+            _ => None,
+        }
+    }
+
+    fn connect_to_devices(&self, addresses: &[String]) -> Vec<Result<(), BluetoothError>> {
+        self.run_commands(addresses.iter().map(|x| vec!["--connect", x]).collect())
+            .into_iter()
+            .zip(addresses)
+            .map(|(output, address)| {
+                check_status(
+                    &output?,
+                    ErrorKind::ConnectFailed,
+                    &format!("Failed to connect to '{}'", address),
+                )
+            })
+            .collect()
+    }
+
+    fn get_devices(&self, addresses: &[String]) -> Vec<Option<DeviceInfo>> {
+        let json = match self.json_format() {
+            Ok(json) => json,
+            Err(_) => return addresses.iter().map(|_| None).collect(),
+        };
+
+        self.run_commands(
+            addresses
+                .iter()
+                .map(|x| with_format(json, vec!["--info", x]))
+                .collect(),
+        )
+        .into_iter()
+        .map(|output| parse_device_info(output, json))
+        .collect()
+    }
+}
+
+impl BlueutilClient {
+    fn new(command_runner: Box<dyn CommandRunner>, blueutil_path: Option<String>) -> Self {
+        BlueutilClient {
+            command_runner,
+            blueutil_path,
+            version: OnceLock::new(),
+        }
+    }
+
+    // The installed version, read once per client.
+    fn version(&self) -> Result<Option<BlueutilVersion>, BluetoothError> {
+        let version = match self.version.get() {
+            Some(version) => *version,
+            None => {
+                let output = self.run_command(vec!["--version"])?;
+
+                *self.version.get_or_init(|| read_version(&output))
+            }
+        };
+
+        require_minimum_version(version)
+    }
+
+    fn supports(&self, feature: BlueutilVersion) -> Result<bool, BluetoothError> {
+        Ok(self.version()?.is_none_or(|x| x >= feature))
+    }
+
+    fn json_format(&self) -> Result<bool, BluetoothError> {
+        self.supports(BlueutilVersion::JSON_FORMAT)
+    }
+
+    // Runs a blueutil listing command and parses every device in its output.
+    fn list_devices(
+        &self,
+        args: Vec<&str>,
+        error: &str,
+    ) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        let listed = self.list_raw(args, error)?;
+        listed.diagnostics.log();
+
+        Ok(listed.devices)
+    }
+
+    fn list_raw(&self, args: Vec<&str>, error: &str) -> Result<RawDeviceList, BluetoothError> {
+        let json = self.json_format()?;
+        let output = self.run_command(with_format(json, args))?;
+
+        check_status(&output, ErrorKind::Other, error)?;
+
+        let output = decode_output(&output.stdout).into_owned();
+        let (devices, diagnostics) = parse_devices(json, &output)?;
+
+        Ok(RawDeviceList {
+            output,
+            devices,
+            diagnostics,
+        })
+    }
+
+    fn run_command(&self, args: Vec<&str>) -> Result<std::process::Output, BluetoothError> {
+        validate_device_ids(&args)?;
+        let blueutil_path = self.get_blueutil_path();
+
+        self.command_runner
+            .run_command(
+                &blueutil_path,
+                args.into_iter().map(|x| x.to_string()).collect(),
+            )
+            .map_err(|err| spawn_error(&blueutil_path, err))
+    }
+
+    fn run_commands(
+        &self,
+        args: Vec<Vec<&str>>,
+    ) -> Vec<Result<std::process::Output, BluetoothError>> {
+        let blueutil_path = self.get_blueutil_path();
+        let checked = args
+            .iter()
+            .map(|x| validate_device_ids(x))
+            .collect::<Vec<Result<(), BluetoothError>>>();
+
+        // Only the commands with valid device ids are run
+        let mut outputs = self
+            .command_runner
+            .run_commands(
+                &blueutil_path,
+                args.into_iter()
+                    .zip(&checked)
+                    .filter(|(_, checked)| checked.is_ok())
+                    .map(|(x, _)| x.into_iter().map(String::from).collect())
+                    .collect(),
+            )
+            .into_iter();
+
+        checked
+            .into_iter()
+            .map(|checked| {
+                checked?;
+
+                match outputs.next() {
+                    Some(result) => result.map_err(|err| spawn_error(&blueutil_path, err)),
+                    None => Err(BluetoothError::new("blueutil wasn't run")),
+                }
+            })
+            .collect()
+    }
+
+    fn get_blueutil_path(&self) -> String {
+        blueutil_path(self.blueutil_path.as_deref())
+    }
+}
+
+// The version `blueutil --version` printed. Versions that can't be read are
+// assumed to be recent rather than failing every command.
+fn read_version(output: &std::process::Output) -> Option<BlueutilVersion> {
+    let version = str::from_utf8(&output.stdout)
+        .ok()
+        .filter(|_| output.status.success())
+        .and_then(BlueutilVersion::parse);
+    if version.is_none() {
+        warn!("Failed to read the blueutil version, assuming it's recent");
+    }
+
+    version
+}
+
+fn require_minimum_version(
+    version: Option<BlueutilVersion>,
+) -> Result<Option<BlueutilVersion>, BluetoothError> {
+    match version {
+        Some(version) if version < BlueutilVersion::MINIMUM => {
+            Err(BluetoothError::BackendUnavailable(format!(
+                "blueutil {} is too old, {} or newer is needed, upgrade it with `brew upgrade blueutil`",
+                version,
+                BlueutilVersion::MINIMUM
+            )))
+        }
+        version => Ok(version),
+    }
+}
+
+// Spawning fails with NotFound when blueutil isn't installed.
+fn spawn_error(blueutil_path: &str, err: Box<dyn Error>) -> BluetoothError {
+    match err.downcast_ref::<io::Error>() {
+        Some(io_err) if io_err.kind() == io::ErrorKind::NotFound => blueutil_missing(blueutil_path),
+        _ => BluetoothError::from(err),
+    }
+}
+
+// Asks for JSON output when the installed blueutil has it.
+fn with_format(json: bool, args: Vec<&str>) -> Vec<&str> {
+    if json {
+        [vec!["--format", "json"], args].concat()
+    } else {
+        args
+    }
+}
+
+fn parse_devices(
+    json: bool,
+    output: &str,
+) -> Result<(Vec<DeviceInfo>, ParseDiagnostics), BluetoothError> {
+    if json {
+        Ok(parse_device_list(output)?)
+    } else {
+        Ok(parse_default_device_list(output))
+    }
+}
+
+// The device in `blueutil --info` output, None when the command failed.
+fn parse_device_info(
+    output: Result<std::process::Output, BluetoothError>,
+    json: bool,
+) -> Option<DeviceInfo> {
+    let output = output.ok()?;
+
+    if !output.status.success() {
+        trace!("{:?}", &output.stderr);
+        return None;
+    }
+
+    let results = decode_output(&output.stdout);
+
+    let (mut devices, diagnostics) = match parse_devices(json, &results) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            warn!("Failed to parse blueutil output : {}", err);
+            return None;
+        }
+    };
+    diagnostics.log();
+
+    if devices.is_empty() {
+        None
+    } else {
+        Some(devices.remove(0))
+    }
+}
+
+fn blueutil_missing(blueutil_path: &str) -> BluetoothError {
+    let location = if blueutil_path == "blueutil" {
+        String::new()
+    } else {
+        format!(" at '{}'", blueutil_path)
+    };
+
+    BluetoothError::with_kind(
+        ErrorKind::BlueutilMissing,
+        &format!(
+            "blueutil not found{}, install it with `brew install blueutil` or set BLUEUTIL_PATH",
+            location
+        ),
+    )
+}
+
+// Fails with the kind when the command didn't exit cleanly, keeping its exit
+// status and whatever it wrote to stderr.
+fn check_status(
+    output: &std::process::Output,
+    kind: ErrorKind,
+    message: &str,
+) -> Result<(), BluetoothError> {
+    if output.status.success() {
+        return Ok(());
+    }
+
+    Err(BluetoothError::CommandFailed {
+        kind,
+        message: message.to_string(),
+        status: output.status.code(),
+        stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+    })
+}
+
+// Decodes a command's output, replacing bytes that aren't UTF-8 instead of
+// failing, e.g. for device names in another encoding.
+fn decode_output(output: &[u8]) -> Cow<'_, str> {
+    let decoded = String::from_utf8_lossy(output);
+    if let Cow::Owned(_) = decoded {
+        warn!("Command output isn't valid UTF-8, unreadable characters were replaced");
+    }
+
+    decoded
+}
+
+// BLUEUTIL_PATH wins over the configured path, which wins over the PATH lookup.
+fn blueutil_path(configured: Option<&str>) -> String {
+    match std::env::var("BLUEUTIL_PATH") {
+        Ok(val) => format!("{}/blueutil", val),
+        Err(_) => configured.unwrap_or("blueutil").to_string(),
+    }
+}
+
+//
+#[automock]
+trait CommandRunner: Send + Sync {
+    fn run_command(
+        &self,
+        command: &str,
+        args: Vec<String>,
+    ) -> Result<std::process::Output, Box<dyn Error>>;
+    // Runs the command once per set of arguments, returning the results in the
+    // same order. Runners that can run them side by side override this.
+    fn run_commands(
+        &self,
+        command: &str,
+        args: Vec<Vec<String>>,
+    ) -> Vec<Result<std::process::Output, Box<dyn Error>>> {
+        args.into_iter()
+            .map(|args| self.run_command(command, args))
+            .collect()
+    }
+}
+
+// Kills commands that run longer than the timeout, as blueutil can hang while
+// Bluetooth is resetting.
+struct DefaultCommandRunner {
+    timeout: Duration,
+}
+
+impl CommandRunner for DefaultCommandRunner {
+    fn run_command(
+        &self,
+        command: &str,
+        args: Vec<String>,
+    ) -> Result<std::process::Output, Box<dyn Error>> {
+        self.spawn(command, args)?.wait(self.timeout)
+    }
+
+    // Starts every command before waiting on any of them, so they take about
+    // as long as the slowest one rather than all of them added up.
+    fn run_commands(
+        &self,
+        command: &str,
+        args: Vec<Vec<String>>,
+    ) -> Vec<Result<std::process::Output, Box<dyn Error>>> {
+        let running = args
+            .into_iter()
+            .map(|args| self.spawn(command, args))
+            .collect::<Vec<_>>();
+
+        running
+            .into_iter()
+            .map(|running| running?.wait(self.timeout))
+            .collect()
+    }
+}
+
+impl DefaultCommandRunner {
+    fn spawn(&self, command: &str, args: Vec<String>) -> Result<RunningCommand, Box<dyn Error>> {
+        let mut child = Command::new(command)
+            .args(&args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Read on other threads so a full pipe can't stall the command
+        let stdout = read_to_end_in_background(child.stdout.take());
+        let stderr = read_to_end_in_background(child.stderr.take());
+
+        Ok(RunningCommand {
+            child,
+            stdout,
+            stderr,
+            started: Instant::now(),
+            description: format!("{} {}", command, args.join(" ")),
+        })
+    }
+}
+
+struct RunningCommand {
+    child: std::process::Child,
+    stdout: thread::JoinHandle<Vec<u8>>,
+    stderr: thread::JoinHandle<Vec<u8>>,
+    started: Instant,
+    description: String,
+}
+
+impl RunningCommand {
+    // Kills the command once it's been running for longer than the timeout.
+    fn wait(mut self, timeout: Duration) -> Result<std::process::Output, Box<dyn Error>> {
+        let status = loop {
+            if let Some(status) = self.child.try_wait()? {
+                break status;
+            }
+
+            if self.started.elapsed() >= timeout {
+                self.child.kill()?;
+                self.child.wait()?;
+
+                return Err(Box::new(BluetoothError::with_kind(
+                    ErrorKind::Timeout,
+                    &format!(
+                        "{} didn't finish within {}s and was killed",
+                        self.description,
+                        timeout.as_secs_f64()
+                    ),
+                )));
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        Ok(std::process::Output {
+            status,
+            stdout: self.stdout.join().unwrap_or_default(),
+            stderr: self.stderr.join().unwrap_or_default(),
+        })
+    }
+}
+
+fn read_to_end_in_background<R: Read + Send + 'static>(
+    reader: Option<R>,
+) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = vec![];
+        if let Some(mut reader) = reader {
+            let _ = reader.read_to_end(&mut buffer);
+        }
+
+        buffer
+    })
+}
+
+// blueutil options that are followed by a device id
+const DEVICE_ID_ARGS: &[&str] = &[
+    "--connect",
+    "--disconnect",
+    "--info",
+    "--is-connected",
+    "--pair",
+    "--unpair",
+    "--add-favourite",
+    "--remove-favourite",
+    "--wait-connect",
+    "--wait-disconnect",
+];
+
+// Every command sent to blueutil is checked here, so a mistyped address or one
+// blueutil would read as an option never reaches it, whichever method sent it.
+fn validate_device_ids(args: &[&str]) -> Result<(), BluetoothError> {
+    for pair in args.windows(2) {
+        if DEVICE_ID_ARGS.contains(&pair[0]) {
+            Address::validate_device_id(pair[1])?;
+        }
+    }
+
+    Ok(())
+}
+
+// blueutil arguments that change state rather than querying it. Waits are
+// skipped too, as they'd wait on a change that was never made.
+const MUTATING_ARGS: &[&str] = &[
+    "--connect",
+    "--disconnect",
+    "--pair",
+    "--unpair",
+    "--add-favourite",
+    "--remove-favourite",
+    "--wait-connect",
+    "--wait-disconnect",
+];
+
+struct DryRunCommandRunner {
+    command_runner: Box<dyn CommandRunner>,
+}
+
+impl DryRunCommandRunner {
+    fn is_mutating(args: &[String]) -> bool {
+        // `--power` on its own is a query, `--power 1` sets it
+        args.iter().any(|x| MUTATING_ARGS.contains(&x.as_str()))
+            || (args.first().map(String::as_str) == Some("--power") && args.len() > 1)
+    }
+}
+
+impl CommandRunner for DryRunCommandRunner {
+    fn run_command(
+        &self,
+        command: &str,
+        args: Vec<String>,
+    ) -> Result<std::process::Output, Box<dyn Error>> {
+        if !Self::is_mutating(&args) {
+            return self.command_runner.run_command(command, args);
+        }
+
+        eprintln!("Dry run : {} {}", command, args.join(" "));
+
+        Ok(std::process::Output {
+            status: success_status(),
+            stdout: Default::default(),
+            stderr: Default::default(),
+        })
+    }
+
+    fn run_commands(
+        &self,
+        command: &str,
+        args: Vec<Vec<String>>,
+    ) -> Vec<Result<std::process::Output, Box<dyn Error>>> {
+        if args.iter().any(|x| Self::is_mutating(x)) {
+            return args
+                .into_iter()
+                .map(|args| self.run_command(command, args))
+                .collect();
+        }
+
+        self.command_runner.run_commands(command, args)
+    }
+}
+
+#[cfg(unix)]
+fn success_status() -> ExitStatus {
+    std::os::unix::process::ExitStatusExt::from_raw(0)
+}
+
+#[cfg(windows)]
+fn success_status() -> ExitStatus {
+    std::os::windows::process::ExitStatusExt::from_raw(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        os::unix::process::ExitStatusExt,
+        sync::{
+            atomic::{AtomicUsize, Ordering as AtomicOrdering},
+            Arc,
+        },
+    };
+
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn parse_device_list_parses_blueutil_json() {
+        let output = r#"[
+            {"address":"5c-2e-fg-da-a3-43","recentAccessDate":"2022-08-01T12:00:10+00:00","favourite":false,"name":"AirPods Pro","connected":false,"paired":true,"slave":false},
+            {"address":"80-3b-5c-c2-b1-7f","recentAccessDate":"2022-08-01T13:10:10+01:00","favourite":true,"name":"AirPods \"Max\"","connected":true,"paired":false,"slave":false}
+        ]"#;
+
+        let (devices, diagnostics) = parse_device_list(output).unwrap();
+
+        assert!(diagnostics.is_empty());
+        assert_eq!(devices[0].name, "AirPods Pro");
+        assert_eq!(devices[0].address, "5c-2e-fg-da-a3-43");
+        assert!(!devices[0].connected);
+        assert!(!devices[0].favourite);
+        assert!(devices[0].paired);
+
+        assert_eq!(devices[1].name, r#"AirPods "Max""#);
+        assert!(devices[1].connected);
+        assert!(devices[1].favourite);
+        assert!(!devices[1].paired);
+        assert_eq!(
+            devices[1].last_used,
+            Some(Utc.with_ymd_and_hms(2022, 8, 1, 12, 10, 10).unwrap())
+        );
+    }
+
+    #[test]
+    fn parse_device_list_parses_a_single_device() {
+        let output = r#"{"address":"80-3b-5c-c2-b1-7f","name":"AirPods Max","connected":true}"#;
+
+        let (devices, _) = parse_device_list(output).unwrap();
+
+        assert_eq!(devices.len(), 1);
+        assert!(devices[0].connected);
+        assert_eq!(devices[0].last_used, None);
+    }
+
+    #[test]
+    fn parse_device_list_reads_rssi_of_connected_devices() {
+        let output = r#"[
+            {"address":"80-3b-5c-c2-b1-7f","connected":true,"slave":false,"RSSI":-56,"rawRSSI":-61},
+            {"address":"5c-2e-fg-da-a3-43","connected":true,"slave":false,"RSSI":127,"rawRSSI":127},
+            {"address":"a4-c6-f0-1d-52-e9","connected":false}
+        ]"#;
+
+        let (devices, _) = parse_device_list(output).unwrap();
+
+        assert_eq!(
+            devices.iter().map(|x| x.rssi).collect::<Vec<_>>(),
+            vec![Some(-56), None, None]
+        );
+    }
+
+    #[test]
+    fn parse_device_list_accepts_the_older_date_format() {
+        let output =
+            r#"[{"address":"80-3b-5c-c2-b1-7f","recentAccessDate":"2022-08-01 12:10:10 +0000"}]"#;
+
+        let (devices, _) = parse_device_list(output).unwrap();
+
+        assert_eq!(
+            devices[0].last_used,
+            Some(Utc.with_ymd_and_hms(2022, 8, 1, 12, 10, 10).unwrap())
+        );
+    }
+
+    #[test]
+    fn parse_device_list_records_failed_entries_with_indices() {
+        let output = r#"[
+            {"address":"5c-2e-fg-da-a3-43","name":"AirPods Pro"},
+            "garbage",
+            {"address":"80-3b-5c-c2-b1-7f","name":"AirPods Max"},
+            {"name":"Keyboard"}
+        ]"#;
+
+        let (devices, diagnostics) = parse_device_list(output).unwrap();
+
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].name, "AirPods Pro");
+        assert_eq!(devices[1].name, "AirPods Max");
+        assert_eq!(
+            diagnostics.failures,
+            vec![
+                ParseFailure {
+                    index: 1,
+                    entry: String::from(r#""garbage""#),
+                    reason: String::from(
+                        "invalid type: string \"garbage\", expected struct BlueutilDevice"
+                    ),
+                },
+                ParseFailure {
+                    index: 3,
+                    entry: String::from(r#"{"name":"Keyboard"}"#),
+                    reason: String::from("missing field `address`"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_default_device_list_parses_blueutil_text() {
+        let output = "address: 80-3b-5c-c2-b1-7f, connected (master, -56 dBm), favourite, paired, name: \"AirPods, Max\", recent access date: 2022-08-01 12:10:10 +0000\n\
+            address: 5c-2e-fg-da-a3-43, not connected, not favourite, not paired, name: -, recent access date: -\n\
+            garbage\n";
+
+        let (devices, diagnostics) = parse_default_device_list(output);
+
+        assert_eq!(
+            devices,
+            vec![
+                DeviceInfo {
+                    name: String::from("AirPods, Max"),
+                    address: String::from("80-3b-5c-c2-b1-7f"),
+                    connected: true,
+                    last_used: Some(Utc.with_ymd_and_hms(2022, 8, 1, 12, 10, 10).unwrap()),
+                    rssi: Some(-56),
+                    favourite: true,
+                    paired: true,
+                    ..Default::default()
+                },
+                DeviceInfo {
+                    address: String::from("5c-2e-fg-da-a3-43"),
+                    ..Default::default()
+                },
+            ]
+        );
+        assert_eq!(
+            diagnostics.failures,
+            vec![ParseFailure {
+                index: 2,
+                entry: String::from("garbage"),
+                reason: String::from("couldn't read the address"),
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_default_device_list_reports_source_line_numbers() {
+        let output = "address: 80-3b-5c-c2-b1-7f, not connected, not favourite, paired, name: \"AirPods\", recent access date: -\n\
+            \n\
+            garbage\n";
+
+        let (devices, diagnostics) = parse_default_device_list(output);
+
+        assert_eq!(devices.len(), 1);
+        assert_eq!(
+            diagnostics.failures,
+            vec![ParseFailure {
+                index: 2,
+                entry: String::from("garbage"),
+                reason: String::from("couldn't read the address"),
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_default_device_keeps_unusual_names() {
+        for (name, expected) in [
+            ("\"Sam's \"Pods\"\"", "Sam's \"Pods\""),
+            (r#""name: AirPods""#, "name: AirPods"),
+            (
+                r#""AirPods, recent access date: -""#,
+                "AirPods, recent access date: -",
+            ),
+            (r#""🎧 AirPods Pro""#, "🎧 AirPods Pro"),
+            (r#""""#, ""),
+        ] {
+            let line = format!(
+                "address: 80-3b-5c-c2-b1-7f, not connected, not favourite, paired, name: {}, recent access date: -",
+                name
+            );
+
+            assert_eq!(parse_default_device(&line).unwrap().name, expected);
+        }
+    }
+
+    #[test]
+    fn parse_default_device_reports_what_it_couldnt_read() {
+        for (line, field) in [
+            ("garbage", "address"),
+            ("address: 80-3b-5c-c2-b1-7f, connected", "name"),
+            (
+                "address: 80-3b-5c-c2-b1-7f, name: \"AirPods\"",
+                "connection state",
+            ),
+        ] {
+            let err = parse_default_device(line).unwrap_err();
+
+            assert_eq!(err, ParseError::new(line, field));
+            assert_eq!(
+                err.to_string(),
+                format!("Couldn't read the {} from '{}'", field, line)
+            );
+        }
+    }
+
+    #[test]
+    fn parse_device_list_errors_for_invalid_json() {
+        assert!(parse_device_list("address: 5c-2e-fg-da-a3-43").is_err());
+        assert!(parse_device_list("\n").unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn device_info_parses_raw_str() {
+        let valid_str_not_connected = r#"address: 5c-2e-fg-da-a3-43, not connected, not favourite, paired, name: "AirPods Pro", recent access date: 2022-08-01 12:00:10 +0000"#;
+        let valid_str_connected = r#"address: 80-3b-5c-c2-b1-7f, connected (master, 0 dBm), not favourite, paired, name: "AirPods Max", recent access date: 2022-08-01 12:10:10 +0000"#;
+
+        let valid_device_not_connected = parse_default_device(valid_str_not_connected).unwrap();
+        assert_eq!(valid_device_not_connected.name, "AirPods Pro");
+        assert_eq!(valid_device_not_connected.address, "5c-2e-fg-da-a3-43");
+        assert!(!valid_device_not_connected.connected);
+
+        let valid_device_connected = parse_default_device(valid_str_connected).unwrap();
+        assert_eq!(valid_device_connected.name, "AirPods Max");
+        assert_eq!(valid_device_connected.address, "80-3b-5c-c2-b1-7f");
+        assert!(valid_device_connected.connected);
+    }
+
+    #[test]
+    fn device_info_errors_for_invalid_str() {
+        let invalid_str = "address: 5c-2e-fg-da-a3-43";
+        assert!(parse_default_device(invalid_str).is_err());
+    }
+
+    #[test]
+    fn dev_device_list_options_constructor() {
+        let result = DeviceListOptions::new(DeviceFilters::AllDevices, Some(String::from("1234")));
+
+        assert_eq!(result.filters, DeviceFilters::AllDevices);
+        assert_eq!(result.previous_address, Some(String::from("1234")))
+    }
+
+    #[test]
+    fn dev_device_list_default_all_devices() {
+        let result = DeviceListOptions::new_default_all_devices();
+
+        assert_eq!(result.filters, DeviceFilters::AllDevices);
+        assert_eq!(result.previous_address, None);
+        assert_eq!(result.sort_order, DEFAULT_SORT_ORDER);
+    }
+
+    #[test]
+    fn dev_device_list_options_with_sort_order() {
+        let result = DeviceListOptions::new_default_all_devices()
+            .with_sort_order(vec![SortKey::Favourite, SortKey::Name]);
+
+        assert_eq!(result.sort_order, vec![SortKey::Favourite, SortKey::Name]);
+    }
+
+    #[test]
+    fn dev_device_list_options_with_sort() {
+        let result = DeviceListOptions::new_default_all_devices().with_sort(SortOrder::Connected);
+        assert_eq!(result.sort_order, DEFAULT_SORT_ORDER);
+
+        let result = DeviceListOptions::new_default_all_devices().with_sort(SortOrder::Frecency);
+        assert_eq!(
+            result.sort_order,
+            vec![
+                SortKey::Previous,
+                SortKey::Favourite,
+                SortKey::Recent,
+                SortKey::Name
+            ]
+        );
+    }
+
+    #[test]
+    fn bluetooth_client_print_devices_retrieves_device_list() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list()
+            .times(1)
+            .returning(|| Ok(vec![]));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        client.print_devices().unwrap();
+    }
+
+    #[test]
+    fn bluetooth_client_connect_to_device() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_connect_to_device()
+            .times(1)
+            .with(predicate::eq("address"))
+            .returning(|_| Ok(()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        client.connect_to_device("address").unwrap();
+    }
+
+    #[test]
+    fn bluetooth_client_rejects_malformed_addresses_before_running_blueutil() {
+        // Nothing is expected to run
+        let client = BluetoothClient::with_client(Box::new(blueutil_client(Box::new(
+            MockCommandRunner::default(),
+        ))));
+
+        for err in [
+            client.connect_to_device("80-3b-5c-c2-b1").unwrap_err(),
+            client.disconnect_from_device("--power").unwrap_err(),
+            client
+                .keepalive("80-3b-5c-c2-b1", Duration::ZERO, Duration::ZERO)
+                .unwrap_err(),
+            client.pair_device("--power", None).unwrap_err(),
+            client.unpair_device("80-3b-5c-c2-b1").unwrap_err(),
+            client.set_favourite("-", true).unwrap_err(),
+        ] {
+            assert_eq!(err.kind(), ErrorKind::InvalidAddress);
+        }
+    }
+
+    #[test]
+    fn bluetooth_client_connect_many_only_runs_valid_addresses() {
+        let mut mock = MockCommandRunner::default();
+        mock.expect_run_commands()
+            .withf(|_, args| *args == [vec!["--connect", "80-3b-5c-c2-b1-7f"]])
+            .times(1)
+            .returning(|_, _| {
+                vec![Ok(std::process::Output {
+                    status: ExitStatusExt::from_raw(0),
+                    stdout: Default::default(),
+                    stderr: Default::default(),
+                })]
+            });
+
+        let client = BluetoothClient::with_client(Box::new(blueutil_client(Box::new(mock))));
+
+        // As connected by `profile connect` from a group in the config
+        let results = client.connect_many(&[
+            String::from("80-3b-5c-c2-b1"),
+            String::from("80-3b-5c-c2-b1-7f"),
+            String::from("--power"),
+        ]);
+
+        assert_eq!(
+            results[0].1.as_ref().unwrap_err().kind(),
+            ErrorKind::InvalidAddress
+        );
+        assert!(results[1].1.is_ok());
+        assert_eq!(
+            results[2].1.as_ref().unwrap_err().kind(),
+            ErrorKind::InvalidAddress
+        );
+    }
+
+    #[test]
+    fn bluetooth_client_disconnect_from_device() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_disconnect_from_device()
+            .times(1)
+            .with(predicate::eq("address"))
+            .returning(|_| Ok(()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        client.disconnect_from_device("address").unwrap();
+    }
+
+    #[test]
+    fn bluetooth_client_records_connects_and_disconnects_in_history() {
+        let path = std::env::temp_dir()
+            .join(format!(
+                "airpod-alfred-client-history-{}",
+                std::process::id()
+            ))
+            .join("history.jsonl");
+        let _ = std::fs::remove_file(&path);
+
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_connect_to_device()
+            .returning(|_| Err(BluetoothError::new("Failed to connect")));
+        mock.expect_disconnect_from_device().returning(|_| Ok(()));
+
+        let client = BluetoothClient {
+            history: Some(History::new(path.clone())),
+            ..BluetoothClient::with_client(Box::new(mock))
+        };
+
+        assert!(client.connect_to_device("address").is_err());
+        client.disconnect_from_device("address").unwrap();
+
+        let entries = History::new(path).load().unwrap();
+        let recorded = entries
+            .iter()
+            .map(|x| (x.action, x.succeeded))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            recorded,
+            vec![
+                (HistoryAction::Connect, false),
+                (HistoryAction::Disconnect, true)
+            ]
+        );
+    }
+
+    #[test]
+    fn bluetooth_client_toggle_connected_status_disconnects_a_connected_device() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+
+        mock.expect_connect_to_device()
+            .times(1)
+            .returning(|_| Ok(()));
+        mock.expect_disconnect_from_device()
+            .times(0)
+            .returning(|_| Ok(()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        client
+            .toggle_connected_status("disconnected-address")
+            .unwrap();
+    }
+
+    #[test]
+    fn bluetooth_client_toggle_connected_status_connects_a_disconnected_device() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+
+        mock.expect_connect_to_device()
+            .times(0)
+            .returning(|_| Ok(()));
+        mock.expect_disconnect_from_device()
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        client.toggle_connected_status("connected-address").unwrap();
+    }
+
+    #[test]
+    fn bluetooth_client_connecting_invalidates_the_cached_device_list() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list()
+            .times(2)
+            .returning(|| Ok(vec![]));
+        mock.expect_connect_to_device().returning(|_| Ok(()));
+
+        let client = BluetoothClient {
+            device_list_cache: Some(DeviceListCache::new(
+                Duration::from_secs(30),
+                None,
+                Box::new(SystemClock),
+            )),
+            ..BluetoothClient::with_client(Box::new(mock))
+        };
+
+        let options = || DeviceListOptions::new_default_all_devices();
+        client.get_device_list(options()).unwrap();
+        client.get_device_list(options()).unwrap();
+        client.connect_to_device("address").unwrap();
+        client.get_device_list(options()).unwrap();
+    }
+
+    #[test]
+    fn bluetooth_client_toggle_connected_status_only_queries_the_device() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list().times(0);
+        mock.expect_get_device().times(1).returning(|address| {
+            Some(DeviceInfo {
+                address: address.to_string(),
+                connected: true,
+                ..Default::default()
+            })
+        });
+        mock.expect_disconnect_from_device()
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        assert!(!client.toggle_connected_status("connected-address").unwrap());
+    }
+
+    #[test]
+    fn bluetooth_client_ensure_connected_leaves_a_connected_device_connected() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+
+        mock.expect_connect_to_device()
+            .times(0)
+            .returning(|_| Ok(()));
+        mock.expect_disconnect_from_device()
+            .times(0)
+            .returning(|_| Ok(()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        assert!(client.ensure_connected("connected-address").unwrap());
+    }
+
+    #[test]
+    fn bluetooth_client_ensure_connected_connects_a_disconnected_device() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+
+        mock.expect_connect_to_device()
+            .times(1)
+            .with(predicate::eq("disconnected-address"))
+            .returning(|_| Ok(()));
+        mock.expect_disconnect_from_device()
+            .times(0)
+            .returning(|_| Ok(()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        assert!(client.ensure_connected("disconnected-address").unwrap());
+    }
+
+    #[test]
+    fn bluetooth_client_connect_with_stats_records_a_two_attempt_success() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+
+        let mut seq = Sequence::new();
+        mock.expect_connect_to_device()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Err(BluetoothError::new("failed")));
+        mock.expect_connect_to_device()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Ok(()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let stats = client.connect_with_stats(
+            "connected-address",
+            &RetryPolicy::new(2, Duration::ZERO),
+            Duration::ZERO,
+        );
+        assert_eq!(stats.attempts, 2);
+        assert!(stats.succeeded);
+        assert_eq!(stats.final_state, Some(ConnectionState::Connected));
+        assert_eq!(stats.error, None);
+    }
+
+    #[test]
+    fn bluetooth_client_connect_with_stats_records_a_failure() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+        mock.expect_connect_to_device()
+            .times(2)
+            .returning(|_| Ok(()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let stats = client.connect_with_stats(
+            "disconnected-address",
+            &RetryPolicy::new(1, Duration::ZERO),
+            Duration::ZERO,
+        );
+        assert_eq!(stats.attempts, 2);
+        assert!(!stats.succeeded);
+        assert_eq!(stats.final_state, Some(ConnectionState::Disconnected));
+        assert_eq!(
+            stats.error,
+            Some(String::from(
+                "Device 'disconnected-address' did not connect"
+            ))
+        );
+    }
+
+    #[test]
+    fn retry_policy_backs_off_exponentially() {
+        let policy = RetryPolicy::new(10, Duration::from_millis(500));
+
+        assert_eq!(policy.max_attempts, 11);
+        assert_eq!(policy.delay_before_retry(1), Duration::from_millis(500));
+        assert_eq!(policy.delay_before_retry(2), Duration::from_secs(1));
+        assert_eq!(policy.delay_before_retry(3), Duration::from_secs(2));
+        assert_eq!(policy.delay_before_retry(10), MAX_RETRY_DELAY);
+        assert_eq!(policy.delay_before_retry(64), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn bluetooth_client_toggle_with_retries_retries_connecting() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+
+        let mut seq = Sequence::new();
+        mock.expect_connect_to_device()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Err(BluetoothError::new("failed")));
+        mock.expect_connect_to_device()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Err(BluetoothError::new("failed again")));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let err = client
+            .toggle_with_retries(
+                "disconnected-address",
+                false,
+                &RetryPolicy::new(1, Duration::ZERO),
+                Duration::ZERO,
+            )
+            .unwrap_err();
+        assert_eq!(err.to_string(), "failed again");
+    }
+
+    #[test]
+    fn bluetooth_client_toggle_with_retries_disconnects_connected_devices() {
+        let client = BluetoothClient::with_client(Box::new(FakeClient::with_devices(
+            blueutil_default_client_list(),
+        )));
+
+        let connected = client
+            .toggle_with_retries(
+                "connected-address",
+                false,
+                &RetryPolicy::no_retries(),
+                Duration::ZERO,
+            )
+            .unwrap();
+        assert!(!connected);
+    }
+
+    // A device that only disconnects on the nth disconnect.
+    fn ignored_disconnects_client(disconnects_needed: usize) -> BluetoothClient {
+        let disconnects = Arc::new(AtomicUsize::new(0));
+
+        let mut mock = MockBlueutilClient::default();
+        let counter = disconnects.clone();
+        mock.expect_disconnect_from_device()
+            .times(2)
+            .returning(move |_| {
+                counter.fetch_add(1, AtomicOrdering::SeqCst);
+                Ok(())
+            });
+        mock.expect_get_device().returning(move |address| {
+            Some(DeviceInfo {
+                address: address.to_string(),
+                connected: disconnects.load(AtomicOrdering::SeqCst) < disconnects_needed,
+                ..Default::default()
+            })
+        });
+
+        BluetoothClient::with_client(Box::new(mock))
+    }
+
+    #[test]
+    fn bluetooth_client_disconnect_and_verify_retries_once() {
+        let client = ignored_disconnects_client(2);
+
+        client
+            .disconnect_and_verify("address", Duration::ZERO)
+            .unwrap();
+    }
+
+    #[test]
+    fn bluetooth_client_disconnect_and_verify_fails_when_the_device_stays_connected() {
+        let client = ignored_disconnects_client(3);
+
+        let err = client
+            .disconnect_and_verify("address", Duration::ZERO)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::DisconnectFailed);
+        assert_eq!(err.to_string(), "Device 'address' did not disconnect");
+    }
+
+    #[test]
+    fn bluetooth_client_switch_devices_swaps_the_connected_device() {
+        for (a, b) in [
+            ("connected-address", "disconnected-address"),
+            ("disconnected-address", "connected-address"),
+        ] {
+            let mut mock = MockBlueutilClient::default();
+            mock_blueutil_client_device_list(&mut mock);
+            mock.expect_disconnect_from_device()
+                .times(1)
+                .with(predicate::eq("connected-address"))
+                .returning(|_| Ok(()));
+            mock.expect_connect_to_device()
+                .times(1)
+                .with(predicate::eq("disconnected-address"))
+                .returning(|_| Ok(()));
+
+            let client = BluetoothClient::with_client(Box::new(mock));
+
+            assert_eq!(client.switch_devices(a, b).unwrap(), "disconnected-address");
+        }
+    }
+
+    #[test]
+    fn bluetooth_client_switch_devices_connects_the_first_when_neither_is_connected() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list().returning(|| {
+            Ok(vec![
+                DeviceInfo {
+                    address: String::from("address-a"),
+                    ..Default::default()
+                },
+                DeviceInfo {
+                    address: String::from("address-b"),
+                    ..Default::default()
+                },
+            ])
+        });
+        mock.expect_get_device().returning(|_| None);
+        mock.expect_disconnect_from_device().times(0);
+        mock.expect_connect_to_device()
+            .times(1)
+            .with(predicate::eq("address-a"))
+            .returning(|_| Ok(()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        assert_eq!(
+            client.switch_devices("address-a", "address-b").unwrap(),
+            "address-a"
+        );
+    }
+
+    #[test]
+    fn bluetooth_client_connect_many_reports_each_device() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_connect_to_devices()
+            .withf(|addresses| *addresses == ["address-1", "address-2"])
+            .times(1)
+            .returning(|_| vec![Err(BluetoothError::new("failed")), Ok(())]);
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let results = client.connect_many(&[String::from("address-1"), String::from("address-2")]);
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, "address-1");
+        assert_eq!(results[0].1.as_ref().unwrap_err().to_string(), "failed");
+        assert_eq!(results[1].0, "address-2");
+        assert!(results[1].1.is_ok());
+    }
+
+    #[test]
+    fn bluetooth_client_connect_many_connects_a_pool_at_a_time() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_connect_to_devices()
+            .withf(|addresses| addresses.len() == CONNECT_POOL_SIZE)
+            .times(1)
+            .returning(|addresses| addresses.iter().map(|_| Ok(())).collect());
+        mock.expect_connect_to_devices()
+            .withf(|addresses| *addresses == ["address-5"])
+            .times(1)
+            .returning(|_| vec![Ok(())]);
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let addresses = (1..=5)
+            .map(|x| format!("address-{}", x))
+            .collect::<Vec<_>>();
+        let results = client.connect_many(&addresses);
+
+        assert_eq!(
+            results
+                .iter()
+                .map(|(address, result)| (address.as_str(), result.is_ok()))
+                .collect::<Vec<_>>(),
+            addresses
+                .iter()
+                .map(|x| (x.as_str(), true))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn bluetooth_client_disconnect_all_only_disconnects_connected_devices() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+        mock.expect_disconnect_from_device()
+            .times(1)
+            .with(predicate::eq("connected-address"))
             .returning(|_| Ok(()));
         mock.expect_disconnect_from_device()
             .times(1)
+            .with(predicate::eq("connected-address-2"))
+            .returning(|_| Err(BluetoothError::new("failed")));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let results = client.disconnect_all(DeviceFilters::AllDevices).unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].device.address, "connected-address");
+        assert_eq!(results[0].error, None);
+        assert_eq!(results[1].device.address, "connected-address-2");
+        assert_eq!(results[1].error, Some(String::from("failed")));
+    }
+
+    #[test]
+    fn bluetooth_client_disconnect_all_respects_filters() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+        mock.expect_disconnect_from_device()
+            .times(1)
+            .with(predicate::eq("connected-address-2"))
+            .returning(|_| Ok(()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let results = client
+            .disconnect_all(DeviceFilters::SpecificAddresses {
+                addresses: vec![
+                    String::from("disconnected-address"),
+                    String::from("connected-address-2"),
+                ],
+            })
+            .unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].device.address, "connected-address-2");
+    }
+
+    #[test]
+    fn bluetooth_client_connect_most_recent_picks_most_recent_in_group() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list()
+            .returning(|| Ok(recently_used_device_list()));
+        mock.expect_connect_to_device()
+            .times(1)
+            .with(predicate::eq("address-2"))
+            .returning(|_| Ok(()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        // address-3 is the most recent overall but isn't in the group
+        let device = client
+            .connect_most_recent(DeviceFilters::SpecificAddresses {
+                addresses: vec![
+                    String::from("address-1"),
+                    String::from("address-2"),
+                    String::from("address-4"),
+                ],
+            })
+            .unwrap();
+        assert_eq!(device.address, "address-2");
+    }
+
+    #[test]
+    fn bluetooth_client_connect_most_recent_across_all_devices() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list()
+            .returning(|| Ok(recently_used_device_list()));
+        mock.expect_connect_to_device()
+            .times(1)
+            .with(predicate::eq("address-3"))
+            .returning(|_| Ok(()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let device = client
+            .connect_most_recent(DeviceFilters::AllDevices)
+            .unwrap();
+        assert_eq!(device.address, "address-3");
+    }
+
+    #[test]
+    fn bluetooth_client_connect_most_recent_errors_for_empty_group() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list()
+            .returning(|| Ok(recently_used_device_list()));
+        mock.expect_connect_to_device()
+            .times(0)
+            .returning(|_| Ok(()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        assert!(client
+            .connect_most_recent(DeviceFilters::SpecificAddresses { addresses: vec![] })
+            .is_err());
+    }
+
+    #[test]
+    fn bluetooth_client_keepalive_check_leaves_a_connected_device_alone() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+        mock.expect_connect_to_device()
+            .times(0)
+            .returning(|_| Ok(()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        assert_eq!(
+            client
+                .keepalive_check("connected-address", Duration::ZERO)
+                .unwrap(),
+            KeepaliveStatus::StillConnected
+        );
+    }
+
+    #[test]
+    fn bluetooth_client_keepalive_check_reconnects_a_dropped_device() {
+        let mut mock = MockBlueutilClient::default();
+        let mut seq = Sequence::new();
+
+        // The first check sees the device dropped, the verification after
+        // reconnecting sees it connected again.
+        mock.expect_is_connected()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Some(false));
+        mock.expect_connect_to_device()
+            .times(1)
+            .with(predicate::eq("disconnected-address"))
+            .in_sequence(&mut seq)
+            .returning(|_| Ok(()));
+        mock.expect_get_device()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Some(reconnected_device()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        assert_eq!(
+            client
+                .keepalive_check("disconnected-address", Duration::ZERO)
+                .unwrap(),
+            KeepaliveStatus::Reconnected
+        );
+    }
+
+    #[test]
+    fn bluetooth_client_keepalive_check_errors_when_reconnect_fails() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+        mock.expect_connect_to_device()
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        assert!(client
+            .keepalive_check("disconnected-address", Duration::ZERO)
+            .is_err());
+    }
+
+    #[test]
+    fn bluetooth_client_is_device_connected_prefers_info_when_sources_disagree() {
+        let mut mock = MockBlueutilClient::default();
+        // Unknown to --is-connected, so the device is looked up
+        mock.expect_is_connected().returning(|_| None);
+        mock.expect_get_device_list()
+            .returning(|| Ok(blueutil_default_client_list()));
+        // --paired still reports the device as disconnected
+        mock.expect_get_device()
+            .returning(|_| Some(reconnected_device()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        assert!(client.is_device_connected("disconnected-address").unwrap());
+    }
+
+    #[test]
+    fn bluetooth_client_is_device_connected_uses_info_when_missing_from_paired() {
+        let mut mock = MockBlueutilClient::default();
+        // Unknown to --is-connected, so the device is looked up
+        mock.expect_is_connected().returning(|_| None);
+        mock.expect_get_device_list().returning(|| Ok(vec![]));
+        mock.expect_get_device()
+            .returning(|_| Some(reconnected_device()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        assert!(client.is_device_connected("disconnected-address").unwrap());
+    }
+
+    #[test]
+    fn bluetooth_client_is_device_connected_falls_back_to_paired_when_info_misses() {
+        let mut mock = MockBlueutilClient::default();
+        // Unknown to --is-connected, so the device is looked up
+        mock.expect_is_connected().returning(|_| None);
+        mock.expect_get_device_list()
+            .returning(|| Ok(blueutil_default_client_list()));
+        mock.expect_get_device().returning(|_| None);
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        assert!(client.is_device_connected("connected-address").unwrap());
+    }
+
+    #[test]
+    fn bluetooth_client_is_device_connected_errors_when_neither_source_has_device() {
+        let mut mock = MockBlueutilClient::default();
+        // Unknown to --is-connected, so the device is looked up
+        mock.expect_is_connected().returning(|_| None);
+        mock.expect_get_device_list()
+            .returning(|| Ok(blueutil_default_client_list()));
+        mock.expect_get_device().returning(|_| None);
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        assert!(client.is_device_connected("unknown-address").is_err());
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_by_address_skips_the_device_list() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device().times(1).returning(|address| {
+            Some(DeviceInfo {
+                name: String::from("AirPods Pro"),
+                address: address.to_string(),
+                connected: true,
+                ..Default::default()
+            })
+        });
+        mock.expect_get_device_list().times(0);
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        assert!(client.get_device("80-3b-5c-c2-b1-7f").unwrap().connected);
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_calls_client() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list()
+            .times(1)
+            .returning(|| Ok(vec![]));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        client
+            .get_device_list(DeviceListOptions::new_default_all_devices())
+            .unwrap();
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_propagates_client_errors() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list()
+            .times(1)
+            .returning(|| Err(BluetoothError::new("blueutil failed")));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let err = client
+            .get_device_list(DeviceListOptions::new_default_all_devices())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "blueutil failed");
+    }
+
+    #[test]
+    fn bluetooth_client_can_be_shared_across_threads() {
+        let client = BluetoothClient::with_client(Box::new(FakeClient::with_devices(
+            blueutil_default_client_list(),
+        )));
+
+        thread::scope(|scope| {
+            scope.spawn(|| client.connect_to_device("disconnected-address").unwrap());
+            scope.spawn(|| client.disconnect_from_device("connected-address").unwrap());
+        });
+
+        let devices = client
+            .get_device_list(DeviceListOptions::new_default_all_devices())
+            .unwrap();
+        let connected = |address: &str| devices.iter().any(|x| x.address == address && x.connected);
+        assert!(connected("disconnected-address"));
+        assert!(!connected("connected-address"));
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_or_fallback_uses_the_fallback_backend() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list()
+            .times(1)
+            .returning(|| Err(blueutil_missing("blueutil")));
+
+        let client = BluetoothClient {
+            fallback_client: Some(Box::new(FakeClient::with_devices(
+                blueutil_default_client_list(),
+            ))),
+            ..BluetoothClient::with_client(Box::new(mock))
+        };
+
+        let (devices, degraded) = client
+            .get_device_list_or_fallback(DeviceListOptions::new(
+                DeviceFilters::ConnectionState(ConnectionState::Disconnected),
+                None,
+            ))
+            .unwrap();
+        assert_eq!(
+            devices.iter().map(|x| x.name.as_str()).collect::<Vec<_>>(),
+            vec!["device1"]
+        );
+        assert_eq!(degraded.unwrap().kind(), ErrorKind::BlueutilMissing);
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_or_fallback_prefers_the_main_backend() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+
+        let client = BluetoothClient {
+            fallback_client: Some(Box::new(FakeClient::with_devices(vec![]))),
+            ..BluetoothClient::with_client(Box::new(mock))
+        };
+
+        let (devices, degraded) = client
+            .get_device_list_or_fallback(DeviceListOptions::new_default_all_devices())
+            .unwrap();
+        assert!(!devices.is_empty());
+        assert!(degraded.is_none());
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_or_fallback_returns_the_main_error() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list()
+            .returning(|| Err(BluetoothError::new("blueutil failed")));
+
+        let mut fallback = MockBlueutilClient::default();
+        fallback
+            .expect_get_device_list()
+            .returning(|| Err(BluetoothError::new("system_profiler failed")));
+
+        let client = BluetoothClient {
+            fallback_client: Some(Box::new(fallback)),
+            ..BluetoothClient::with_client(Box::new(mock))
+        };
+
+        let err = client
+            .get_device_list_or_fallback(DeviceListOptions::new_default_all_devices())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "blueutil failed");
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_filters_all() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let devices = client
+            .get_device_list(DeviceListOptions {
+                filters: DeviceFilters::AllDevices,
+                previous_address: None,
+                ..Default::default()
+            })
+            .unwrap();
+        let all_devices = blueutil_default_client_list();
+
+        assert_eq!(devices.len(), all_devices.len());
+        assert!(devices.contains(&all_devices[0]));
+        assert!(devices.contains(&all_devices[1]));
+        assert!(devices.contains(&all_devices[2]));
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_filters_regex() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let devices = client
+            .get_device_list(DeviceListOptions {
+                filters: DeviceFilters::Regex {
+                    value: String::from("device1"),
+                },
+                previous_address: None,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name, "device1");
+        assert_eq!(devices[0].address, "disconnected-address");
+        assert!(!devices[0].connected);
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_filters_regex_patterns() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list().returning(|| {
+            Ok([
+                "AirPods Pro",
+                "AirPods Max",
+                "Work AirPods Pro",
+                "airpods max",
+            ]
+            .iter()
+            .map(|x| DeviceInfo {
+                name: x.to_string(),
+                ..Default::default()
+            })
+            .collect())
+        });
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let devices = client
+            .get_device_list(
+                DeviceListOptions::new(
+                    DeviceFilters::Regex {
+                        value: String::from("^AirPods (Pro|Max)$"),
+                    },
+                    None,
+                )
+                .with_sort_order(vec![]),
+            )
+            .unwrap();
+        assert_eq!(
+            devices.iter().map(|x| x.name.as_str()).collect::<Vec<_>>(),
+            vec!["AirPods Pro", "AirPods Max", "airpods max"]
+        );
+
+        let err = client
+            .get_device_list(DeviceListOptions::new(
+                DeviceFilters::Regex {
+                    value: String::from("AirPods (Pro"),
+                },
+                None,
+            ))
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Invalid filter pattern 'AirPods (Pro' :"));
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_combines_filters() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+        let names = |filters| {
+            client
+                .get_device_list(DeviceListOptions::new(filters, None).with_sort_order(vec![]))
+                .unwrap()
+                .into_iter()
+                .map(|x| x.name)
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(
+            names(DeviceFilters::All(vec![
+                DeviceFilters::Regex {
+                    value: String::from("^device"),
+                },
+                DeviceFilters::Not(Box::new(DeviceFilters::SpecificAddresses {
+                    addresses: vec![String::from("connected-address")],
+                })),
+            ])),
+            vec!["device1", "device3"]
+        );
+        assert_eq!(
+            names(DeviceFilters::Any(vec![
+                DeviceFilters::Regex {
+                    value: String::from("device1"),
+                },
+                DeviceFilters::SpecificAddresses {
+                    addresses: vec![String::from("connected-address-2")],
+                },
+            ])),
+            vec!["device1", "device3"]
+        );
+        assert_eq!(
+            names(DeviceFilters::All(vec![])),
+            vec!["device1", "device2", "device3"]
+        );
+        assert!(names(DeviceFilters::Any(vec![])).is_empty());
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_excludes_addresses_and_names() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list().returning(|| {
+            Ok(vec![
+                DeviceInfo {
+                    name: String::from("AirPods Pro"),
+                    address: String::from("80-3b-5c-c2-b1-7f"),
+                    ..Default::default()
+                },
+                DeviceInfo {
+                    name: String::from("AirPods Max"),
+                    address: String::from("a4-c6-f0-1d-52-e9"),
+                    ..Default::default()
+                },
+                DeviceInfo {
+                    name: String::from("Magic Keyboard"),
+                    address: String::from("f0-b3-ec-12-4a-9d"),
+                    ..Default::default()
+                },
+            ])
+        });
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let devices = client
+            .get_device_list(DeviceListOptions::new(
+                DeviceFilters::Exclude {
+                    values: vec![String::from("A4:C6:F0:1D:52:E9"), String::from("keyboard")],
+                },
+                None,
+            ))
+            .unwrap();
+        assert_eq!(
+            devices.iter().map(|x| x.name.as_str()).collect::<Vec<_>>(),
+            vec!["AirPods Pro"]
+        );
+    }
+
+    #[test]
+    fn bluetooth_client_get_recent_device_list_keeps_recent_order() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_recent_devices().times(1).returning(|| {
+            let mut devices = blueutil_default_client_list();
+            devices.reverse();
+            Ok(devices)
+        });
+        mock.expect_get_device_list().times(0);
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let devices = client
+            .get_recent_device_list(DeviceFilters::SpecificAddresses {
+                addresses: vec![
+                    String::from("disconnected-address"),
+                    String::from("connected-address-2"),
+                ],
+            })
+            .unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].address, "connected-address-2");
+        assert_eq!(devices[1].address, "disconnected-address");
+    }
+
+    #[test]
+    fn bluetooth_client_discover_skips_paired_devices() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_discover()
+            .with(predicate::eq(Duration::from_secs(5)))
+            .times(1)
+            .returning(|_| {
+                Ok(vec![
+                    DeviceInfo {
+                        name: String::from("AirPods Pro"),
+                        address: String::from("paired-address"),
+                        paired: true,
+                        ..Default::default()
+                    },
+                    DeviceInfo {
+                        name: String::from("AirPods Max"),
+                        address: String::from("nearby-address"),
+                        ..Default::default()
+                    },
+                ])
+            });
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let devices = client.discover(Duration::from_secs(5)).unwrap();
+        assert_eq!(
+            devices
+                .iter()
+                .map(|x| x.address.as_str())
+                .collect::<Vec<_>>(),
+            vec!["nearby-address"]
+        );
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_filters_connection_state() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let devices = client
+            .get_device_list(DeviceListOptions::new(
+                DeviceFilters::ConnectionState(ConnectionState::Connected),
+                None,
+            ))
+            .unwrap();
+        assert_eq!(devices.len(), 2);
+        assert!(devices.iter().all(|x| x.connected));
+
+        let devices = client
+            .get_device_list(DeviceListOptions::new(
+                DeviceFilters::ConnectionState(ConnectionState::Disconnected),
+                None,
+            ))
+            .unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].address, "disconnected-address");
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_filters_device_class() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list().returning(|| {
+            Ok(vec![
+                DeviceInfo {
+                    name: String::from("Headphones"),
+                    address: String::from("headphones-address"),
+                    device_class: Some(DeviceClass::Audio),
+                    ..Default::default()
+                },
+                DeviceInfo {
+                    name: String::from("Keyboard"),
+                    address: String::from("keyboard-address"),
+                    device_class: Some(DeviceClass::Peripheral),
+                    ..Default::default()
+                },
+                // blueutil doesn't report the class
+                DeviceInfo {
+                    name: String::from("Bose QC45"),
+                    address: String::from("airpods-address"),
+                    ..Default::default()
+                },
+                DeviceInfo {
+                    name: String::from("Mouse"),
+                    address: String::from("mouse-address"),
+                    ..Default::default()
+                },
+            ])
+        });
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let devices = client
+            .get_device_list(DeviceListOptions::new(
+                DeviceFilters::DeviceClass(DeviceClass::Audio),
+                None,
+            ))
+            .unwrap();
+        assert_eq!(
+            devices
+                .iter()
+                .map(|x| x.address.as_str())
+                .collect::<Vec<_>>(),
+            vec!["headphones-address", "airpods-address", "mouse-address"]
+        );
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_adds_details_for_connected_devices() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+        mock.expect_get_devices()
+            .withf(|addresses| *addresses == ["connected-address", "connected-address-2"])
+            .times(1)
+            .returning(|addresses| {
+                vec![
+                    Some(DeviceInfo {
+                        address: addresses[0].clone(),
+                        battery: Some(80),
+                        rssi: Some(-60),
+                        ..Default::default()
+                    }),
+                    None,
+                ]
+            });
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let devices = client
+            .get_device_list(
+                DeviceListOptions::new_default_all_devices()
+                    .with_sort_order(vec![SortKey::Name])
+                    .with_details(),
+            )
+            .unwrap();
+        assert_eq!(
+            devices
+                .iter()
+                .map(|x| (x.address.as_str(), x.battery, x.rssi))
+                .collect::<Vec<_>>(),
+            vec![
+                ("disconnected-address", None, None),
+                ("connected-address", Some(80), Some(-60)),
+                ("connected-address-2", None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_filters_specific_address() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let devices = client
+            .get_device_list(DeviceListOptions {
+                filters: DeviceFilters::SpecificAddresses {
+                    addresses: vec![String::from("connected-address-2")],
+                },
+                previous_address: None,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name, "device3");
+        assert_eq!(devices[0].address, "connected-address-2");
+        assert!(devices[0].connected);
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_filters_specific_addresses() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let devices = client
+            .get_device_list(DeviceListOptions {
+                filters: DeviceFilters::SpecificAddresses {
+                    addresses: vec![
+                        String::from("connected-address"),
+                        String::from("connected-address-2"),
+                    ],
+                },
+                previous_address: None,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].name, "device2");
+        assert_eq!(devices[1].name, "device3");
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_moves_previous_address_to_top() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        vec![
+            String::from("connected-address"),
+            String::from("connected-address-2"),
+            String::from("disconnected-address"),
+        ]
+        .into_iter()
+        .for_each(|address| {
+            let devices = client
+                .get_device_list(DeviceListOptions {
+                    filters: DeviceFilters::AllDevices,
+                    previous_address: Some(address.clone()),
+                    ..Default::default()
+                })
+                .unwrap();
+
+            assert_eq!(devices.len(), 3);
+            assert_eq!(devices[0].address, address);
+        });
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_matches_addresses_in_any_format() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list().returning(|| {
+            Ok(vec![
+                DeviceInfo {
+                    name: String::from("AirPods Max"),
+                    address: String::from("a4-c6-f0-1d-52-e9"),
+                    ..Default::default()
+                },
+                DeviceInfo {
+                    name: String::from("AirPods Pro"),
+                    address: String::from("80-3b-5c-c2-b1-7f"),
+                    ..Default::default()
+                },
+                DeviceInfo {
+                    name: String::from("Magic Keyboard"),
+                    address: String::from("f0-b3-ec-12-4a-9d"),
+                    ..Default::default()
+                },
+            ])
+        });
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let devices = client
+            .get_device_list(DeviceListOptions::new(
+                DeviceFilters::SpecificAddresses {
+                    addresses: vec![
+                        String::from("A4:C6:F0:1D:52:E9"),
+                        String::from("803B5CC2B17F"),
+                    ],
+                },
+                Some(String::from("80:3b:5c:c2:b1:7f")),
+            ))
+            .unwrap();
+
+        assert_eq!(
+            devices.iter().map(|x| x.name.as_str()).collect::<Vec<_>>(),
+            vec!["AirPods Pro", "AirPods Max"]
+        );
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_sorts_connected_before_favourite() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list()
+            .returning(|| Ok(sortable_device_list()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let devices = client
+            .get_device_list(
+                DeviceListOptions::new(DeviceFilters::AllDevices, Some(String::from("address-d")))
+                    .with_sort_order(vec![
+                        SortKey::Previous,
+                        SortKey::Connected,
+                        SortKey::Favourite,
+                        SortKey::Name,
+                    ]),
+            )
+            .unwrap();
+
+        let names = devices.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["d", "c", "b", "a", "e"]);
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_sorts_favourites_up_by_default() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list()
+            .returning(|| Ok(sortable_device_list()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let devices = client
+            .get_device_list(DeviceListOptions::new(DeviceFilters::AllDevices, None))
+            .unwrap();
+
+        let names = devices.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["c", "b", "a", "e", "d"]);
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_sorts_favourite_before_connected() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list()
+            .returning(|| Ok(sortable_device_list()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let devices = client
+            .get_device_list(
+                DeviceListOptions::new(DeviceFilters::AllDevices, Some(String::from("address-d")))
+                    .with_sort_order(vec![SortKey::Favourite, SortKey::Connected, SortKey::Name]),
+            )
+            .unwrap();
+
+        let names = devices.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["c", "a", "b", "d", "e"]);
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_sorts_by_recent() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list()
+            .returning(|| Ok(recently_used_device_list()));
+        mock.expect_get_recent_devices().returning(|| Ok(vec![]));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let devices = client
+            .get_device_list(
+                DeviceListOptions::new(DeviceFilters::AllDevices, None)
+                    .with_sort(SortOrder::Recent),
+            )
+            .unwrap();
+
+        let names = devices.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["device3", "device2", "device1", "device4"]);
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_list_sorts_by_recent_list_dates() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list()
+            .returning(|| Ok(recently_used_device_list()));
+        mock.expect_get_recent_devices().times(1).returning(|| {
+            Ok(vec![DeviceInfo {
+                address: String::from("ADDRESS-4"),
+                last_used: Some(Utc.with_ymd_and_hms(2022, 8, 1, 13, 0, 0).unwrap()),
+                ..Default::default()
+            }])
+        });
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let devices = client
+            .get_device_list(
+                DeviceListOptions::new(DeviceFilters::AllDevices, None)
+                    .with_sort(SortOrder::Recent),
+            )
+            .unwrap();
+
+        let names = devices.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["device4", "device3", "device2", "device1"]);
+    }
+
+    #[test]
+    fn bluetooth_client_is_device_connected() {
+        let mut mock = MockBlueutilClient::default();
+        mock_blueutil_client_device_list(&mut mock);
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        assert!(client.is_device_connected("connected-address").unwrap());
+
+        assert!(!client.is_device_connected("disconnected-address").unwrap());
+    }
+
+    #[test]
+    fn bluetooth_client_is_device_connected_skips_the_device_list() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_is_connected()
+            .times(1)
+            .returning(|_| Some(true));
+        mock.expect_get_device_list().times(0);
+        mock.expect_get_device().times(0);
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        assert!(client.is_device_connected("address").unwrap());
+    }
+
+    #[test]
+    fn blueutil_client_is_connected() {
+        let mut mock = MockCommandRunner::default();
+        mock.expect_run_command()
+            .withf(|command, args| {
+                command == "blueutil" && args.eq(&vec!["--is-connected", "address"])
+            })
+            .times(1)
+            .returning(|_, _| {
+                Ok(std::process::Output {
+                    status: ExitStatusExt::from_raw(0),
+                    stdout: b"1\n".to_vec(),
+                    stderr: Default::default(),
+                })
+            });
+
+        let client = blueutil_client(Box::new(mock));
+
+        assert_eq!(client.is_connected("address"), Some(true));
+    }
+
+    #[test]
+    fn blueutil_client_is_connected_is_none_for_unknown_devices() {
+        let mut mock = MockCommandRunner::default();
+        mock.expect_run_command().times(1).returning(|_, _| {
+            Ok(std::process::Output {
+                status: ExitStatusExt::from_raw(1 << 8),
+                stdout: Default::default(),
+                stderr: b"Device not found by address: address\n".to_vec(),
+            })
+        });
+
+        let client = blueutil_client(Box::new(mock));
+
+        assert_eq!(client.is_connected("address"), None);
+    }
+
+    #[test]
+    fn blueutil_client_connect_to_device() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command()
+            .withf(|command, args| command == "blueutil" && args.eq(&vec!["--connect", "address"]))
+            .times(1)
+            .returning(|_, _| {
+                Ok(std::process::Output {
+                    status: ExitStatusExt::from_raw(0),
+                    stdout: Default::default(),
+                    stderr: Default::default(),
+                })
+            });
+
+        let client = blueutil_client(Box::new(mock));
+
+        client.connect_to_device("address").unwrap();
+    }
+
+    #[test]
+    fn blueutil_client_disconnect_from_device() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command()
+            .withf(|command, args| {
+                command == "blueutil"
+                    && args.eq(&vec!["--disconnect", "address", "--info", "address"])
+            })
+            .times(1)
+            .returning(|_, _| {
+                Ok(std::process::Output {
+                    status: ExitStatusExt::from_raw(0),
+                    stdout: Default::default(),
+                    stderr: Default::default(),
+                })
+            });
+
+        let client = blueutil_client(Box::new(mock));
+
+        client.disconnect_from_device("address").unwrap();
+    }
+
+    #[test]
+    fn blueutil_client_waits_for_state_in_whole_seconds() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command()
+            .withf(|command, args| {
+                command == "blueutil" && *args == ["--wait-disconnect", "address", "2"]
+            })
+            .times(1)
+            .returning(|_, _| {
+                Ok(std::process::Output {
+                    status: ExitStatusExt::from_raw(1 << 8),
+                    stdout: Default::default(),
+                    stderr: Default::default(),
+                })
+            });
+
+        let client = blueutil_client(Box::new(mock));
+
+        let err = client
+            .wait_for_state(
+                "address",
+                ConnectionState::Disconnected,
+                Duration::from_millis(1500),
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Timeout);
+        assert_eq!(err.to_string(), "Device 'address' did not disconnect");
+    }
+
+    #[test]
+    fn blueutil_client_reports_missing_blueutil() {
+        let client = BlueutilClient::new(
+            Box::new(DefaultCommandRunner {
+                timeout: Duration::from_secs(5),
+            }),
+            Some(String::from("/nonexistent/blueutil")),
+        );
+
+        let err = client.get_device_list().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BlueutilMissing);
+        assert_eq!(
+            err.to_string(),
+            "blueutil not found at '/nonexistent/blueutil', install it with `brew install blueutil` or set BLUEUTIL_PATH"
+        );
+    }
+
+    #[test]
+    fn blueutil_client_connect_fails_on_exit_status() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command().times(1).returning(|_, _| {
+            Ok(std::process::Output {
+                status: ExitStatusExt::from_raw(1 << 8),
+                stdout: Default::default(),
+                stderr: b"Error: Failed to connect\n".to_vec(),
+            })
+        });
+
+        let client = blueutil_client(Box::new(mock));
+
+        let err = client.connect_to_device("address").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectFailed);
+        assert_eq!(
+            err.to_string(),
+            "Failed to connect to 'address' : Error: Failed to connect"
+        );
+        assert!(matches!(
+            err,
+            BluetoothError::CommandFailed { status: Some(1), ref stderr, .. }
+                if stderr == "Error: Failed to connect"
+        ));
+    }
+
+    #[test]
+    fn blueutil_client_disconnect_fails_on_exit_status() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command().times(1).returning(|_, _| {
+            Ok(std::process::Output {
+                status: ExitStatusExt::from_raw(1 << 8),
+                stdout: Default::default(),
+                stderr: Default::default(),
+            })
+        });
+
+        let client = blueutil_client(Box::new(mock));
+
+        let err = client.disconnect_from_device("address").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::DisconnectFailed);
+        assert_eq!(err.to_string(), "Failed to disconnect from 'address'");
+    }
+
+    #[test]
+    fn blueutil_client_get_device_list() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command()
+            .withf(|command, args| {
+                command == "blueutil" && args.eq(&vec!["--format", "json", "--paired"])
+            })
+            .times(1)
+            .returning(|_, _| {
+                Ok(std::process::Output {
+                    status: ExitStatusExt::from_raw(0),
+                    stdout: Default::default(),
+                    stderr: Default::default(),
+                })
+            });
+
+        let client = blueutil_client(Box::new(mock));
+
+        assert!(client.get_device_list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn blueutil_client_get_recent_devices() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command()
+            .withf(|command, args| command == "blueutil" && args.eq(&vec!["--format", "json", "--recent"]))
+            .times(1)
+            .returning(|_, _| Ok(std::process::Output {
+                status: ExitStatusExt::from_raw(0),
+                stdout: br#"[{"address":"80-3b-5c-c2-b1-7f","name":"AirPods Max","connected":true},{"address":"80-3b-5c-c2-b1-80","name":"Keyboard","connected":false}]"#
+                    .to_vec(),
+                stderr: Default::default(),
+            }));
+
+        let client = blueutil_client(Box::new(mock));
+
+        let devices = client.get_recent_devices().unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].name, "AirPods Max");
+        assert_eq!(devices[1].name, "Keyboard");
+    }
+
+    #[test]
+    fn blueutil_client_discover_runs_an_inquiry_in_whole_seconds() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command()
+            .withf(|command, args| command == "blueutil" && args.eq(&vec!["--format", "json", "--inquiry", "3"]))
+            .times(1)
+            .returning(|_, _| Ok(std::process::Output {
+                status: ExitStatusExt::from_raw(0),
+                stdout: br#"[{"address":"80-3b-5c-c2-b1-7f","name":"AirPods Pro","connected":false,"paired":false}]"#
+                    .to_vec(),
+                stderr: Default::default(),
+            }));
+
+        let client = blueutil_client(Box::new(mock));
+
+        let devices = client.discover(Duration::from_millis(2500)).unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name, "AirPods Pro");
+    }
+
+    #[test]
+    fn blueutil_client_get_device() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command()
+            .withf(|command, args| {
+                command == "blueutil"
+                    && args.eq(&vec!["--format", "json", "--info", "80-3b-5c-c2-b1-7f"])
+            })
+            .times(1)
+            .returning(|_, _| {
+                Ok(std::process::Output {
+                    status: ExitStatusExt::from_raw(0),
+                    stdout:
+                        br#"{"address":"80-3b-5c-c2-b1-7f","name":"AirPods Max","connected":true}"#
+                            .to_vec(),
+                    stderr: Default::default(),
+                })
+            });
+
+        let client = blueutil_client(Box::new(mock));
+
+        let device = client.get_device("80-3b-5c-c2-b1-7f").unwrap();
+        assert_eq!(device.name, "AirPods Max");
+        assert!(device.connected);
+    }
+
+    #[test]
+    fn blueutil_client_get_device_returns_none_on_failure() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command().times(1).returning(|_, _| {
+            Ok(std::process::Output {
+                status: ExitStatusExt::from_raw(1 << 8),
+                stdout: Default::default(),
+                stderr: b"Device not found".to_vec(),
+            })
+        });
+
+        let client = blueutil_client(Box::new(mock));
+
+        assert_eq!(client.get_device("80-3b-5c-c2-b1-7f"), None);
+    }
+
+    #[test]
+    fn blueutil_client_uses_configured_blueutil_path() {
+        std::env::remove_var("BLUEUTIL_PATH");
+
+        let mut mock = MockCommandRunner::default();
+        mock.expect_run_command()
+            .withf(|command, _| command == "/opt/homebrew/bin/blueutil")
+            .times(1)
+            .returning(|_, _| {
+                Ok(std::process::Output {
+                    status: ExitStatusExt::from_raw(0),
+                    stdout: Default::default(),
+                    stderr: Default::default(),
+                })
+            });
+
+        let client = BlueutilClient {
+            blueutil_path: Some(String::from("/opt/homebrew/bin/blueutil")),
+            ..blueutil_client(Box::new(mock))
+        };
+
+        client.get_device_list().unwrap();
+    }
+
+    #[test]
+    fn blueutil_client_uses_the_text_format_before_json_was_added() {
+        let mut mock = MockCommandRunner::default();
+        mock.expect_run_command()
+            .times(2)
+            .returning(|_, args| {
+                let stdout = match args[0].as_str() {
+                    "--version" => "2.1.0\n",
+                    "--paired" => "address: 80-3b-5c-c2-b1-7f, not connected, not favourite, paired, name: \"AirPods Pro\", recent access date: -\n",
+                    _ => panic!("Unexpected arguments {:?}", args),
+                };
+
+                Ok(std::process::Output {
+                    status: ExitStatusExt::from_raw(0),
+                    stdout: stdout.as_bytes().to_vec(),
+                    stderr: Default::default(),
+                })
+            });
+
+        let client = BlueutilClient::new(Box::new(mock), None);
+
+        let devices = client.get_device_list().unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name, "AirPods Pro");
+    }
+
+    #[test]
+    fn blueutil_client_rejects_versions_that_are_too_old() {
+        let mut mock = MockCommandRunner::default();
+        mock.expect_run_command()
+            .withf(|_, args| *args == ["--version"])
+            .times(1)
+            .returning(|_, _| {
+                Ok(std::process::Output {
+                    status: ExitStatusExt::from_raw(0),
+                    stdout: b"1.1.2\n".to_vec(),
+                    stderr: Default::default(),
+                })
+            });
+
+        let client = BlueutilClient::new(Box::new(mock), None);
+
+        // The version is only read once
+        for _ in 0..2 {
+            let err = client.get_device_list().unwrap_err();
+            assert!(matches!(err, BluetoothError::BackendUnavailable(_)));
+            assert_eq!(
+                err.to_string(),
+                "blueutil 1.1.2 is too old, 2.0.0 or newer is needed, upgrade it with `brew upgrade blueutil`"
+            );
+        }
+    }
+
+    #[test]
+    fn blueutil_client_get_device_list_errors_on_failure() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command().times(1).returning(|_, _| {
+            Ok(std::process::Output {
+                status: ExitStatusExt::from_raw(1 << 8),
+                stdout: Default::default(),
+                stderr: Default::default(),
+            })
+        });
+
+        let client = blueutil_client(Box::new(mock));
+
+        assert!(client.get_device_list().is_err());
+    }
+
+    #[test]
+    fn blueutil_client_lists_devices_with_names_that_arent_utf8() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command().returning(|_, args| {
+            let stdout = if args.contains(&String::from("--paired")) {
+                b"[{\"address\": \"80-3b-5c-c2-b1-7f\", \"name\": \"Caf\xe9 AirPods\", \"connected\": true}]".to_vec()
+            } else {
+                b"2.9.1\n".to_vec()
+            };
+
+            Ok(std::process::Output {
+                status: ExitStatusExt::from_raw(0),
+                stdout,
+                stderr: Default::default(),
+            })
+        });
+
+        let client = blueutil_client(Box::new(mock));
+
+        let devices = client.get_device_list().unwrap();
+        assert_eq!(devices[0].name, "Caf\u{fffd} AirPods");
+        assert!(devices[0].connected);
+    }
+
+    #[test]
+    fn blueutil_client_raw_device_list_keeps_output_and_failures() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command().returning(|_, args| {
+            let stdout = if args.contains(&String::from("--paired")) {
+                b"[{\"address\": \"80-3b-5c-c2-b1-7f\", \"name\": \"AirPods\", \"connected\": true}, {\"name\": \"Broken\"}]".to_vec()
+            } else {
+                b"2.9.1\n".to_vec()
+            };
+
+            Ok(std::process::Output {
+                status: ExitStatusExt::from_raw(0),
+                stdout,
+                stderr: Default::default(),
+            })
+        });
+
+        let client = blueutil_client(Box::new(mock));
+
+        let listed = client.raw_device_list().unwrap();
+        assert!(listed.output.contains("\"Broken\""));
+        assert_eq!(listed.devices.len(), 1);
+        assert_eq!(listed.diagnostics.failures.len(), 1);
+        assert_eq!(listed.diagnostics.failures[0].index, 1);
+    }
+
+    #[test]
+    fn blueutil_client_errors_include_stderr() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command().times(2).returning(|_, _| {
+            Ok(std::process::Output {
+                status: ExitStatusExt::from_raw(1 << 8),
+                stdout: Default::default(),
+                stderr: b"Device not found by address: address\n".to_vec(),
+            })
+        });
+
+        let client = blueutil_client(Box::new(mock));
+
+        assert_eq!(
+            client.set_favourite("address", true).unwrap_err().to_string(),
+            "Failed to update favourites for device 'address' : Device not found by address: address"
+        );
+        assert_eq!(
+            client.get_device_list().unwrap_err().to_string(),
+            "Failed to list paired devices : Device not found by address: address"
+        );
+    }
+
+    #[test]
+    fn bluetooth_client_pair_device() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_pair_device()
+            .times(1)
+            .withf(|address, pin| address == "address" && pin.as_deref() == Some("0000"))
+            .returning(|_, _| Ok(()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        client
+            .pair_device("address", Some(String::from("0000")))
+            .unwrap();
+    }
+
+    #[test]
+    fn blueutil_client_pair_device() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command()
+            .withf(|command, args| command == "blueutil" && args.eq(&vec!["--pair", "address"]))
+            .times(1)
+            .returning(|_, _| {
+                Ok(std::process::Output {
+                    status: ExitStatusExt::from_raw(0),
+                    stdout: Default::default(),
+                    stderr: Default::default(),
+                })
+            });
+
+        let client = blueutil_client(Box::new(mock));
+
+        client.pair_device("address", None).unwrap();
+    }
+
+    #[test]
+    fn blueutil_client_pair_device_passes_pin() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command()
+            .withf(|command, args| {
+                command == "blueutil" && args.eq(&vec!["--pair", "address", "1234"])
+            })
+            .times(1)
+            .returning(|_, _| {
+                Ok(std::process::Output {
+                    status: ExitStatusExt::from_raw(0),
+                    stdout: Default::default(),
+                    stderr: Default::default(),
+                })
+            });
+
+        let client = blueutil_client(Box::new(mock));
+
+        client
+            .pair_device("address", Some(String::from("1234")))
+            .unwrap();
+    }
+
+    #[test]
+    fn blueutil_client_pair_device_asks_for_pin() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command().times(1).returning(|_, _| {
+            Ok(std::process::Output {
+                status: ExitStatusExt::from_raw(1 << 8),
+                stdout: Default::default(),
+                stderr: b"Pairing failed: PIN required".to_vec(),
+            })
+        });
+
+        let client = blueutil_client(Box::new(mock));
+
+        let err = client.pair_device("address", None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Device 'address' requires a PIN to pair, pass it with --pin"
+        );
+    }
+
+    #[test]
+    fn bluetooth_client_unpair_device() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_unpair_device()
+            .times(1)
+            .with(predicate::eq("address"))
             .returning(|_| Ok(()));
 
-        let client = BluetoothClient {
-            blueutil_client: Box::new(mock),
-        };
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        client.unpair_device("address").unwrap();
+    }
+
+    #[test]
+    fn blueutil_client_unpair_device() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command()
+            .withf(|command, args| command == "blueutil" && args.eq(&vec!["--unpair", "address"]))
+            .times(1)
+            .returning(|_, _| {
+                Ok(std::process::Output {
+                    status: ExitStatusExt::from_raw(0),
+                    stdout: Default::default(),
+                    stderr: Default::default(),
+                })
+            });
+
+        let client = blueutil_client(Box::new(mock));
+
+        client.unpair_device("address").unwrap();
+    }
+
+    #[test]
+    fn blueutil_client_unpair_device_errors_on_failure() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command().times(1).returning(|_, _| {
+            Ok(std::process::Output {
+                status: ExitStatusExt::from_raw(1 << 8),
+                stdout: Default::default(),
+                stderr: Default::default(),
+            })
+        });
+
+        let client = blueutil_client(Box::new(mock));
 
-        client.toggle_connected_status("connected-address").unwrap();
+        assert!(client.unpair_device("address").is_err());
     }
 
     #[test]
-    fn bluetooth_client_get_device_list_calls_client() {
-        let mut mock = MockBlueutilClient::default();
-        mock.expect_get_device_list().times(1).returning(|| vec![]);
+    fn blueutil_client_set_favourite() {
+        for (favourite, expected_arg) in [(true, "--add-favourite"), (false, "--remove-favourite")]
+        {
+            let mut mock = MockCommandRunner::default();
 
-        let client = BluetoothClient {
-            blueutil_client: Box::new(mock),
-        };
+            mock.expect_run_command()
+                .withf(move |command, args| {
+                    command == "blueutil" && args.eq(&vec![expected_arg, "address"])
+                })
+                .times(1)
+                .returning(|_, _| {
+                    Ok(std::process::Output {
+                        status: ExitStatusExt::from_raw(0),
+                        stdout: Default::default(),
+                        stderr: Default::default(),
+                    })
+                });
+
+            let client = blueutil_client(Box::new(mock));
 
-        client.get_device_list(DeviceListOptions::new_default_all_devices());
+            client.set_favourite("address", favourite).unwrap();
+        }
     }
 
     #[test]
-    fn bluetooth_client_get_device_list_filters_all() {
-        let mut mock = MockBlueutilClient::default();
-        mock_blueutil_client_device_list(&mut mock);
-
-        let client = BluetoothClient {
-            blueutil_client: Box::new(mock),
-        };
+    fn blueutil_client_set_favourite_errors_on_failure() {
+        let mut mock = MockCommandRunner::default();
 
-        let devices = client.get_device_list(DeviceListOptions {
-            filters: DeviceFilters::AllDevices,
-            previous_address: None,
+        mock.expect_run_command().times(1).returning(|_, _| {
+            Ok(std::process::Output {
+                status: ExitStatusExt::from_raw(1 << 8),
+                stdout: Default::default(),
+                stderr: Default::default(),
+            })
         });
-        let all_devices = blueutil_default_client_list();
 
-        assert_eq!(devices.len(), all_devices.len());
-        assert!(devices.contains(&all_devices[0]));
-        assert!(devices.contains(&all_devices[1]));
-        assert!(devices.contains(&all_devices[2]));
+        let client = blueutil_client(Box::new(mock));
+
+        assert!(client.set_favourite("address", true).is_err());
     }
 
     #[test]
-    fn bluetooth_client_get_device_list_filters_regex() {
-        let mut mock = MockBlueutilClient::default();
-        mock_blueutil_client_device_list(&mut mock);
+    fn dry_run_command_runner_skips_mutating_commands() {
+        let mut mock = MockCommandRunner::default();
+        mock.expect_run_command().times(0);
 
-        let client = BluetoothClient {
-            blueutil_client: Box::new(mock),
+        let runner = DryRunCommandRunner {
+            command_runner: Box::new(mock),
         };
 
-        let devices = client.get_device_list(DeviceListOptions {
-            filters: DeviceFilters::Regex {
-                value: String::from("device1"),
-            },
-            previous_address: None,
+        for args in [
+            vec!["--connect", "address"],
+            vec!["--power", "0"],
+            vec!["--wait-connect", "address", "5"],
+        ] {
+            let output = runner
+                .run_command("blueutil", args.into_iter().map(String::from).collect())
+                .unwrap();
+            assert!(output.status.success());
+        }
+    }
+
+    #[test]
+    fn dry_run_command_runner_runs_queries() {
+        let mut mock = MockCommandRunner::default();
+        mock.expect_run_command().times(2).returning(|_, _| {
+            Ok(std::process::Output {
+                status: ExitStatusExt::from_raw(0),
+                stdout: b"1".to_vec(),
+                stderr: Default::default(),
+            })
         });
-        assert_eq!(devices.len(), 1);
-        assert_eq!(devices[0].name, "device1");
-        assert_eq!(devices[0].address, "disconnected-address");
-        assert_eq!(devices[0].connected, false);
+
+        let runner = DryRunCommandRunner {
+            command_runner: Box::new(mock),
+        };
+
+        for args in [vec!["--paired"], vec!["--power"]] {
+            let output = runner
+                .run_command("blueutil", args.into_iter().map(String::from).collect())
+                .unwrap();
+            assert_eq!(output.stdout, b"1");
+        }
     }
 
     #[test]
-    fn bluetooth_client_get_device_list_filters_specific_address() {
-        let mut mock = MockBlueutilClient::default();
-        mock_blueutil_client_device_list(&mut mock);
+    fn default_command_runner_captures_output() {
+        let runner = DefaultCommandRunner {
+            timeout: Duration::from_secs(5),
+        };
 
-        let client = BluetoothClient {
-            blueutil_client: Box::new(mock),
+        let output = runner
+            .run_command("echo", vec![String::from("hello")])
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"hello\n");
+    }
+
+    #[test]
+    fn default_command_runner_runs_commands_side_by_side() {
+        let runner = DefaultCommandRunner {
+            timeout: Duration::from_secs(5),
         };
 
-        let devices = client.get_device_list(DeviceListOptions {
-            filters: DeviceFilters::SpecificAddresses {
-                addresses: vec![String::from("connected-address-2")],
-            },
-            previous_address: None,
-        });
-        assert_eq!(devices.len(), 1);
-        assert_eq!(devices[0].name, "device3");
-        assert_eq!(devices[0].address, "connected-address-2");
-        assert_eq!(devices[0].connected, true);
+        let started = Instant::now();
+        let results = runner.run_commands(
+            "sh",
+            (1..=3)
+                .map(|x| vec![String::from("-c"), format!("sleep 0.3; echo {}", x)])
+                .collect(),
+        );
+
+        assert!(started.elapsed() < Duration::from_millis(900));
+        assert_eq!(
+            results
+                .into_iter()
+                .map(|x| x.unwrap().stdout)
+                .collect::<Vec<_>>(),
+            vec![b"1\n".to_vec(), b"2\n".to_vec(), b"3\n".to_vec()]
+        );
     }
 
     #[test]
-    fn bluetooth_client_get_device_list_filters_specific_addresses() {
+    fn default_command_runner_kills_hung_commands() {
+        let runner = DefaultCommandRunner {
+            timeout: Duration::from_millis(50),
+        };
+
+        let started = Instant::now();
+        let err = runner
+            .run_command("sleep", vec![String::from("5")])
+            .unwrap_err();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(BluetoothError::from(err).kind(), ErrorKind::Timeout);
+    }
+
+    #[test]
+    fn bluetooth_client_error_kinds() {
         let mut mock = MockBlueutilClient::default();
-        mock_blueutil_client_device_list(&mut mock);
+        mock.expect_get_device_list()
+            .returning(|| Err(BluetoothError::with_kind(ErrorKind::Timeout, "timed out")));
 
-        let client = BluetoothClient {
-            blueutil_client: Box::new(mock),
-        };
+        let client = BluetoothClient::with_client(Box::new(mock));
 
-        let devices = client.get_device_list(DeviceListOptions {
-            filters: DeviceFilters::SpecificAddresses {
-                addresses: vec![
-                    String::from("connected-address"),
-                    String::from("connected-address-2"),
-                ],
-            },
-            previous_address: None,
-        });
-        assert_eq!(devices.len(), 2);
-        assert_eq!(devices[0].name, "device2");
-        assert_eq!(devices[1].name, "device3");
+        let err = client
+            .get_device_list(DeviceListOptions::new_default_all_devices())
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Timeout);
+
+        let err = named_device_client()
+            .resolve_device_id("Kitchen")
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::DeviceNotFound);
     }
 
     #[test]
-    fn bluetooth_client_get_device_list_moves_previous_address_to_top() {
+    fn bluetooth_client_toggle_power() {
         let mut mock = MockBlueutilClient::default();
-        mock_blueutil_client_device_list(&mut mock);
+        mock.expect_is_powered().times(1).returning(|| Ok(true));
+        mock.expect_set_power()
+            .times(1)
+            .with(predicate::eq(false))
+            .returning(|_| Ok(()));
 
-        let client = BluetoothClient {
-            blueutil_client: Box::new(mock),
-        };
+        let client = BluetoothClient::with_client(Box::new(mock));
 
-        vec![
-            String::from("connected-address"),
-            String::from("connected-address-2"),
-            String::from("disconnected-address"),
-        ]
-        .into_iter()
-        .for_each(|address| {
-            let devices = client.get_device_list(DeviceListOptions {
-                filters: DeviceFilters::AllDevices,
-                previous_address: Some(address.clone()),
-            });
+        assert!(!client.toggle_power().unwrap());
+    }
 
-            assert_eq!(devices.len(), 3);
-            assert_eq!(devices[0].address, address);
-        });
+    #[test]
+    fn bluetooth_client_connect_powers_on_first_when_asked() {
+        let mut sequence = Sequence::new();
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_is_powered()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|| Ok(false));
+        mock.expect_set_power()
+            .times(1)
+            .with(predicate::eq(true))
+            .in_sequence(&mut sequence)
+            .returning(|_| Ok(()));
+        mock.expect_connect_to_device()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_| Ok(()));
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        client
+            .connect_with_options("address", &ConnectOptions::default().with_power_on())
+            .unwrap();
     }
 
     #[test]
-    fn bluetooth_client_is_device_connected() {
+    fn bluetooth_client_connect_fails_when_the_device_does_not_report_connected() {
         let mut mock = MockBlueutilClient::default();
         mock_blueutil_client_device_list(&mut mock);
+        mock.expect_connect_to_device()
+            .times(2)
+            .returning(|_| Ok(()));
 
-        let client = BluetoothClient {
-            blueutil_client: Box::new(mock),
-        };
+        let client = BluetoothClient::with_client(Box::new(mock));
+        let options = ConnectOptions::default().with_verify(Duration::ZERO);
 
-        assert_eq!(
-            client.is_device_connected("connected-address").unwrap(),
-            true,
-        );
+        client
+            .connect_with_options("connected-address", &options)
+            .unwrap();
 
+        let err = client
+            .connect_with_options("disconnected-address", &options)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectFailed);
         assert_eq!(
-            client.is_device_connected("disconnected-address").unwrap(),
-            false,
+            err.to_string(),
+            "Device 'disconnected-address' did not connect"
         );
     }
 
     #[test]
-    fn blueutil_client_connect_to_device() {
-        let mut mock = MockCommandRunner::default();
-
-        mock.expect_run_command()
-            .withf(|command, args| command == "blueutil" && args.eq(&vec!["--connect", "address"]))
-            .times(1)
-            .returning(|_, _| std::process::Output {
-                status: ExitStatusExt::from_raw(0),
-                stdout: Default::default(),
-                stderr: Default::default(),
-            });
+    fn bluetooth_client_ensure_powered_leaves_a_powered_radio_alone() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_is_powered().times(1).returning(|| Ok(true));
+        mock.expect_set_power().times(0);
 
-        let client = BlueutilClient {
-            command_runner: Box::new(mock),
-        };
+        let client = BluetoothClient::with_client(Box::new(mock));
 
-        client.connect_to_device("address").unwrap();
+        assert!(!client.ensure_powered().unwrap());
     }
 
     #[test]
-    fn blueutil_client_disconnect_from_device() {
+    fn blueutil_client_connect_to_devices_runs_the_connects_together() {
         let mut mock = MockCommandRunner::default();
 
-        mock.expect_run_command()
+        mock.expect_run_commands()
             .withf(|command, args| {
                 command == "blueutil"
-                    && args.eq(&vec!["--disconnect", "address", "--info", "address"])
+                    && *args
+                        == [
+                            vec!["--connect", "address-1"],
+                            vec!["--connect", "address-2"],
+                        ]
             })
             .times(1)
-            .returning(|_, _| std::process::Output {
+            .returning(|_, _| {
+                vec![
+                    Ok(std::process::Output {
+                        status: ExitStatusExt::from_raw(0),
+                        stdout: Default::default(),
+                        stderr: Default::default(),
+                    }),
+                    Ok(std::process::Output {
+                        status: ExitStatusExt::from_raw(1 << 8),
+                        stdout: Default::default(),
+                        stderr: b"Error: Failed to connect\n".to_vec(),
+                    }),
+                ]
+            });
+
+        let client = blueutil_client(Box::new(mock));
+
+        let results =
+            client.connect_to_devices(&[String::from("address-1"), String::from("address-2")]);
+
+        assert!(results[0].is_ok());
+        assert_eq!(
+            results[1].as_ref().unwrap_err().to_string(),
+            "Failed to connect to 'address-2' : Error: Failed to connect"
+        );
+    }
+
+    #[test]
+    fn blueutil_client_is_powered() {
+        for (stdout, expected) in [("1\n", true), ("0\n", false)] {
+            let mut mock = MockCommandRunner::default();
+
+            mock.expect_run_command()
+                .withf(|command, args| command == "blueutil" && args.eq(&vec!["--power"]))
+                .times(1)
+                .returning(move |_, _| {
+                    Ok(std::process::Output {
+                        status: ExitStatusExt::from_raw(0),
+                        stdout: stdout.as_bytes().to_vec(),
+                        stderr: Default::default(),
+                    })
+                });
+
+            let client = blueutil_client(Box::new(mock));
+
+            assert_eq!(client.is_powered().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn blueutil_client_is_powered_errors_on_unexpected_output() {
+        let mut mock = MockCommandRunner::default();
+
+        mock.expect_run_command().times(1).returning(|_, _| {
+            Ok(std::process::Output {
                 status: ExitStatusExt::from_raw(0),
-                stdout: Default::default(),
+                stdout: b"maybe".to_vec(),
                 stderr: Default::default(),
-            });
+            })
+        });
 
-        let client = BlueutilClient {
-            command_runner: Box::new(mock),
-        };
+        let client = blueutil_client(Box::new(mock));
 
-        client.disconnect_from_device("address").unwrap();
+        assert!(client.is_powered().is_err());
     }
 
     #[test]
-    fn blueutil_client_get_device_list() {
+    fn blueutil_client_set_power() {
         let mut mock = MockCommandRunner::default();
 
         mock.expect_run_command()
-            .withf(|command, args| command == "blueutil" && args.eq(&vec!["--paired"]))
+            .withf(|command, args| command == "blueutil" && args.eq(&vec!["--power", "0"]))
             .times(1)
-            .returning(|_, _| std::process::Output {
-                status: ExitStatusExt::from_raw(0),
-                stdout: Default::default(),
-                stderr: Default::default(),
+            .returning(|_, _| {
+                Ok(std::process::Output {
+                    status: ExitStatusExt::from_raw(0),
+                    stdout: Default::default(),
+                    stderr: Default::default(),
+                })
             });
 
-        let client = BlueutilClient {
-            command_runner: Box::new(mock),
-        };
+        let client = blueutil_client(Box::new(mock));
+
+        client.set_power(false).unwrap();
+    }
+
+    fn named_device_list() -> Vec<DeviceInfo> {
+        vec![
+            ("address-1", "Airpods"),
+            ("address-2", "AirPods"),
+            ("address-3", "Office Headphones"),
+            ("address-4", "Desk Speaker"),
+            ("address-5", "desk speaker"),
+        ]
+        .into_iter()
+        .map(|(address, name)| DeviceInfo {
+            name: String::from(name),
+            address: String::from(address),
+            ..Default::default()
+        })
+        .collect()
+    }
+
+    fn named_device_client() -> BluetoothClient {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list()
+            .returning(|| Ok(named_device_list()));
 
-        client.get_device_list();
+        BluetoothClient::with_client(Box::new(mock))
+    }
+
+    #[test]
+    fn bluetooth_client_resolve_device_id_skips_the_list_for_mac_addresses() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list().times(0);
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        assert_eq!(
+            client.resolve_device_id("80:3B:5C:C2:B1:7F").unwrap(),
+            "80:3B:5C:C2:B1:7F"
+        );
+    }
+
+    #[test]
+    fn bluetooth_client_resolve_device_id_by_name() {
+        let client = named_device_client();
+
+        assert_eq!(
+            client.resolve_device_id("Office Headphones").unwrap(),
+            "address-3"
+        );
+        assert_eq!(
+            client.resolve_device_id("office headphones").unwrap(),
+            "address-3"
+        );
+        assert_eq!(client.resolve_device_id("ADDRESS-4").unwrap(), "address-4");
+    }
+
+    #[test]
+    fn bluetooth_client_resolve_device_id_prefers_exact_names() {
+        let client = named_device_client();
+
+        assert_eq!(client.resolve_device_id("AirPods").unwrap(), "address-2");
+        assert_eq!(client.resolve_device_id("Airpods").unwrap(), "address-1");
+    }
+
+    #[test]
+    fn bluetooth_client_resolve_device_id_errors_when_ambiguous() {
+        let client = named_device_client();
+
+        let err = client.resolve_device_id("DESK SPEAKER").unwrap_err();
+        assert!(err.to_string().contains("address-4, address-5"));
+    }
+
+    #[test]
+    fn bluetooth_client_resolve_device_id_errors_when_not_found() {
+        let client = named_device_client();
+
+        assert!(client.resolve_device_id("Kitchen").is_err());
+    }
+
+    #[test]
+    fn bluetooth_client_resolve_device_id_errors_for_mistyped_addresses() {
+        let client = named_device_client();
+
+        let err = client.resolve_device_id("80-3b-5c-c2-b1").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidAddress);
+        assert_eq!(
+            err.to_string(),
+            "'80-3b-5c-c2-b1' isn't a MAC address, e.g. 80-3b-5c-c2-b1-7f"
+        );
+    }
+
+    #[test]
+    fn bluetooth_client_get_device_by_name() {
+        let mut mock = MockBlueutilClient::default();
+        mock.expect_get_device_list()
+            .returning(|| Ok(named_device_list()));
+        mock.expect_get_device().returning(|_| None);
+
+        let client = BluetoothClient::with_client(Box::new(mock));
+
+        let device = client.get_device("office headphones").unwrap();
+        assert_eq!(device.name, "Office Headphones");
+        assert_eq!(device.address, "address-3");
+
+        let err = client.get_device("Kitchen").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::DeviceNotFound);
+    }
+
+    // Skips detecting the version, so the mock only sees the commands under test.
+    fn blueutil_client(command_runner: Box<dyn CommandRunner>) -> BlueutilClient {
+        BlueutilClient {
+            version: OnceLock::from(Some(BlueutilVersion::new(2, 9, 1))),
+            ..BlueutilClient::new(command_runner, None)
+        }
     }
 
     fn mock_blueutil_client_device_list(mock: &mut MockBlueutilClient) {
         mock.expect_get_device_list()
-            .returning(|| blueutil_default_client_list());
+            .returning(|| Ok(blueutil_default_client_list()));
+        mock.expect_get_device()
+            .returning(find_in_default_client_list);
+        mock.expect_is_connected()
+            .returning(|address| find_in_default_client_list(address).map(|x| x.connected));
+    }
+
+    fn find_in_default_client_list(address: &str) -> Option<DeviceInfo> {
+        blueutil_default_client_list()
+            .into_iter()
+            .find(|x| x.address == address)
+    }
+
+    fn recently_used_device_list() -> Vec<DeviceInfo> {
+        vec![(1, Some(5)), (2, Some(30)), (3, Some(45)), (4, None)]
+            .into_iter()
+            .map(|(index, minute)| DeviceInfo {
+                name: format!("device{}", index),
+                address: format!("address-{}", index),
+                connected: false,
+                last_used: minute.map(|x| Utc.with_ymd_and_hms(2022, 8, 1, 12, x, 0).unwrap()),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    // (name, connected, favourite), listed out of order
+    fn sortable_device_list() -> Vec<DeviceInfo> {
+        vec![
+            ("e", false, false),
+            ("c", true, true),
+            ("a", false, true),
+            ("d", false, false),
+            ("b", true, false),
+        ]
+        .into_iter()
+        .map(|(name, connected, favourite)| DeviceInfo {
+            name: String::from(name),
+            address: format!("address-{}", name),
+            connected,
+            favourite,
+            ..Default::default()
+        })
+        .collect()
+    }
+
+    fn reconnected_device() -> DeviceInfo {
+        DeviceInfo {
+            name: String::from("device1"),
+            address: String::from("disconnected-address"),
+            connected: true,
+            ..Default::default()
+        }
     }
 
     fn blueutil_default_client_list() -> Vec<DeviceInfo> {
@@ -625,16 +5105,19 @@ mod tests {
                 name: String::from("device1"),
                 address: String::from("disconnected-address"),
                 connected: false,
+                ..Default::default()
             },
             DeviceInfo {
                 name: String::from("device2"),
                 address: String::from("connected-address"),
                 connected: true,
+                ..Default::default()
             },
             DeviceInfo {
                 name: String::from("device3"),
                 address: String::from("connected-address-2"),
                 connected: true,
+                ..Default::default()
             },
         ]
     }
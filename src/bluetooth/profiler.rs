@@ -0,0 +1,250 @@
+use std::str;
+
+use serde_json::Value;
+
+use crate::battery::BatteryInfo;
+
+use super::{
+    check_status, decode_output, Address, Backend, BluetoothError, Capabilities, Capability,
+    Client, CommandRunner, DeviceClass, DeviceInfo, ErrorKind,
+};
+
+const SYSTEM_PROFILER: &str = "system_profiler";
+
+// Lists devices from `system_profiler SPBluetoothDataType -json`, which ships
+// with macOS. It can't change anything, so everything but listing devices and
+// reading the power state is unsupported.
+pub struct ProfilerClient {
+    command_runner: Box<dyn CommandRunner>,
+}
+
+impl ProfilerClient {
+    pub(super) fn new(command_runner: Box<dyn CommandRunner>) -> Self {
+        ProfilerClient { command_runner }
+    }
+
+    fn run_profiler(&self) -> Result<Value, BluetoothError> {
+        let output = self.command_runner.run_command(
+            SYSTEM_PROFILER,
+            vec![String::from("SPBluetoothDataType"), String::from("-json")],
+        )?;
+
+        check_status(
+            &output,
+            ErrorKind::Other,
+            "Failed to read the Bluetooth report from system_profiler",
+        )?;
+
+        Ok(serde_json::from_str(&decode_output(&output.stdout))?)
+    }
+}
+
+// The report has a section per controller, with connected and disconnected
+// devices as single key objects mapping the name to the details.
+fn parse_profiler_devices(report: &Value) -> Vec<DeviceInfo> {
+    let mut devices = vec![];
+
+    for section in members(&report["SPBluetoothDataType"]) {
+        for (key, connected) in [("device_connected", true), ("device_not_connected", false)] {
+            for entry in members(&section[key]) {
+                if let Some(entry) = entry.as_object() {
+                    for (name, details) in entry {
+                        if let Some(address) = details["device_address"].as_str() {
+                            let battery_levels = profiler_battery(details);
+                            devices.push(DeviceInfo {
+                                name: name.clone(),
+                                address: address.to_string(),
+                                connected,
+                                battery: battery_levels.combined,
+                                battery_levels: Some(battery_levels).filter(|x| x.has_buds()),
+                                device_class: details["device_minorType"]
+                                    .as_str()
+                                    .map(DeviceClass::from_minor_type),
+                                paired: true,
+                                ..Default::default()
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    devices
+}
+
+// AirPods report each bud and the case, other devices a single level.
+fn profiler_battery(details: &Value) -> BatteryInfo {
+    let level = |key: &str| {
+        details[key]
+            .as_str()
+            .and_then(|x| x.trim_end_matches('%').parse::<u8>().ok())
+    };
+
+    let mut battery = BatteryInfo::from_buds(
+        level("device_batteryLevelLeft"),
+        level("device_batteryLevelRight"),
+        level("device_batteryLevelCase"),
+    );
+    battery.combined = battery
+        .combined
+        .or_else(|| level("device_batteryLevelMain"));
+
+    battery
+}
+
+// The elements of an array, nothing for anything else.
+fn members(value: &Value) -> impl Iterator<Item = &Value> {
+    value.as_array().into_iter().flatten()
+}
+
+impl Client for ProfilerClient {
+    fn connect_to_device(&self, _address: &str) -> Result<(), BluetoothError> {
+        Err(Capability::Connect.unsupported(Backend::Profiler))
+    }
+
+    fn disconnect_from_device(&self, _address: &str) -> Result<(), BluetoothError> {
+        Err(Capability::Connect.unsupported(Backend::Profiler))
+    }
+
+    fn get_device_list(&self) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        Ok(parse_profiler_devices(&self.run_profiler()?))
+    }
+
+    fn get_recent_devices(&self) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        Err(Capability::RecentDevices.unsupported(Backend::Profiler))
+    }
+
+    fn get_device(&self, address: &str) -> Option<DeviceInfo> {
+        let address = Address::new(address);
+
+        self.get_device_list()
+            .ok()?
+            .into_iter()
+            .find(|x| Address::new(&x.address) == address)
+    }
+
+    fn pair_device(&self, _address: &str, _pin: Option<String>) -> Result<(), BluetoothError> {
+        Err(Capability::Pair.unsupported(Backend::Profiler))
+    }
+
+    fn unpair_device(&self, _address: &str) -> Result<(), BluetoothError> {
+        Err(Capability::Pair.unsupported(Backend::Profiler))
+    }
+
+    fn set_favourite(&self, _address: &str, _favourite: bool) -> Result<(), BluetoothError> {
+        Err(Capability::Favourite.unsupported(Backend::Profiler))
+    }
+
+    fn is_powered(&self) -> Result<bool, BluetoothError> {
+        let report = self.run_profiler()?;
+        let state = report["SPBluetoothDataType"][0]["controller_properties"]["controller_state"]
+            .as_str()
+            .unwrap_or_default();
+
+        Ok(state == "attrib_on")
+    }
+
+    fn set_power(&self, _on: bool) -> Result<(), BluetoothError> {
+        Err(Capability::SetPower.unsupported(Backend::Profiler))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            connect: false,
+            pair: false,
+            favourite: false,
+            set_power: false,
+            recent_devices: false,
+            discover: false,
+            ..Capabilities::all(Backend::Profiler)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+
+    use super::super::MockCommandRunner;
+    use super::*;
+
+    const REPORT: &str = r#"{
+        "SPBluetoothDataType": [{
+            "controller_properties": { "controller_state": "attrib_on" },
+            "device_connected": [
+                { "AirPods Pro": {
+                    "device_address": "80:3B:5C:C2:B1:7F",
+                    "device_minorType": "Headphones",
+                    "device_batteryLevelLeft": "80%",
+                    "device_batteryLevelRight": "74%",
+                    "device_batteryLevelCase": "50%"
+                } }
+            ],
+            "device_not_connected": [
+                { "Magic Keyboard": {
+                    "device_address": "F0:B3:EC:12:4A:9D",
+                    "device_batteryLevelMain": "61%"
+                } },
+                { "Missing Address": {} }
+            ]
+        }]
+    }"#;
+
+    fn client() -> ProfilerClient {
+        let mut mock = MockCommandRunner::new();
+        mock.expect_run_command().returning(|command, args| {
+            assert_eq!(command, "system_profiler");
+            assert_eq!(args, vec!["SPBluetoothDataType", "-json"]);
+
+            Ok(std::process::Output {
+                status: ExitStatusExt::from_raw(0),
+                stdout: REPORT.as_bytes().to_vec(),
+                stderr: vec![],
+            })
+        });
+
+        ProfilerClient::new(Box::new(mock))
+    }
+
+    #[test]
+    fn profiler_client_lists_connected_and_disconnected_devices() {
+        let devices = client().get_device_list().unwrap();
+
+        assert_eq!(
+            devices,
+            vec![
+                DeviceInfo {
+                    name: String::from("AirPods Pro"),
+                    address: String::from("80:3B:5C:C2:B1:7F"),
+                    connected: true,
+                    battery: Some(74),
+                    battery_levels: Some(BatteryInfo::from_buds(Some(80), Some(74), Some(50))),
+                    device_class: Some(DeviceClass::Audio),
+                    paired: true,
+                    ..Default::default()
+                },
+                DeviceInfo {
+                    name: String::from("Magic Keyboard"),
+                    address: String::from("F0:B3:EC:12:4A:9D"),
+                    battery: Some(61),
+                    paired: true,
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn profiler_client_reads_power_and_rejects_changes() {
+        let client = client();
+
+        assert!(client.is_powered().unwrap());
+        assert!(client
+            .get_device("80-3b-5c-c2-b1-7f")
+            .is_some_and(|x| x.connected));
+
+        let err = client.connect_to_device("80-3b-5c-c2-b1-7f").unwrap_err();
+        assert_eq!(err.kind(), super::super::ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+
+// A blueutil release, as printed by `blueutil --version`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BlueutilVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl BlueutilVersion {
+    // The 2.x arguments, older releases took commands instead of flags
+    pub const MINIMUM: BlueutilVersion = BlueutilVersion::new(2, 0, 0);
+    // `--format json`, older releases only have the default text format
+    pub const JSON_FORMAT: BlueutilVersion = BlueutilVersion::new(2, 2, 0);
+    // `--wait-connect` and `--wait-disconnect`
+    pub const WAIT: BlueutilVersion = BlueutilVersion::new(2, 7, 0);
+
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        BlueutilVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    // Parses e.g. `2.9.1` or `v2.9`, a missing minor or patch version is 0.
+    pub fn parse(version: &str) -> Option<Self> {
+        let mut parts = version
+            .trim()
+            .trim_start_matches('v')
+            .split('.')
+            .map(|x| x.parse::<u32>());
+
+        let major = parts.next()?.ok()?;
+        let minor = parts.next().unwrap_or(Ok(0)).ok()?;
+        let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+
+        Some(BlueutilVersion::new(major, minor, patch))
+    }
+}
+
+impl fmt::Display for BlueutilVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blueutil_version_parses_release_numbers() {
+        assert_eq!(
+            BlueutilVersion::parse("2.9.1\n"),
+            Some(BlueutilVersion::new(2, 9, 1))
+        );
+        assert_eq!(
+            BlueutilVersion::parse("v2.2"),
+            Some(BlueutilVersion::new(2, 2, 0))
+        );
+        assert_eq!(BlueutilVersion::parse("unknown"), None);
+        assert_eq!(BlueutilVersion::new(2, 10, 0).to_string(), "2.10.0");
+    }
+
+    #[test]
+    fn blueutil_version_compares_numerically() {
+        assert!(BlueutilVersion::new(2, 10, 0) > BlueutilVersion::new(2, 9, 1));
+        assert!(BlueutilVersion::new(1, 9, 0) < BlueutilVersion::MINIMUM);
+        assert!(BlueutilVersion::JSON_FORMAT < BlueutilVersion::WAIT);
+    }
+}
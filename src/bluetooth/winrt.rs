@@ -0,0 +1,207 @@
+use log::warn;
+use windows::{
+    Devices::Bluetooth::{BluetoothConnectionStatus, BluetoothDevice},
+    Devices::Enumeration::{
+        DeviceInformation, DevicePairingResultStatus, DeviceUnpairingResultStatus,
+    },
+    Devices::Radios::{Radio, RadioAccessStatus, RadioKind, RadioState},
+};
+
+use super::{
+    Address, Backend, BluetoothError, Capabilities, Capability, Client, DeviceInfo, ErrorKind,
+};
+
+// Talks to the WinRT Bluetooth APIs, so Windows launchers like PowerToys Run or
+// Flow Launcher can reuse the CLI. WinRT can't connect or disconnect audio
+// devices, that's left to Windows, but it can list, pair and power the radio.
+// With dry run set, changes are printed instead of made.
+pub struct WinRtClient {
+    dry_run: bool,
+}
+
+impl WinRtClient {
+    pub fn new(dry_run: bool) -> Self {
+        WinRtClient { dry_run }
+    }
+
+    // Returns true when the change should be skipped.
+    fn skip_for_dry_run(&self, action: &str, address: &str) -> bool {
+        if self.dry_run {
+            eprintln!("Dry run : {} {}", action, address);
+        }
+
+        self.dry_run
+    }
+
+    fn find_device(&self, address: &str) -> Result<BluetoothDevice, BluetoothError> {
+        let not_found = || -> BluetoothError {
+            BluetoothError::with_kind(
+                ErrorKind::DeviceNotFound,
+                &format!("Device '{}' not found", address),
+            )
+        };
+
+        let raw_address = to_raw_address(address).ok_or_else(not_found)?;
+
+        // Fails rather than returning null for unknown addresses
+        BluetoothDevice::FromBluetoothAddressAsync(raw_address)?
+            .get()
+            .map_err(|_| not_found())
+    }
+}
+
+// WinRT addresses are the six octets packed into a u64.
+fn to_raw_address(address: &str) -> Option<u64> {
+    let address = Address::new(address);
+    if !address.is_mac() {
+        return None;
+    }
+
+    u64::from_str_radix(&address.as_str().replace('-', ""), 16).ok()
+}
+
+fn from_raw_address(address: u64) -> String {
+    Address::new(&format!("{:012x}", address)).to_string()
+}
+
+fn device_info(device: &BluetoothDevice) -> Result<DeviceInfo, BluetoothError> {
+    Ok(DeviceInfo {
+        name: device.Name()?.to_string(),
+        address: from_raw_address(device.BluetoothAddress()?),
+        connected: device.ConnectionStatus()? == BluetoothConnectionStatus::Connected,
+        paired: device.DeviceInformation()?.Pairing()?.IsPaired()?,
+        ..Default::default()
+    })
+}
+
+fn bluetooth_radio() -> Result<Radio, BluetoothError> {
+    let radios = Radio::GetRadiosAsync()?.get()?;
+
+    for index in 0..radios.Size()? {
+        let radio = radios.GetAt(index)?;
+        if radio.Kind()? == RadioKind::Bluetooth {
+            return Ok(radio);
+        }
+    }
+
+    Err(BluetoothError::BackendUnavailable(String::from(
+        "No Bluetooth radio was found",
+    )))
+}
+
+impl From<windows::core::Error> for BluetoothError {
+    fn from(err: windows::core::Error) -> Self {
+        BluetoothError::new(&err.to_string())
+    }
+}
+
+impl Client for WinRtClient {
+    fn connect_to_device(&self, _address: &str) -> Result<(), BluetoothError> {
+        Err(Capability::Connect.unsupported(Backend::Windows))
+    }
+
+    fn disconnect_from_device(&self, _address: &str) -> Result<(), BluetoothError> {
+        Err(Capability::Connect.unsupported(Backend::Windows))
+    }
+
+    fn get_device_list(&self) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        let selector = BluetoothDevice::GetDeviceSelectorFromPairingState(true)?;
+        let infos = DeviceInformation::FindAllAsyncAqsFilter(&selector)?.get()?;
+
+        let mut devices = vec![];
+        for index in 0..infos.Size()? {
+            let id = infos.GetAt(index)?.Id()?;
+            devices.push(device_info(&BluetoothDevice::FromIdAsync(&id)?.get()?)?);
+        }
+
+        Ok(devices)
+    }
+
+    fn get_recent_devices(&self) -> Result<Vec<DeviceInfo>, BluetoothError> {
+        Err(Capability::RecentDevices.unsupported(Backend::Windows))
+    }
+
+    fn get_device(&self, address: &str) -> Option<DeviceInfo> {
+        device_info(&self.find_device(address).ok()?).ok()
+    }
+
+    fn pair_device(&self, address: &str, pin: Option<String>) -> Result<(), BluetoothError> {
+        // A PIN needs custom pairing with an event handler
+        if pin.is_some() {
+            warn!("The windows backend can't be given a PIN, it's ignored");
+        }
+        if self.skip_for_dry_run("pair", address) {
+            return Ok(());
+        }
+
+        let pairing = self.find_device(address)?.DeviceInformation()?.Pairing()?;
+        let status = pairing.PairAsync()?.get()?.Status()?;
+
+        if status == DevicePairingResultStatus::Paired
+            || status == DevicePairingResultStatus::AlreadyPaired
+        {
+            Ok(())
+        } else {
+            Err(BluetoothError::new(&format!(
+                "Failed to pair with device '{}' : {:?}",
+                address, status
+            )))
+        }
+    }
+
+    fn unpair_device(&self, address: &str) -> Result<(), BluetoothError> {
+        if self.skip_for_dry_run("unpair", address) {
+            return Ok(());
+        }
+
+        let pairing = self.find_device(address)?.DeviceInformation()?.Pairing()?;
+        let status = pairing.UnpairAsync()?.get()?.Status()?;
+
+        if status == DeviceUnpairingResultStatus::Unpaired
+            || status == DeviceUnpairingResultStatus::AlreadyUnpaired
+        {
+            Ok(())
+        } else {
+            Err(BluetoothError::new(&format!(
+                "Failed to unpair device '{}' : {:?}",
+                address, status
+            )))
+        }
+    }
+
+    fn set_favourite(&self, _address: &str, _favourite: bool) -> Result<(), BluetoothError> {
+        Err(Capability::Favourite.unsupported(Backend::Windows))
+    }
+
+    fn is_powered(&self) -> Result<bool, BluetoothError> {
+        Ok(bluetooth_radio()?.State()? == RadioState::On)
+    }
+
+    fn set_power(&self, on: bool) -> Result<(), BluetoothError> {
+        if self.skip_for_dry_run("power", if on { "1" } else { "0" }) {
+            return Ok(());
+        }
+
+        let state = if on { RadioState::On } else { RadioState::Off };
+        let access = bluetooth_radio()?.SetStateAsync(state)?.get()?;
+
+        if access == RadioAccessStatus::Allowed {
+            Ok(())
+        } else {
+            Err(BluetoothError::new(&format!(
+                "Not allowed to set the Bluetooth power state : {:?}",
+                access
+            )))
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            connect: false,
+            favourite: false,
+            recent_devices: false,
+            discover: false,
+            ..Capabilities::all(Backend::Windows)
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use std::{
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::bluetooth::{Address, DeviceInfo};
+use super::clock::Clock;
+use super::config;
+use super::history::HISTORY_FILE;
+
+pub const DEVICE_CACHE_FILE: &str = "devices.json";
+
+// Every file the tool writes to the data directory
+pub const DATA_FILES: &[&str] = &[DEVICE_CACHE_FILE, HISTORY_FILE];
+
+// Removes the tool's files from `dir`, returning the ones that were deleted.
+// Files that don't exist are skipped.
+pub fn clear_data_files(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut deleted = vec![];
+
+    for name in DATA_FILES {
+        let path = dir.join(name);
+
+        match fs::remove_file(&path) {
+            Ok(_) => deleted.push(path),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(Box::new(err)),
+        }
+    }
+
+    Ok(deleted)
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheFile {
+    updated_at: Option<DateTime<Utc>>,
+    devices: Vec<DeviceInfo>,
+}
+
+// Devices persisted between runs in the order they were fetched. Addresses are
+// normalized so the same device is only stored once regardless of how blueutil
+// formatted it.
+#[derive(Debug, Default, PartialEq)]
+pub struct DeviceCache {
+    devices: Vec<DeviceInfo>,
+    updated_at: Option<DateTime<Utc>>,
+}
+
+impl DeviceCache {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn default_path() -> Option<PathBuf> {
+        config::data_dir().map(|dir| dir.join(DEVICE_CACHE_FILE))
+    }
+
+    // Loads the cache, treating a missing file as an empty cache.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Default::default()),
+            Err(err) => return Err(Box::new(err)),
+        };
+
+        let file: CacheFile = serde_json::from_str(&contents)?;
+
+        let mut cache = DeviceCache::new();
+        for device in file.devices {
+            cache.insert_entry(device);
+        }
+        cache.updated_at = file.updated_at;
+
+        Ok(cache)
+    }
+
+    pub fn store(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = CacheFile {
+            updated_at: self.updated_at,
+            devices: self.devices.clone(),
+        };
+        fs::write(path, serde_json::to_string(&file)?)?;
+
+        Ok(())
+    }
+
+    // Replaces the cached devices, stamping the cache with the current time.
+    pub fn update(&mut self, devices: &[DeviceInfo], clock: &dyn Clock) {
+        self.devices.clear();
+        for device in devices {
+            self.insert(device);
+        }
+        self.updated_at = Some(clock.now());
+    }
+
+    // A cache that has never been updated is always expired.
+    pub fn is_expired(&self, ttl: Duration, clock: &dyn Clock) -> bool {
+        let ttl = match chrono::Duration::from_std(ttl) {
+            Ok(ttl) => ttl,
+            Err(_) => return false,
+        };
+
+        match self.updated_at {
+            Some(updated_at) => clock.now().signed_duration_since(updated_at) >= ttl,
+            None => true,
+        }
+    }
+
+    pub fn insert(&mut self, device: &DeviceInfo) {
+        self.insert_entry(device.clone());
+    }
+
+    // Empties the cache so it's expired until it's next updated.
+    pub fn invalidate(&mut self) {
+        self.devices.clear();
+        self.updated_at = None;
+    }
+
+    pub fn get(&self, address: &str) -> Option<DeviceInfo> {
+        self.position(&Address::new(address))
+            .map(|index| self.devices[index].clone())
+    }
+
+    // In the order the devices were fetched.
+    pub fn devices(&self) -> Vec<DeviceInfo> {
+        self.devices.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    fn position(&self, address: &Address) -> Option<usize> {
+        self.devices
+            .iter()
+            .position(|x| Address::new(&x.address) == *address)
+    }
+
+    // Duplicates of the same device keep whichever entry was used most recently,
+    // in the place of the first one.
+    fn insert_entry(&mut self, mut device: DeviceInfo) {
+        let address = Address::new(&device.address);
+        device.address = address.to_string();
+
+        match self.position(&address) {
+            Some(index) if self.devices[index].last_used > device.last_used => {}
+            Some(index) => self.devices[index] = device,
+            None => self.devices.push(device),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, path::PathBuf, process};
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::battery::BatteryInfo;
+    use crate::clock::FixedClock;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir()
+            .join(format!("airpod-alfred-cache-test-{}", process::id()))
+            .join(name)
+    }
+
+    fn device(address: &str, minute: u32) -> DeviceInfo {
+        DeviceInfo {
+            name: String::from("AirPods Pro"),
+            address: String::from(address),
+            connected: false,
+            last_used: Some(Utc.with_ymd_and_hms(2022, 8, 1, 12, minute, 0).unwrap()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn device_cache_loads_hyphen_form_with_colon_lookup() {
+        let path = temp_path("hyphen.json");
+
+        let mut cache = DeviceCache::new();
+        cache.insert(&device("80-3b-5c-c2-b1-7f", 0));
+        cache.store(&path).unwrap();
+
+        let loaded = DeviceCache::load(&path).unwrap();
+        let found = loaded.get("80:3B:5C:C2:B1:7F").unwrap();
+        assert_eq!(found.address, "80-3b-5c-c2-b1-7f");
+        assert_eq!(found.name, "AirPods Pro");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn device_cache_merges_duplicate_address_forms() {
+        let mut cache = DeviceCache::new();
+        cache.insert(&device("80:3B:5C:C2:B1:7F", 5));
+        cache.insert(&device("80-3b-5c-c2-b1-7f", 0));
+
+        assert_eq!(cache.len(), 1);
+        assert_eq!(
+            cache.get("80-3b-5c-c2-b1-7f").unwrap().last_used,
+            device("", 5).last_used
+        );
+    }
+
+    #[test]
+    fn device_cache_merges_duplicates_when_loading() {
+        let path = temp_path("duplicates.json");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(
+            &path,
+            r#"{"updated_at":null,"devices":[
+                {"address":"80-3b-5c-c2-b1-7f","name":"Old","connected":false,"last_used":"2022-08-01T12:00:00Z"},
+                {"address":"80:3B:5C:C2:B1:7F","name":"New","connected":true,"last_used":"2022-08-01T12:10:00Z"}
+            ]}"#,
+        )
+        .unwrap();
+
+        let loaded = DeviceCache::load(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded.devices()[0].name, "New");
+        assert_eq!(loaded.devices()[0].address, "80-3b-5c-c2-b1-7f");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn device_cache_keeps_the_fetched_order_and_every_field() {
+        let path = temp_path("order.json");
+        let connected = DeviceInfo {
+            connected: true,
+            rssi: Some(-56),
+            battery_levels: Some(BatteryInfo::from_buds(Some(80), Some(90), Some(50))),
+            active_output: true,
+            ..device("80-3b-5c-c2-b1-7f", 5)
+        };
+        let devices = vec![connected, device("5c-2e-fg-da-a3-43", 0)];
+
+        let mut cache = DeviceCache::new();
+        cache.update(&devices, &FixedClock(Utc::now()));
+        cache.store(&path).unwrap();
+
+        assert_eq!(cache.devices(), devices);
+        assert_eq!(DeviceCache::load(&path).unwrap().devices(), devices);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn device_cache_missing_file_is_empty() {
+        let loaded = DeviceCache::load(&temp_path("missing.json")).unwrap();
+
+        assert!(loaded.is_empty());
+    }
+
+    #[test]
+    fn device_cache_expires_after_ttl() {
+        let updated_at = Utc.with_ymd_and_hms(2022, 8, 1, 12, 0, 0).unwrap();
+        let ttl = Duration::from_secs(30);
+
+        let mut cache = DeviceCache::new();
+        assert!(cache.is_expired(ttl, &FixedClock(updated_at)));
+
+        cache.update(&[device("80-3b-5c-c2-b1-7f", 0)], &FixedClock(updated_at));
+
+        let fresh = FixedClock(updated_at + chrono::Duration::seconds(29));
+        let expired = FixedClock(updated_at + chrono::Duration::seconds(30));
+        assert!(!cache.is_expired(ttl, &fresh));
+        assert!(cache.is_expired(ttl, &expired));
+    }
+
+    #[test]
+    fn device_cache_keeps_updated_at_across_store_and_load() {
+        let path = temp_path("updated_at.json");
+        let updated_at = Utc.with_ymd_and_hms(2022, 8, 1, 12, 0, 0).unwrap();
+
+        let mut cache = DeviceCache::new();
+        cache.update(&[device("80-3b-5c-c2-b1-7f", 0)], &FixedClock(updated_at));
+        cache.store(&path).unwrap();
+
+        let loaded = DeviceCache::load(&path).unwrap();
+        assert_eq!(loaded, cache);
+        assert!(!loaded.is_expired(Duration::from_secs(60), &FixedClock(updated_at)));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn clear_data_files_removes_existing_files() {
+        let dir = temp_path("clear");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(DEVICE_CACHE_FILE), "{}").unwrap();
+        fs::write(dir.join("unrelated.txt"), "keep").unwrap();
+
+        let deleted = clear_data_files(&dir).unwrap();
+
+        assert_eq!(deleted, vec![dir.join(DEVICE_CACHE_FILE)]);
+        assert!(!dir.join(DEVICE_CACHE_FILE).exists());
+        assert!(dir.join("unrelated.txt").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn clear_data_files_skips_missing_files() {
+        let dir = temp_path("clear-missing");
+
+        assert!(clear_data_files(&dir).unwrap().is_empty());
+    }
+}
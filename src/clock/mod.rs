@@ -0,0 +1,41 @@
+use chrono::{DateTime, Utc};
+
+// Source of the current time, so time based behaviour can be tested against a
+// pinned time instead of the system clock.
+pub trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn fixed_clock_returns_pinned_time() {
+        let pinned = Utc.with_ymd_and_hms(2022, 8, 1, 12, 0, 0).unwrap();
+        let clock = FixedClock(pinned);
+
+        assert_eq!(clock.now(), pinned);
+        assert_eq!(clock.now(), pinned);
+    }
+}
@@ -0,0 +1,630 @@
+use std::{collections::HashMap, env, error::Error, fmt, fs, io, path::PathBuf, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use super::bluetooth::{Address, Backend, DeviceClass, DeviceFilters, DeviceInfo, SortOrder};
+use super::output::OutputFormat;
+
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 5;
+
+// Plain values have to come before tables for the config to serialize to TOML.
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct Config {
+    // When enabled, destructive actions like disconnecting require --confirm
+    pub safe_mode: bool,
+    // Seconds to wait for a connection to be verified
+    pub connect_timeout: Option<u64>,
+    // Turns Bluetooth on before connecting when it's off, like connect --power-on
+    pub power_on: bool,
+    // Checks connected devices report as connected, like connect --verify
+    pub verify_connect: bool,
+    // Case insensitive name pattern `list` uses when no devices are specified
+    // instead of only listing audio devices, an empty string lists every device
+    pub default_filter: Option<String>,
+    // Addresses or name patterns `list` always hides
+    pub exclude: Vec<String>,
+    // Bluetooth backend used when --backend isn't passed
+    pub backend: Option<Backend>,
+    // Path to the blueutil binary
+    pub blueutil_path: Option<String>,
+    // Seconds a blueutil command can run before it's killed
+    pub blueutil_timeout: Option<u64>,
+    // Seconds the device list is cached for when --cache-ttl isn't passed
+    pub cache_ttl: Option<u64>,
+    // Logs every backend command, like --audit-log
+    pub audit_log: bool,
+    // Output format used when --format isn't passed
+    pub format: Option<OutputFormat>,
+    // Sort order `list` uses when --sort isn't passed
+    pub sort: Option<SortOrder>,
+    // Shortcut `noise` runs with the mode as its input, instead of scripting
+    // Control Center
+    pub noise_shortcut: Option<String>,
+    // Named groups of device addresses
+    pub groups: HashMap<String, Vec<String>>,
+    // Per-device connect timeouts in seconds, keyed by address
+    pub connect_timeouts: HashMap<String, u64>,
+    // Nicknames for devices, mapping the alias to an address
+    pub aliases: HashMap<String, String>,
+    pub alfred: AlfredConfig,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct AlfredConfig {
+    // Subtitle templates for list items, see utilities::render_subtitle
+    pub connected_subtitle: Option<String>,
+    pub disconnected_subtitle: Option<String>,
+}
+
+impl Config {
+    // Loads the config file, falling back to an empty config if it doesn't exist.
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        match config_path() {
+            Some(path) => Self::load_from(&path),
+            None => Ok(Default::default()),
+        }
+    }
+
+    pub fn load_from(path: &PathBuf) -> Result<Self, Box<dyn Error>> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::from_toml(&contents),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Default::default()),
+            Err(err) => Err(Box::new(err)),
+        }
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    // Writes the config back to the config file. Comments in the existing file
+    // aren't preserved.
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        match config_path() {
+            Some(path) => self.save_to(&path),
+            None => Err(Box::new(ConfigError::new(
+                "Could not determine the config file path",
+            ))),
+        }
+    }
+
+    pub fn save_to(&self, path: &PathBuf) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(path, self.to_toml()?)?;
+
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> Result<String, Box<dyn Error>> {
+        Ok(toml::to_string(self)?)
+    }
+
+    // Looks up a dotted key like `alfred.connected_subtitle`, returning None for
+    // unknown or unset keys.
+    pub fn get_value(&self, key: &str) -> Result<Option<toml::Value>, Box<dyn Error>> {
+        let mut value = toml::Value::try_from(self)?;
+
+        for part in key.split('.') {
+            value = match value.get(part) {
+                Some(value) => value.clone(),
+                None => return Ok(None),
+            };
+        }
+
+        Ok(Some(value))
+    }
+
+    // Sets a dotted key from its TOML representation. Values that aren't valid
+    // TOML, like `AirPods` rather than `"AirPods"`, are taken as strings.
+    pub fn set_value(&mut self, key: &str, raw_value: &str) -> Result<(), Box<dyn Error>> {
+        let new_value =
+            match toml::from_str::<toml::value::Table>(&format!("value = {}", raw_value)) {
+                Ok(mut table) => table.remove("value").unwrap(),
+                Err(_) => toml::Value::String(raw_value.to_string()),
+            };
+
+        let mut config = toml::Value::try_from(&*self)?;
+        let parts = key.split('.').collect::<Vec<&str>>();
+        let (last, parents) = parts.split_last().unwrap();
+
+        let mut table = config.as_table_mut().unwrap();
+        for part in parents {
+            table = match table.get_mut(*part).and_then(|x| x.as_table_mut()) {
+                Some(table) => table,
+                None => {
+                    return Err(Box::new(ConfigError::new(&format!(
+                        "Unknown config key : '{}'",
+                        key
+                    ))))
+                }
+            };
+        }
+        table.insert(last.to_string(), new_value);
+
+        let updated: Config = config.try_into()?;
+        if updated.get_value(key)?.is_none() {
+            return Err(Box::new(ConfigError::new(&format!(
+                "Unknown config key : '{}'",
+                key
+            ))));
+        }
+
+        *self = updated;
+
+        Ok(())
+    }
+
+    // Aliases are matched case insensitively.
+    pub fn resolve_alias(&self, alias: &str) -> Option<&str> {
+        self.aliases
+            .iter()
+            .find(|(x, _)| x.to_lowercase() == alias.to_lowercase())
+            .map(|(_, address)| address.as_str())
+    }
+
+    pub fn alias_for(&self, address: &str) -> Option<&str> {
+        let address = Address::new(address);
+
+        self.aliases
+            .iter()
+            .find(|(_, x)| Address::new(x) == address)
+            .map(|(alias, _)| alias.as_str())
+    }
+
+    // Replaces the name of every device that has an alias with the alias.
+    pub fn apply_aliases(&self, devices: &mut [DeviceInfo]) {
+        for device in devices {
+            if let Some(alias) = self.alias_for(&device.address) {
+                device.name = alias.to_string();
+            }
+        }
+    }
+
+    // A device has at most one alias, so setting one replaces any existing alias
+    // for the same address.
+    pub fn set_alias(&mut self, alias: &str, address: &str) {
+        let normalized = Address::new(address);
+        self.aliases
+            .retain(|x, existing| x != alias && Address::new(existing) != normalized);
+        self.aliases
+            .insert(alias.to_string(), normalized.as_str().to_string());
+    }
+
+    // Returns false if there was no such alias.
+    pub fn remove_alias(&mut self, alias: &str) -> bool {
+        self.aliases.remove(alias).is_some()
+    }
+
+    // Returns the lowercased addresses of the devices in the named group.
+    pub fn resolve_group(&self, name: &str) -> Result<Vec<String>, ConfigError> {
+        match self.groups.get(name) {
+            Some(addresses) => Ok(addresses.iter().map(|x| x.to_lowercase()).collect()),
+            None => Err(ConfigError::new(&format!("Unknown group : '{}'", name))),
+        }
+    }
+
+    pub fn check_destructive_action(
+        &self,
+        action: &str,
+        confirmed: bool,
+    ) -> Result<(), ConfigError> {
+        if self.safe_mode && !confirmed {
+            Err(ConfigError::new(&format!(
+                "Safe mode is enabled, pass --confirm to {}",
+                action
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    // An address specific timeout wins over the command's timeout, which wins over
+    // the global config timeout.
+    pub fn connect_timeout_for(
+        &self,
+        address: &str,
+        command_timeout: Option<Duration>,
+    ) -> Duration {
+        let address = Address::new(address);
+        let device_timeout = self
+            .connect_timeouts
+            .iter()
+            .find(|(x, _)| Address::new(x) == address)
+            .map(|(_, timeout)| Duration::from_secs(*timeout));
+
+        device_timeout.or(command_timeout).unwrap_or_else(|| {
+            Duration::from_secs(self.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS))
+        })
+    }
+
+    // Groups double as profiles, sorted by name.
+    pub fn profiles(&self) -> Vec<(&str, &[String])> {
+        let mut profiles = self
+            .groups
+            .iter()
+            .map(|(name, addresses)| (name.as_str(), addresses.as_slice()))
+            .collect::<Vec<_>>();
+        profiles.sort();
+
+        profiles
+    }
+
+    pub fn group_filter(&self, name: &str) -> Result<DeviceFilters, ConfigError> {
+        Ok(DeviceFilters::SpecificAddresses {
+            addresses: self.resolve_group(name)?,
+        })
+    }
+
+    // The filter used when no devices are specified, audio devices unless a
+    // default_filter is set.
+    pub fn default_list_filter(&self) -> DeviceFilters {
+        match &self.default_filter {
+            Some(value) => DeviceFilters::Regex {
+                value: value.clone(),
+            },
+            None => DeviceFilters::DeviceClass(DeviceClass::Audio),
+        }
+    }
+}
+
+// AIRPOD_ALFRED_CONFIG overrides the default ~/.config/airpod-alfred/config.toml
+pub fn config_path() -> Option<PathBuf> {
+    if let Ok(path) = env::var("AIRPOD_ALFRED_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+
+    env::var("HOME").ok().map(|home| {
+        PathBuf::from(home)
+            .join(".config")
+            .join("airpod-alfred")
+            .join("config.toml")
+    })
+}
+
+// Where cache and state files are kept. AIRPOD_ALFRED_DATA_DIR overrides the
+// workflow data directory Alfred provides, falling back to
+// ~/Library/Application Support/airpod-alfred
+pub fn data_dir() -> Option<PathBuf> {
+    if let Ok(path) = env::var("AIRPOD_ALFRED_DATA_DIR") {
+        return Some(PathBuf::from(path));
+    }
+
+    if let Ok(path) = env::var("alfred_workflow_data") {
+        return Some(PathBuf::from(path));
+    }
+
+    env::var("HOME").ok().map(|home| {
+        PathBuf::from(home)
+            .join("Library")
+            .join("Application Support")
+            .join("airpod-alfred")
+    })
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    details: String,
+}
+
+impl ConfigError {
+    fn new(msg: &str) -> ConfigError {
+        ConfigError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+[groups]
+work = ["80-3B-5C-C2-B1-7F", "5c-2e-fg-da-a3-43"]
+"#;
+
+    #[test]
+    fn config_parses_groups() {
+        let config = Config::from_toml(CONFIG).unwrap();
+
+        assert_eq!(config.groups.len(), 1);
+        assert_eq!(config.groups["work"].len(), 2);
+    }
+
+    #[test]
+    fn config_defaults_when_empty() {
+        assert_eq!(Config::from_toml("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn config_missing_file_is_default() {
+        let config = Config::load_from(&PathBuf::from("/nonexistent/config.toml")).unwrap();
+
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn config_resolves_group_to_lowercase_addresses() {
+        let config = Config::from_toml(CONFIG).unwrap();
+
+        assert_eq!(
+            config.resolve_group("work").unwrap(),
+            vec![
+                String::from("80-3b-5c-c2-b1-7f"),
+                String::from("5c-2e-fg-da-a3-43")
+            ]
+        );
+    }
+
+    #[test]
+    fn config_group_filter_targets_group_addresses() {
+        let config = Config::from_toml(CONFIG).unwrap();
+
+        assert_eq!(
+            config.group_filter("work").unwrap(),
+            DeviceFilters::SpecificAddresses {
+                addresses: vec![
+                    String::from("80-3b-5c-c2-b1-7f"),
+                    String::from("5c-2e-fg-da-a3-43")
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn config_profiles_are_sorted_groups() {
+        let config = Config::from_toml(
+            r#"
+[groups]
+work = ["80-3b-5c-c2-b1-7f"]
+home = ["5c-2e-fg-da-a3-43", "80-3b-5c-c2-b1-7f"]
+"#,
+        )
+        .unwrap();
+
+        let names = config
+            .profiles()
+            .iter()
+            .map(|(name, addresses)| (*name, addresses.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec![("home", 2), ("work", 1)]);
+    }
+
+    #[test]
+    fn config_errors_on_unknown_group() {
+        let config = Config::from_toml(CONFIG).unwrap();
+
+        let err = config.resolve_group("home").unwrap_err();
+        assert_eq!(err.to_string(), "Unknown group : 'home'");
+        assert!(config.group_filter("home").is_err());
+    }
+
+    #[test]
+    fn config_parses_alfred_subtitles() {
+        let config = Config::from_toml(
+            r#"
+[alfred]
+connected_subtitle = "{action} · {battery}"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.alfred.connected_subtitle,
+            Some(String::from("{action} · {battery}"))
+        );
+        assert_eq!(config.alfred.disconnected_subtitle, None);
+    }
+
+    #[test]
+    fn config_safe_mode_requires_confirmation() {
+        let config = Config::from_toml("safe_mode = true").unwrap();
+
+        let err = config
+            .check_destructive_action("disconnect", false)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Safe mode is enabled, pass --confirm to disconnect"
+        );
+        assert!(config.check_destructive_action("disconnect", true).is_ok());
+    }
+
+    #[test]
+    fn config_without_safe_mode_allows_destructive_actions() {
+        let config = Config::from_toml(CONFIG).unwrap();
+
+        assert!(!config.safe_mode);
+        assert!(config.check_destructive_action("disconnect", false).is_ok());
+    }
+
+    #[test]
+    fn config_connect_timeout_uses_address_override() {
+        let config = Config::from_toml(
+            r#"
+connect_timeout = 8
+
+[connect_timeouts]
+"80:3B:5C:C2:B1:7F" = 20
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.connect_timeout_for("80-3b-5c-c2-b1-7f", None),
+            Duration::from_secs(20)
+        );
+        assert_eq!(
+            config.connect_timeout_for("80-3b-5c-c2-b1-7f", Some(Duration::from_secs(3))),
+            Duration::from_secs(20)
+        );
+    }
+
+    #[test]
+    fn config_connect_timeout_falls_back_to_global() {
+        let config = Config::from_toml(
+            r#"
+connect_timeout = 8
+
+[connect_timeouts]
+"80:3B:5C:C2:B1:7F" = 20
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.connect_timeout_for("5c-2e-fg-da-a3-43", Some(Duration::from_secs(3))),
+            Duration::from_secs(3)
+        );
+        assert_eq!(
+            config.connect_timeout_for("5c-2e-fg-da-a3-43", None),
+            Duration::from_secs(8)
+        );
+        assert_eq!(
+            Config::default().connect_timeout_for("5c-2e-fg-da-a3-43", None),
+            Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS)
+        );
+    }
+
+    #[test]
+    fn config_resolves_aliases_case_insensitively() {
+        let config = Config::from_toml(
+            r#"
+[aliases]
+"Work Pods" = "80-3b-5c-c2-b1-7f"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.resolve_alias("work pods"), Some("80-3b-5c-c2-b1-7f"));
+        assert_eq!(config.resolve_alias("home pods"), None);
+        assert_eq!(config.alias_for("80:3B:5C:C2:B1:7F"), Some("Work Pods"));
+    }
+
+    #[test]
+    fn config_applies_aliases_to_device_names() {
+        let mut config = Config::default();
+        config.set_alias("work pods", "80:3B:5C:C2:B1:7F");
+
+        let mut devices = vec![
+            DeviceInfo {
+                name: String::from("AirPods Pro"),
+                address: String::from("80-3b-5c-c2-b1-7f"),
+                ..Default::default()
+            },
+            DeviceInfo {
+                name: String::from("Keyboard"),
+                address: String::from("5c-2e-fg-da-a3-43"),
+                ..Default::default()
+            },
+        ];
+        config.apply_aliases(&mut devices);
+
+        assert_eq!(devices[0].name, "work pods");
+        assert_eq!(devices[1].name, "Keyboard");
+    }
+
+    #[test]
+    fn config_set_alias_replaces_existing_alias_for_address() {
+        let mut config = Config::default();
+        config.set_alias("old", "80-3b-5c-c2-b1-7f");
+        config.set_alias("new", "80:3B:5C:C2:B1:7F");
+
+        assert_eq!(config.aliases.len(), 1);
+        assert_eq!(config.resolve_alias("new"), Some("80-3b-5c-c2-b1-7f"));
+
+        assert!(config.remove_alias("new"));
+        assert!(!config.remove_alias("new"));
+        assert!(config.aliases.is_empty());
+    }
+
+    #[test]
+    fn config_round_trips_through_toml() {
+        let mut config = Config::from_toml(CONFIG).unwrap();
+        config.safe_mode = true;
+        config.connect_timeout = Some(8);
+        config.set_alias("work pods", "80-3b-5c-c2-b1-7f");
+        config.alfred.connected_subtitle = Some(String::from("{action}"));
+
+        let path = env::temp_dir()
+            .join(format!("airpod-alfred-config-test-{}", std::process::id()))
+            .join("config.toml");
+        config.save_to(&path).unwrap();
+
+        assert_eq!(Config::load_from(&path).unwrap(), config);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn config_get_value_by_dotted_key() {
+        let config = Config::from_toml(
+            r#"
+safe_mode = true
+
+[alfred]
+connected_subtitle = "{action}"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.get_value("safe_mode").unwrap(),
+            Some(toml::Value::Boolean(true))
+        );
+        assert_eq!(
+            config.get_value("alfred.connected_subtitle").unwrap(),
+            Some(toml::Value::String(String::from("{action}")))
+        );
+        assert_eq!(config.get_value("format").unwrap(), None);
+        assert_eq!(config.get_value("unknown.key").unwrap(), None);
+    }
+
+    #[test]
+    fn config_set_value_parses_toml_values() {
+        let mut config = Config::default();
+
+        config.set_value("safe_mode", "true").unwrap();
+        config.set_value("connect_timeout", "8").unwrap();
+        config.set_value("format", "table").unwrap();
+        config.set_value("sort", "\"frecency\"").unwrap();
+        config.set_value("default_filter", "AirPods Pro").unwrap();
+        config
+            .set_value("alfred.disconnected_subtitle", "{address}")
+            .unwrap();
+
+        assert!(config.safe_mode);
+        assert_eq!(config.connect_timeout, Some(8));
+        assert_eq!(config.format, Some(OutputFormat::Table));
+        assert_eq!(config.sort, Some(SortOrder::Frecency));
+        assert_eq!(config.default_filter, Some(String::from("AirPods Pro")));
+        assert_eq!(
+            config.alfred.disconnected_subtitle,
+            Some(String::from("{address}"))
+        );
+    }
+
+    #[test]
+    fn config_set_value_rejects_invalid_keys_and_values() {
+        let mut config = Config::default();
+
+        assert!(config.set_value("unknown", "1").is_err());
+        assert!(config.set_value("missing.key", "1").is_err());
+        assert!(config.set_value("connect_timeout", "soon").is_err());
+        assert!(config.set_value("format", "yaml").is_err());
+        assert_eq!(config, Config::default());
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+
+use super::bluetooth::{BluetoothClient, ConnectionWatcher, DeviceInfo, DeviceListOptions};
+use super::clock::Clock;
+
+pub const LAUNCHD_LABEL: &str = "com.sendhil.airpod-alfred";
+
+// A connected device is idle once its recent access date is older than the
+// timeout. Devices without a recent access date are never considered idle.
+pub fn is_idle(device: &DeviceInfo, idle_timeout: Duration, now: DateTime<Utc>) -> bool {
+    if !device.connected {
+        return false;
+    }
+
+    let idle_timeout = match chrono::Duration::from_std(idle_timeout) {
+        Ok(idle_timeout) => idle_timeout,
+        Err(_) => return false,
+    };
+
+    match device.last_used {
+        Some(last_used) => now.signed_duration_since(last_used) > idle_timeout,
+        None => false,
+    }
+}
+
+pub fn idle_devices(
+    devices: &[DeviceInfo],
+    idle_timeout: Duration,
+    now: DateTime<Utc>,
+) -> Vec<&DeviceInfo> {
+    devices
+        .iter()
+        .filter(|x| is_idle(x, idle_timeout, now))
+        .collect()
+}
+
+// Logs connection changes and disconnects idle devices every poll interval.
+// Runs until the process is killed.
+pub fn run_idle_disconnect(
+    client: &BluetoothClient,
+    clock: &dyn Clock,
+    idle_timeout: Duration,
+    poll_interval: Duration,
+) {
+    let mut watcher = ConnectionWatcher::new();
+
+    loop {
+        match client.get_device_list(DeviceListOptions::new_default_all_devices()) {
+            Ok(devices) => {
+                for event in watcher.update(&devices, clock.now()) {
+                    info!(
+                        "{} ({}) {}",
+                        event.name,
+                        event.address,
+                        event.state.as_str()
+                    );
+                }
+
+                for device in idle_devices(&devices, idle_timeout, clock.now()) {
+                    info!(
+                        "Disconnecting idle device {} ({})",
+                        device.name, device.address
+                    );
+
+                    if let Err(err) = client.disconnect_from_device(&device.address) {
+                        warn!("Failed to disconnect {} : {}", device.address, err);
+                    }
+                }
+            }
+            Err(err) => warn!("Failed to list devices : {}", err),
+        }
+
+        thread::sleep(poll_interval);
+    }
+}
+
+// ~/Library/LaunchAgents/<label>.plist
+pub fn launch_agent_path() -> Option<PathBuf> {
+    env::var("HOME").ok().map(|home| {
+        PathBuf::from(home)
+            .join("Library")
+            .join("LaunchAgents")
+            .join(format!("{}.plist", LAUNCHD_LABEL))
+    })
+}
+
+// A launch agent that starts `program` with `args` at login and restarts it if
+// it exits.
+pub fn launchd_plist(program: &Path, args: &[String]) -> String {
+    let arguments = std::iter::once(program.display().to_string())
+        .chain(args.iter().cloned())
+        .map(|x| format!("        <string>{}</string>\n", escape_xml(&x)))
+        .collect::<String>();
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+{}    </array>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <true/>
+</dict>
+</plist>
+"#,
+        LAUNCHD_LABEL, arguments
+    )
+}
+
+// Writes the plist to the launch agent path, creating the directory if needed.
+pub fn install_launch_agent(path: &Path, plist: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(path, plist)
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2022, 8, 1, 12, 0, 0).unwrap()
+    }
+
+    fn device(name: &str, connected: bool, minutes_ago: Option<i64>) -> DeviceInfo {
+        DeviceInfo {
+            name: String::from(name),
+            address: format!("{}-address", name),
+            connected,
+            last_used: minutes_ago.map(|x| now() - chrono::Duration::minutes(x)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn is_idle_only_past_the_timeout() {
+        let timeout = Duration::from_secs(30 * 60);
+
+        assert!(!is_idle(&device("recent", true, Some(5)), timeout, now()));
+        assert!(!is_idle(&device("edge", true, Some(30)), timeout, now()));
+        assert!(is_idle(&device("stale", true, Some(31)), timeout, now()));
+    }
+
+    #[test]
+    fn is_idle_ignores_disconnected_devices() {
+        let timeout = Duration::from_secs(60);
+
+        assert!(!is_idle(&device("stale", false, Some(120)), timeout, now()));
+    }
+
+    #[test]
+    fn is_idle_ignores_devices_without_a_recent_access_date() {
+        let timeout = Duration::from_secs(60);
+
+        assert!(!is_idle(&device("unknown", true, None), timeout, now()));
+    }
+
+    #[test]
+    fn idle_devices_selects_stale_connected_devices() {
+        let devices = vec![
+            device("recent", true, Some(1)),
+            device("stale", true, Some(90)),
+            device("disconnected", false, Some(90)),
+            device("unknown", true, None),
+        ];
+
+        let idle = idle_devices(&devices, Duration::from_secs(60 * 60), now());
+
+        assert_eq!(idle.len(), 1);
+        assert_eq!(idle[0].name, "stale");
+    }
+
+    #[test]
+    fn launchd_plist_includes_program_and_arguments() {
+        let plist = launchd_plist(
+            Path::new("/usr/local/bin/airpod-alfred"),
+            &[String::from("daemon"), String::from("<&>")],
+        );
+
+        assert!(plist.contains(&format!("<string>{}</string>", LAUNCHD_LABEL)));
+        assert!(plist.contains(
+            "        <string>/usr/local/bin/airpod-alfred</string>\n        <string>daemon</string>\n        <string>&lt;&amp;&gt;</string>\n    </array>"
+        ));
+    }
+
+    #[test]
+    fn install_launch_agent_creates_the_directory() {
+        let dir = env::temp_dir().join(format!("airpod-alfred-launchd-{}", std::process::id()));
+        let path = dir.join("LaunchAgents").join("agent.plist");
+
+        install_launch_agent(&path, "plist").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "plist");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    error::Error,
+    ffi::{c_void, CStr, CString},
+    os::raw::c_char,
+    ptr, slice,
+    sync::Once,
+    time::Duration,
+};
+
+use objc::{
+    class,
+    declare::ClassDecl,
+    msg_send,
+    rc::autoreleasepool,
+    runtime::{Class, Object, Sel},
+    sel, sel_impl,
+};
+
+use super::{airpods_model, DiscoveredDevice, DiscoveryError};
+
+const DELEGATE_CLASS: &str = "AirpodAlfredScanDelegate";
+const STATE_IVAR: &str = "scanState";
+
+// CBManagerState values
+const CB_MANAGER_STATE_UNSUPPORTED: isize = 2;
+const CB_MANAGER_STATE_UNAUTHORIZED: isize = 3;
+const CB_MANAGER_STATE_POWERED_OFF: isize = 4;
+const CB_MANAGER_STATE_POWERED_ON: isize = 5;
+
+// The value of CBAdvertisementDataManufacturerDataKey
+const MANUFACTURER_DATA_KEY: &str = "kCBAdvDataManufacturerData";
+
+#[link(name = "Foundation", kind = "framework")]
+extern "C" {}
+
+#[link(name = "CoreBluetooth", kind = "framework")]
+extern "C" {}
+
+#[derive(Default)]
+struct ScanState {
+    manager_state: isize,
+    devices: Vec<DiscoveredDevice>,
+}
+
+// Runs the main run loop for the duration while a CBCentralManager scans,
+// collecting every AirPods advertisement it sees.
+pub fn scan(duration: Duration) -> Result<Vec<DiscoveredDevice>, Box<dyn Error>> {
+    let mut state = ScanState::default();
+
+    autoreleasepool(|| unsafe {
+        let delegate: *mut Object = msg_send![delegate_class(), new];
+        (*delegate).set_ivar(STATE_IVAR, &mut state as *mut ScanState as *mut c_void);
+
+        // A nil queue delivers events on the main queue, which the run loop drains
+        let queue: *mut Object = ptr::null_mut();
+        let central: *mut Object = msg_send![class!(CBCentralManager), alloc];
+        let central: *mut Object = msg_send![central, initWithDelegate: delegate queue: queue];
+
+        let run_loop: *mut Object = msg_send![class!(NSRunLoop), currentRunLoop];
+        let until: *mut Object = msg_send![
+            class!(NSDate),
+            dateWithTimeIntervalSinceNow: duration.as_secs_f64()
+        ];
+        let _: () = msg_send![run_loop, runUntilDate: until];
+
+        let _: () = msg_send![central, stopScan];
+        let _: () = msg_send![central, release];
+        let _: () = msg_send![delegate, release];
+    });
+
+    let message = match state.manager_state {
+        CB_MANAGER_STATE_UNSUPPORTED => Some("This Mac doesn't support Bluetooth LE"),
+        CB_MANAGER_STATE_UNAUTHORIZED => Some(
+            "Bluetooth access was denied, allow it in System Settings > Privacy & Security > Bluetooth",
+        ),
+        CB_MANAGER_STATE_POWERED_OFF => Some("Bluetooth is turned off"),
+        _ => None,
+    };
+
+    match message {
+        Some(message) => Err(Box::new(DiscoveryError::new(message))),
+        None => Ok(state.devices),
+    }
+}
+
+fn delegate_class() -> &'static Class {
+    static REGISTER: Once = Once::new();
+
+    REGISTER.call_once(|| {
+        let mut decl = ClassDecl::new(DELEGATE_CLASS, class!(NSObject)).unwrap();
+        decl.add_ivar::<*mut c_void>(STATE_IVAR);
+
+        unsafe {
+            decl.add_method(
+                sel!(centralManagerDidUpdateState:),
+                did_update_state as extern "C" fn(&Object, Sel, *mut Object),
+            );
+            decl.add_method(
+                sel!(centralManager:didDiscoverPeripheral:advertisementData:RSSI:),
+                did_discover
+                    as extern "C" fn(
+                        &Object,
+                        Sel,
+                        *mut Object,
+                        *mut Object,
+                        *mut Object,
+                        *mut Object,
+                    ),
+            );
+        }
+
+        decl.register();
+    });
+
+    Class::get(DELEGATE_CLASS).unwrap()
+}
+
+unsafe fn scan_state(this: &Object) -> &mut ScanState {
+    let state: *mut c_void = *this.get_ivar(STATE_IVAR);
+    &mut *(state as *mut ScanState)
+}
+
+extern "C" fn did_update_state(this: &Object, _: Sel, central: *mut Object) {
+    unsafe {
+        let manager_state: isize = msg_send![central, state];
+        scan_state(this).manager_state = manager_state;
+
+        if manager_state == CB_MANAGER_STATE_POWERED_ON {
+            let nil: *mut Object = ptr::null_mut();
+            let _: () = msg_send![central, scanForPeripheralsWithServices: nil options: nil];
+        }
+    }
+}
+
+extern "C" fn did_discover(
+    this: &Object,
+    _: Sel,
+    _central: *mut Object,
+    peripheral: *mut Object,
+    advertisement_data: *mut Object,
+    rssi: *mut Object,
+) {
+    unsafe {
+        let key = match CString::new(MANUFACTURER_DATA_KEY) {
+            Ok(key) => key,
+            Err(_) => return,
+        };
+        let key: *mut Object = msg_send![class!(NSString), stringWithUTF8String: key.as_ptr()];
+        let data: *mut Object = msg_send![advertisement_data, objectForKey: key];
+        if data.is_null() {
+            return;
+        }
+
+        let length: usize = msg_send![data, length];
+        let bytes: *const u8 = msg_send![data, bytes];
+        if bytes.is_null() {
+            return;
+        }
+
+        let model = match airpods_model(slice::from_raw_parts(bytes, length)) {
+            Some(model) => model,
+            None => return,
+        };
+
+        let identifier: *mut Object = msg_send![peripheral, identifier];
+        let identifier: *mut Object = msg_send![identifier, UUIDString];
+        let name: *mut Object = msg_send![peripheral, name];
+        let rssi: isize = msg_send![rssi, integerValue];
+
+        scan_state(this).devices.push(DiscoveredDevice {
+            identifier: from_nsstring(identifier).unwrap_or_default(),
+            name: from_nsstring(name),
+            rssi: rssi as i16,
+            model,
+            address: None,
+        });
+    }
+}
+
+unsafe fn from_nsstring(value: *mut Object) -> Option<String> {
+    if value.is_null() {
+        return None;
+    }
+
+    let bytes: *const c_char = msg_send![value, UTF8String];
+    if bytes.is_null() {
+        return None;
+    }
+
+    Some(CStr::from_ptr(bytes).to_string_lossy().into_owned())
+}
@@ -0,0 +1,263 @@
+#[cfg(target_os = "macos")]
+mod corebluetooth;
+
+use std::{collections::HashMap, error::Error, fmt, time::Duration};
+
+use super::bluetooth::DeviceInfo;
+
+// Opens the Bluetooth settings, where nearby AirPods in pairing mode can be paired.
+pub const BLUETOOTH_SETTINGS_URL: &str = "x-apple.systempreferences:com.apple.BluetoothSettings";
+
+// Apple's Bluetooth company identifier, little endian as it's advertised
+const APPLE_COMPANY_ID: [u8; 2] = [0x4c, 0x00];
+// Continuity message type AirPods advertise while the case is open
+const PROXIMITY_PAIRING_TYPE: u8 = 0x07;
+
+// Shown for devices found by an inquiry that don't have a name yet
+const INQUIRY_MODEL: &str = "Bluetooth device";
+
+// A device found while scanning for Bluetooth LE advertisements or by a classic
+// inquiry. CoreBluetooth hides MAC addresses, so scanned devices are only
+// identified by a per-Mac UUID and have to be paired in the Bluetooth settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveredDevice {
+    pub identifier: String,
+    pub name: Option<String>,
+    pub rssi: i16,
+    pub model: &'static str,
+    // Set for devices found by an inquiry, which can be paired directly
+    pub address: Option<String>,
+}
+
+impl DiscoveredDevice {
+    pub fn from_inquiry(device: DeviceInfo) -> Self {
+        DiscoveredDevice {
+            identifier: device.address.clone(),
+            name: Some(device.name).filter(|x| !x.is_empty()),
+            rssi: device.rssi.unwrap_or(i16::MIN),
+            model: INQUIRY_MODEL,
+            address: Some(device.address),
+        }
+    }
+
+    // The advertised name, falling back to the model.
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(self.model)
+    }
+}
+
+// The AirPods model from the manufacturer data of a proximity pairing
+// advertisement, None for anything else.
+pub fn airpods_model(manufacturer_data: &[u8]) -> Option<&'static str> {
+    if manufacturer_data.len() < 7
+        || manufacturer_data[..2] != APPLE_COMPANY_ID
+        || manufacturer_data[2] != PROXIMITY_PAIRING_TYPE
+    {
+        return None;
+    }
+
+    match (manufacturer_data[5], manufacturer_data[6]) {
+        (0x02, 0x20) => Some("AirPods"),
+        (0x0f, 0x20) => Some("AirPods (2nd generation)"),
+        (0x13, 0x20) => Some("AirPods (3rd generation)"),
+        (0x0e, 0x20) => Some("AirPods Pro"),
+        (0x14, 0x20) => Some("AirPods Pro (2nd generation)"),
+        (0x0a, 0x20) => Some("AirPods Max"),
+        _ => None,
+    }
+}
+
+// Keeps one entry per device, with its strongest signal, nearest first.
+pub fn dedupe_discovered(devices: Vec<DiscoveredDevice>) -> Vec<DiscoveredDevice> {
+    let mut strongest: HashMap<String, DiscoveredDevice> = HashMap::new();
+
+    for device in devices {
+        match strongest.get(&device.identifier) {
+            Some(existing) if existing.rssi >= device.rssi => {}
+            _ => {
+                strongest.insert(device.identifier.clone(), device);
+            }
+        }
+    }
+
+    let mut devices = strongest.into_values().collect::<Vec<DiscoveredDevice>>();
+    devices.sort_by(|a, b| b.rssi.cmp(&a.rssi).then(a.identifier.cmp(&b.identifier)));
+
+    devices
+}
+
+// Drops discovered devices that share a name with a paired device, as paired
+// AirPods advertise too while their case is open.
+pub fn unpaired(discovered: Vec<DiscoveredDevice>, paired: &[DeviceInfo]) -> Vec<DiscoveredDevice> {
+    discovered
+        .into_iter()
+        .filter(|x| !paired.iter().any(|device| device.name == x.display_name()))
+        .collect()
+}
+
+// Devices found by an inquiry, then scanned devices that the inquiry didn't also
+// find, as the inquiry's can be paired without opening the Bluetooth settings.
+pub fn with_inquiry_results(
+    scanned: Vec<DiscoveredDevice>,
+    inquiry: Vec<DeviceInfo>,
+) -> Vec<DiscoveredDevice> {
+    let inquiry = inquiry
+        .into_iter()
+        .map(DiscoveredDevice::from_inquiry)
+        .collect::<Vec<DiscoveredDevice>>();
+    let scanned = scanned
+        .into_iter()
+        .filter(|x| {
+            !inquiry
+                .iter()
+                .any(|y| y.name.as_deref() == Some(x.display_name()))
+        })
+        .collect::<Vec<DiscoveredDevice>>();
+
+    [inquiry, scanned].concat()
+}
+
+// Scans for nearby AirPods advertising over Bluetooth LE, which includes ones
+// that aren't paired with this Mac yet.
+#[cfg(target_os = "macos")]
+pub fn scan_for_airpods(duration: Duration) -> Result<Vec<DiscoveredDevice>, Box<dyn Error>> {
+    Ok(dedupe_discovered(corebluetooth::scan(duration)?))
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn scan_for_airpods(_duration: Duration) -> Result<Vec<DiscoveredDevice>, Box<dyn Error>> {
+    Err(Box::new(DiscoveryError::new(
+        "Discovering nearby AirPods needs CoreBluetooth, which is only available on macOS",
+    )))
+}
+
+#[derive(Debug)]
+pub struct DiscoveryError {
+    details: String,
+}
+
+impl DiscoveryError {
+    fn new(msg: &str) -> DiscoveryError {
+        DiscoveryError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for DiscoveryError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn discovered(identifier: &str, rssi: i16) -> DiscoveredDevice {
+        DiscoveredDevice {
+            identifier: String::from(identifier),
+            name: None,
+            rssi,
+            model: "AirPods Pro",
+            address: None,
+        }
+    }
+
+    #[test]
+    fn airpods_model_reads_proximity_pairing_messages() {
+        let data = [
+            0x4c, 0x00, 0x07, 0x19, 0x01, 0x0e, 0x20, 0x2b, 0x99, 0x8f, 0x01,
+        ];
+
+        assert_eq!(airpods_model(&data), Some("AirPods Pro"));
+    }
+
+    #[test]
+    fn airpods_model_ignores_other_advertisements() {
+        // Another company
+        assert_eq!(
+            airpods_model(&[0x06, 0x00, 0x07, 0x19, 0x01, 0x0e, 0x20]),
+            None
+        );
+        // A different continuity message
+        assert_eq!(
+            airpods_model(&[0x4c, 0x00, 0x10, 0x05, 0x01, 0x0e, 0x20]),
+            None
+        );
+        // An unknown model
+        assert_eq!(
+            airpods_model(&[0x4c, 0x00, 0x07, 0x19, 0x01, 0x55, 0x55]),
+            None
+        );
+        assert_eq!(airpods_model(&[0x4c, 0x00, 0x07]), None);
+    }
+
+    #[test]
+    fn dedupe_discovered_keeps_strongest_signal_nearest_first() {
+        let devices = dedupe_discovered(vec![
+            discovered("a", -80),
+            discovered("b", -60),
+            discovered("a", -50),
+            discovered("b", -70),
+        ]);
+
+        assert_eq!(devices, vec![discovered("a", -50), discovered("b", -60)]);
+    }
+
+    #[test]
+    fn unpaired_drops_devices_named_like_paired_ones() {
+        let mut named = discovered("a", -50);
+        named.name = Some(String::from("AirPods Pro"));
+        let mut other = discovered("b", -60);
+        other.name = Some(String::from("Sam's AirPods"));
+
+        let paired = [DeviceInfo {
+            name: String::from("AirPods Pro"),
+            ..Default::default()
+        }];
+
+        assert_eq!(unpaired(vec![named, other.clone()], &paired), vec![other]);
+    }
+
+    #[test]
+    fn with_inquiry_results_prefers_devices_that_can_be_paired_directly() {
+        let mut scanned = discovered("a", -50);
+        scanned.name = Some(String::from("AirPods Pro"));
+        let mut other = discovered("b", -60);
+        other.name = Some(String::from("Sam's AirPods"));
+
+        let inquiry = vec![DeviceInfo {
+            name: String::from("AirPods Pro"),
+            address: String::from("80-3b-5c-c2-b1-7f"),
+            rssi: Some(-55),
+            ..Default::default()
+        }];
+
+        let devices = with_inquiry_results(vec![scanned, other.clone()], inquiry);
+        assert_eq!(
+            devices,
+            vec![
+                DiscoveredDevice {
+                    identifier: String::from("80-3b-5c-c2-b1-7f"),
+                    name: Some(String::from("AirPods Pro")),
+                    rssi: -55,
+                    model: INQUIRY_MODEL,
+                    address: Some(String::from("80-3b-5c-c2-b1-7f")),
+                },
+                other
+            ]
+        );
+    }
+
+    #[test]
+    fn display_name_falls_back_to_the_model() {
+        let mut device = discovered("a", -50);
+        assert_eq!(device.display_name(), "AirPods Pro");
+
+        device.name = Some(String::from("Sam's AirPods"));
+        assert_eq!(device.display_name(), "Sam's AirPods");
+    }
+}
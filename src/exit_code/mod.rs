@@ -0,0 +1,64 @@
+use std::error::Error;
+
+use super::bluetooth::{BluetoothError, ErrorKind};
+
+// Process exit codes, so Alfred and scripts can branch on why a command failed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExitCode {
+    Success = 0,
+    Failure = 1,
+    DeviceNotFound = 2,
+    BlueutilMissing = 3,
+    ConnectFailed = 4,
+    Timeout = 5,
+    Unsupported = 6,
+    InvalidAddress = 7,
+}
+
+impl ExitCode {
+    pub fn from_error(err: &(dyn Error + 'static)) -> Self {
+        match err.downcast_ref::<BluetoothError>() {
+            Some(err) => ExitCode::from_kind(err.kind()),
+            None => ExitCode::Failure,
+        }
+    }
+
+    pub fn from_kind(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::Other => ExitCode::Failure,
+            ErrorKind::DeviceNotFound => ExitCode::DeviceNotFound,
+            ErrorKind::BlueutilMissing => ExitCode::BlueutilMissing,
+            ErrorKind::ConnectFailed => ExitCode::ConnectFailed,
+            ErrorKind::DisconnectFailed => ExitCode::Failure,
+            ErrorKind::Timeout => ExitCode::Timeout,
+            ErrorKind::Unsupported => ExitCode::Unsupported,
+            ErrorKind::InvalidAddress => ExitCode::InvalidAddress,
+        }
+    }
+
+    pub fn code(&self) -> i32 {
+        *self as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_code_from_error_kinds() {
+        assert_eq!(ExitCode::from_kind(ErrorKind::Other), ExitCode::Failure);
+        assert_eq!(ExitCode::from_kind(ErrorKind::Timeout).code(), 5);
+        assert_eq!(
+            ExitCode::from_kind(ErrorKind::DeviceNotFound),
+            ExitCode::DeviceNotFound
+        );
+    }
+
+    #[test]
+    fn exit_code_defaults_to_failure_for_other_errors() {
+        let err: Box<dyn Error> = Box::from("something else");
+
+        assert_eq!(ExitCode::from_error(err.as_ref()), ExitCode::Failure);
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    error::Error,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::bluetooth::{Address, BluetoothError};
+use super::config;
+
+pub const HISTORY_FILE: &str = "history.jsonl";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryAction {
+    Connect,
+    Disconnect,
+}
+
+impl HistoryAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HistoryAction::Connect => "connect",
+            HistoryAction::Disconnect => "disconnect",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub timestamp: DateTime<Utc>,
+    pub action: HistoryAction,
+    pub address: String,
+    pub succeeded: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl HistoryEntry {
+    pub fn new(
+        timestamp: DateTime<Utc>,
+        action: HistoryAction,
+        address: &str,
+        result: &Result<(), BluetoothError>,
+    ) -> Self {
+        HistoryEntry {
+            timestamp,
+            action,
+            address: address.to_string(),
+            succeeded: result.is_ok(),
+            error: result.as_ref().err().map(|err| err.to_string()),
+        }
+    }
+}
+
+// Every connect and disconnect, stored one JSON object per line so recording an
+// entry is a cheap append rather than a rewrite of the whole file.
+#[derive(Debug, Clone)]
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    pub fn new(path: PathBuf) -> Self {
+        History { path }
+    }
+
+    pub fn default_path() -> Option<PathBuf> {
+        config::data_dir().map(|dir| dir.join(HISTORY_FILE))
+    }
+
+    pub fn record(&self, entry: &HistoryEntry) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+
+        Ok(())
+    }
+
+    // Loads every entry, oldest first. A missing file is an empty history and
+    // lines that can't be parsed are skipped so one bad write doesn't lose the rest.
+    pub fn load(&self) -> Result<Vec<HistoryEntry>, Box<dyn Error>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(Box::new(err)),
+        };
+
+        Ok(contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .filter_map(|(index, line)| match serde_json::from_str(line) {
+                Ok(entry) => Some(entry),
+                Err(err) => {
+                    warn!("Skipping history line {} : {}", index + 1, err);
+                    None
+                }
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct HistoryQuery {
+    pub address: Option<String>,
+    pub failures_only: bool,
+    pub limit: Option<usize>,
+}
+
+// The entries matching the query, newest first.
+pub fn query<'a>(entries: &'a [HistoryEntry], query: &HistoryQuery) -> Vec<&'a HistoryEntry> {
+    let address = query.address.as_deref().map(Address::new);
+
+    entries
+        .iter()
+        .rev()
+        .filter(|x| match &address {
+            Some(address) => Address::new(&x.address) == *address,
+            None => true,
+        })
+        .filter(|x| !query.failures_only || !x.succeeded)
+        .take(query.limit.unwrap_or(usize::MAX))
+        .collect()
+}
+
+// The most recent successful connect, i.e. the last device that was in use.
+pub fn last_connected(entries: &[HistoryEntry]) -> Option<&HistoryEntry> {
+    entries
+        .iter()
+        .rev()
+        .find(|x| x.action == HistoryAction::Connect && x.succeeded)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2022, 8, 1, 12, 0, 0).unwrap()
+    }
+
+    fn entry(minutes: i64, address: &str, succeeded: bool) -> HistoryEntry {
+        let result = if succeeded {
+            Ok(())
+        } else {
+            Err(BluetoothError::Other(String::from("Failed to connect")))
+        };
+
+        HistoryEntry::new(
+            now() + Duration::minutes(minutes),
+            HistoryAction::Connect,
+            address,
+            &result,
+        )
+    }
+
+    fn temp_history(name: &str) -> History {
+        let dir = env::temp_dir().join(format!("airpod-alfred-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        History::new(dir.join(HISTORY_FILE))
+    }
+
+    #[test]
+    fn history_entry_records_errors() {
+        let failed = entry(0, "80-3b-5c-c2-b1-7f", false);
+
+        assert!(!failed.succeeded);
+        assert_eq!(failed.error, Some(String::from("Failed to connect")));
+        assert_eq!(entry(0, "80-3b-5c-c2-b1-7f", true).error, None);
+    }
+
+    #[test]
+    fn history_appends_and_loads_entries() {
+        let history = temp_history("history-append");
+        let entries = vec![
+            entry(0, "80-3b-5c-c2-b1-7f", true),
+            entry(1, "5c-2e-fg-da-a3-43", false),
+        ];
+
+        for entry in &entries {
+            history.record(entry).unwrap();
+        }
+
+        assert_eq!(history.load().unwrap(), entries);
+    }
+
+    #[test]
+    fn history_load_treats_missing_file_as_empty() {
+        let history = temp_history("history-missing");
+
+        assert!(history.load().unwrap().is_empty());
+    }
+
+    #[test]
+    fn history_load_skips_unparseable_lines() {
+        let history = temp_history("history-corrupt");
+        history
+            .record(&entry(0, "80-3b-5c-c2-b1-7f", true))
+            .unwrap();
+        fs::write(
+            &history.path,
+            format!("{}not json\n", fs::read_to_string(&history.path).unwrap()),
+        )
+        .unwrap();
+        history
+            .record(&entry(1, "80-3b-5c-c2-b1-7f", true))
+            .unwrap();
+
+        assert_eq!(history.load().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn last_connected_skips_failures_and_disconnects() {
+        let mut disconnect = entry(3, "80-3b-5c-c2-b1-7f", true);
+        disconnect.action = HistoryAction::Disconnect;
+        let entries = vec![
+            entry(0, "80-3b-5c-c2-b1-7f", true),
+            entry(1, "5c-2e-fg-da-a3-43", true),
+            entry(2, "80-3b-5c-c2-b1-7f", false),
+            disconnect,
+        ];
+
+        assert_eq!(last_connected(&entries), Some(&entries[1]));
+        assert_eq!(last_connected(&entries[..0]), None);
+    }
+
+    #[test]
+    fn query_returns_newest_first_and_filters() {
+        let entries = vec![
+            entry(0, "80-3b-5c-c2-b1-7f", true),
+            entry(1, "5c-2e-fg-da-a3-43", false),
+            entry(2, "80:3B:5C:C2:B1:7F", false),
+            entry(3, "80-3b-5c-c2-b1-7f", true),
+        ];
+
+        let all = query(&entries, &Default::default());
+        assert_eq!(all.first().unwrap().timestamp, entries[3].timestamp);
+
+        let device = query(
+            &entries,
+            &HistoryQuery {
+                address: Some(String::from("80-3b-5c-c2-b1-7f")),
+                limit: Some(2),
+                ..Default::default()
+            },
+        );
+        assert_eq!(device, vec![&entries[3], &entries[2]]);
+
+        let failures = query(
+            &entries,
+            &HistoryQuery {
+                failures_only: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(failures, vec![&entries[2], &entries[1]]);
+    }
+}
@@ -1,2 +1,16 @@
+pub mod audio;
+pub mod battery;
 pub mod bluetooth;
+pub mod cache;
+pub mod clock;
+pub mod config;
+pub mod daemon;
+pub mod discovery;
+pub mod exit_code;
+pub mod history;
+pub mod noise;
+pub mod notify;
+pub mod output;
+pub mod stats;
 pub mod utilities;
+pub mod watch;
@@ -1,21 +1,168 @@
-use std::env;
+use std::{
+    env,
+    error::Error,
+    io, process,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
 
 use airpod_alfred_connector::bluetooth::DeviceFilters;
+use clap::ArgEnum;
+use clap::CommandFactory;
 use clap::Parser;
 use clap::Subcommand;
+use clap_complete::Shell;
+use log::warn;
 
-use airpod_alfred_connector::bluetooth::{self, DeviceListOptions};
-use airpod_alfred_connector::utilities;
+use airpod_alfred_connector::audio;
+use airpod_alfred_connector::bluetooth::{
+    self, Address, Backend, BluetoothClientOptions, Capability, ConnectOptions, ConnectResult,
+    ConnectionState, DeviceListOptions, RetryPolicy, SortKey, SortOrder,
+};
+use airpod_alfred_connector::cache::{self, DeviceCache};
+use airpod_alfred_connector::clock::{Clock, SystemClock};
+use airpod_alfred_connector::config::{self, Config};
+use airpod_alfred_connector::daemon;
+use airpod_alfred_connector::discovery;
+use airpod_alfred_connector::exit_code::ExitCode;
+use airpod_alfred_connector::history::{self, History, HistoryEntry, HistoryQuery};
+use airpod_alfred_connector::noise::{self, NoiseBridge, NoiseMode};
+use airpod_alfred_connector::notify;
+use airpod_alfred_connector::output::{self, OutputFormat};
+use airpod_alfred_connector::stats;
+use airpod_alfred_connector::utilities::{self, AlfredOutputOptions, TimeFormat};
+use airpod_alfred_connector::watch;
+
+// Set from --notify before any command runs
+static NOTIFY: AtomicBool = AtomicBool::new(false);
+
+// How long a connected device has to show up as an audio output
+const AUDIO_OUTPUT_TIMEOUT: Duration = Duration::from_secs(5);
+// How long list --discover scans for nearby AirPods and looks for other devices
+const DISCOVERY_SCAN_DURATION: Duration = Duration::from_secs(3);
 
 #[derive(Debug, Parser)]
 #[clap(name = "airpod-alfred-bluetooth")]
 #[clap(about = "Utility to simplify connecting/disconnecting to Airpods from Alfred")]
+#[clap(
+    after_help = "EXIT CODES:\n    0  Success\n    1  Failure\n    2  Device not found\n    3  blueutil not found or too old\n    4  Connecting failed\n    5  Timed out\n    6  Not supported by the backend\n    7  Invalid address"
+)]
 struct Cli {
     #[clap(subcommand)]
     command: Commands,
 
     #[clap(flatten)]
     verbose: clap_verbosity_flag::Verbosity,
+
+    // Prints the blueutil commands that would change anything instead of running them
+    #[clap(long, global = true)]
+    dry_run: bool,
+
+    // Posts a notification with the outcome, as Alfred doesn't show the output
+    #[clap(long, global = true)]
+    notify: bool,
+
+    // Bluetooth backend, `iobluetooth` works without blueutil installed and `fake`
+    // uses canned devices without touching Bluetooth, `profiler` can only list
+    // devices, `bluez` (Linux, built with the bluez feature) uses bluetoothctl and
+    // `windows` (built with the winrt feature) uses the WinRT Bluetooth APIs.
+    // Defaults to the config file's backend, then blueutil
+    #[clap(long, arg_enum, global = true)]
+    backend: Option<Backend>,
+
+    // Seconds a blueutil command can run before it's killed and reported as timed
+    // out. Waiting for a connect or disconnect is set with --verify-timeout instead
+    #[clap(long = "timeout", alias = "blueutil-timeout", global = true)]
+    command_timeout: Option<u64>,
+
+    // Seconds the device list is cached for, so listing on every keystroke doesn't
+    // run the backend each time. Connecting or disconnecting clears the cache
+    #[clap(long, global = true)]
+    cache_ttl: Option<u64>,
+
+    // Appends every backend command, how long it took and its exit status to
+    // ~/Library/Logs/airpod-alfred.log
+    #[clap(long, global = true)]
+    audit_log: bool,
+
+    // Output format, defaults to Alfred JSON for lists and plain text for messages
+    #[clap(long, arg_enum, global = true)]
+    format: Option<OutputFormat>,
+}
+
+#[derive(Debug, Clone, ArgEnum)]
+enum PowerAction {
+    On,
+    Off,
+    Toggle,
+    Status,
+}
+
+#[derive(Debug, Subcommand)]
+enum FavouriteCommands {
+    // Marks an Airpod as a favourite
+    #[clap(arg_required_else_help = true)]
+    Add {
+        device_id: String,
+    },
+    // Removes an Airpod from the favourites
+    #[clap(arg_required_else_help = true)]
+    Remove {
+        device_id: String,
+    },
+    // Lists favourite devices
+    List,
+}
+
+#[derive(Debug, Subcommand)]
+enum AliasCommands {
+    // Gives an Airpod a nickname that can be used instead of its name or address
+    #[clap(arg_required_else_help = true)]
+    Set {
+        alias: String,
+        device_id: String,
+    },
+    // Removes a nickname
+    #[clap(arg_required_else_help = true)]
+    Remove {
+        alias: String,
+    },
+    // Lists nicknames and the addresses they refer to
+    List,
+}
+
+#[derive(Debug, Subcommand)]
+enum ProfileCommands {
+    // Connects every device in a profile
+    #[clap(arg_required_else_help = true)]
+    Connect {
+        name: String,
+    },
+    // Lists profiles
+    List,
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommands {
+    // Writes a config file with the default settings
+    Init {
+        // Overwrites an existing config file
+        #[clap(long)]
+        force: bool,
+    },
+    // Prints the config file's path and the current settings
+    Show,
+    // Prints a setting, e.g. `alfred.connected_subtitle`
+    #[clap(arg_required_else_help = true)]
+    Get {
+        key: String,
+    },
+    // Changes a setting, e.g. `config set sort frecency`
+    #[clap(arg_required_else_help = true)]
+    Set {
+        key: String,
+        value: String,
+    },
 }
 
 #[derive(Debug, Subcommand)]
@@ -24,22 +171,285 @@ enum Commands {
     List {
         #[clap(short)]
         all_devices: Option<bool>,
-        #[clap(short)]
+        #[clap(short, conflicts_with = "group")]
         device_list: Option<String>,
+        // Only lists devices in the named group from the config file
+        #[clap(long)]
+        group: Option<String>,
+        // Shows when each device was last used
+        #[clap(long, arg_enum)]
+        last_used: Option<TimeFormat>,
+        // Shows the action enter will perform instead of the MAC address
+        #[clap(long)]
+        action_labels: bool,
+        // Subtitle template for connected devices, overriding the config file
+        #[clap(long)]
+        connected_subtitle: Option<String>,
+        // Subtitle template for disconnected devices, overriding the config file
+        #[clap(long)]
+        disconnected_subtitle: Option<String>,
+        // Marks the device that is the current audio output (requires SwitchAudioSource)
+        #[clap(long)]
+        show_active_output: bool,
+        // How to sort the devices, the previous device always stays on top
+        #[clap(long, arg_enum, conflicts_with = "sort-order")]
+        sort: Option<SortOrder>,
+        // Comma separated sort precedence, e.g. previous,connected,favourite,name
+        #[clap(long, arg_enum, use_value_delimiter = true)]
+        sort_order: Vec<SortKey>,
+        // Only lists connected devices, narrowing the device list or group if given
+        #[clap(long, conflicts_with = "disconnected")]
+        connected: bool,
+        // Only lists disconnected devices, narrowing the device list or group if given
+        #[clap(long)]
+        disconnected: bool,
+        // Lists recently used devices, most recent first, instead of paired devices
+        #[clap(long, conflicts_with_all = &["sort", "sort-order"])]
+        recent: bool,
+        // Lists profiles instead of devices
+        #[clap(long)]
+        profiles: bool,
+        // Also scans for nearby devices that aren't paired yet, with a pair action
+        #[clap(long, conflicts_with = "profiles")]
+        discover: bool,
+        // Queries each connected device for its battery and signal strength
+        #[clap(long, conflicts_with = "recent")]
+        details: bool,
+        // Hides devices by address or name pattern, on top of `exclude` in the config
+        #[clap(long, use_value_delimiter = true)]
+        exclude: Vec<String>,
     },
     #[clap(arg_required_else_help = true)]
-    // Connects to an Airpod
+    // Connects to an Airpod by address or name
     Connect {
-        device_id: String,
+        // One or more devices, separated by spaces or commas
+        #[clap(required_unless_present = "last", use_value_delimiter = true)]
+        device_ids: Vec<String>,
+        // Connects the last device that was connected, from the history file
+        #[clap(long, conflicts_with = "device-ids")]
+        last: bool,
+        // Number of additional verified attempts if connecting fails
+        #[clap(long, default_value = "0")]
+        retries: u32,
+        // Milliseconds to wait before the first retry, doubling after each failure
+        #[clap(long, default_value = "1000")]
+        retry_delay: u64,
+        // Prints a JSON report of the attempts made
+        #[clap(long)]
+        stats_json: bool,
+        // Seconds to wait for each attempt to be verified
+        #[clap(long = "verify-timeout")]
+        timeout: Option<u64>,
+        // Waits until the device reports as connected, failing after the timeout
+        #[clap(long)]
+        wait: bool,
+        // Switches the system audio output to the device once it's connected
+        #[clap(long)]
+        set_output: bool,
+        // Turns Bluetooth on first when it's off
+        #[clap(long)]
+        power_on: bool,
+        // Fails when the device doesn't report as connected within the timeout,
+        // e.g. AirPods that are still in their case
+        #[clap(long)]
+        verify: bool,
     },
-    // Disconnects from an Airpod
+    // Disconnects from an Airpod by address or name
     #[clap(arg_required_else_help = true)]
     Disconnect {
         device_id: String,
+        // Required to disconnect when safe mode is enabled in the config
+        #[clap(long)]
+        confirm: bool,
+        // Waits until the device reports as disconnected, failing after the timeout
+        #[clap(long)]
+        wait: bool,
+        // Fails when the device doesn't report as disconnected within the
+        // timeout, after disconnecting once more
+        #[clap(long)]
+        verify: bool,
+        // Seconds to wait for the disconnect with --wait or --verify
+        #[clap(long = "verify-timeout")]
+        timeout: Option<u64>,
+    },
+    // Disconnects every connected device, optionally only those in a device list or group
+    DisconnectAll {
+        #[clap(short, conflicts_with = "group")]
+        device_list: Option<String>,
+        #[clap(long)]
+        group: Option<String>,
+        // Required to disconnect when safe mode is enabled in the config
+        #[clap(long)]
+        confirm: bool,
+    },
+    // Disconnects whichever of two Airpods is connected and connects the other
+    #[clap(arg_required_else_help = true)]
+    Switch {
+        a: String,
+        b: String,
+        // Switches the system audio output to the device once it's connected
+        #[clap(long)]
+        set_output: bool,
+        // Required to disconnect when safe mode is enabled in the config
+        #[clap(long)]
+        confirm: bool,
     },
-    // Toggles Connection to Airpod
+    // Toggles Connection to Airpod by address or name
     Toggle {
         device_id: String,
+        // Leaves an already connected device connected instead of disconnecting it
+        #[clap(long)]
+        ensure_connected: bool,
+        // Number of additional verified attempts if connecting fails
+        #[clap(long, default_value = "0")]
+        retries: u32,
+        // Milliseconds to wait before the first retry, doubling after each failure
+        #[clap(long, default_value = "1000")]
+        retry_delay: u64,
+        // Seconds to wait for each attempt to be verified
+        #[clap(long = "verify-timeout")]
+        timeout: Option<u64>,
+        // Switches the system audio output to the device once it's connected
+        #[clap(long)]
+        set_output: bool,
+        // Required to disconnect when safe mode is enabled in the config
+        #[clap(long)]
+        confirm: bool,
+    },
+    // Connects and disconnects an Airpod repeatedly, reporting how long each took
+    #[clap(arg_required_else_help = true)]
+    Stats {
+        device_id: String,
+        // Number of connect and disconnect cycles
+        #[clap(short = 'n', long, default_value = "5")]
+        runs: u32,
+        // Seconds to wait for each connect or disconnect to be verified
+        #[clap(long = "verify-timeout")]
+        timeout: Option<u64>,
+    },
+    // Connects to an Airpod and keeps reconnecting it until interrupted
+    #[clap(arg_required_else_help = true)]
+    Keepalive {
+        device_id: String,
+        // Seconds between connection checks
+        #[clap(short, long, default_value = "30")]
+        interval: u64,
+    },
+    // Monitors devices, disconnecting those that haven't been used recently. Runs until killed
+    Daemon {
+        // Minutes since a device's recent access date before it's disconnected
+        #[clap(long, default_value = "30")]
+        idle_timeout: u64,
+        // Seconds between checks
+        #[clap(short, long, default_value = "60")]
+        interval: u64,
+        // Prints a launchd plist that runs the daemon with these options instead of running it
+        #[clap(long, conflicts_with = "install-launch-agent")]
+        print_plist: bool,
+        // Writes the launchd plist to ~/Library/LaunchAgents instead of running the daemon
+        #[clap(long)]
+        install_launch_agent: bool,
+        // Required to disconnect idle devices when safe mode is enabled in the config
+        #[clap(long)]
+        confirm: bool,
+    },
+    // Prints a JSON line whenever a device connects or disconnects. Runs until killed
+    Watch {
+        // Seconds between checks
+        #[clap(short, long, default_value = "2")]
+        interval: u64,
+    },
+    // Connects the most recently used Airpod, optionally within a group from the config file
+    ConnectRecent {
+        #[clap(long)]
+        group: Option<String>,
+        // Switches the system audio output to the device once it's connected
+        #[clap(long)]
+        set_output: bool,
+    },
+    // Prints recorded connects and disconnects, newest first
+    History {
+        // Only shows entries for this device, by address, name or alias
+        #[clap(short, long)]
+        device_id: Option<String>,
+        // Only shows entries that failed
+        #[clap(long)]
+        failures: bool,
+        #[clap(short = 'n', long, default_value = "20")]
+        limit: usize,
+    },
+    // Deletes the cache and state files
+    ClearCache,
+    // Prints which operations the selected backend supports
+    Capabilities,
+    // Prints the backend's unparsed device list, and any entries that couldn't be parsed
+    Raw,
+    // Checks that every entry in the backend's device list can be parsed
+    Doctor,
+    // Pairs with a new Airpod
+    #[clap(arg_required_else_help = true)]
+    Pair {
+        device_id: String,
+        // PIN to use if the device asks for one
+        #[clap(long)]
+        pin: Option<String>,
+    },
+    // Removes an Airpod from the paired devices
+    #[clap(arg_required_else_help = true)]
+    Unpair {
+        device_id: String,
+        // Required to unpair when safe mode is enabled in the config
+        #[clap(long)]
+        confirm: bool,
+    },
+    // Manages favourite devices
+    #[clap(subcommand)]
+    Favourite(FavouriteCommands),
+    // Manages device nicknames stored in the config file
+    #[clap(subcommand)]
+    Alias(AliasCommands),
+    // Connects profiles, the device groups in the config file
+    #[clap(subcommand)]
+    Profile(ProfileCommands),
+    // Manages the config file
+    #[clap(subcommand)]
+    Config(ConfigCommands),
+    // Switches the listening mode of connected Airpods
+    #[clap(arg_required_else_help = true)]
+    Noise {
+        #[clap(arg_enum)]
+        mode: NoiseMode,
+        // Airpod to change by address, name or alias, defaults to the first
+        // connected device matching the default filter
+        #[clap(short, long)]
+        device_id: Option<String>,
+    },
+    // Controls the Bluetooth radio
+    #[clap(arg_required_else_help = true)]
+    Power {
+        #[clap(arg_enum)]
+        action: PowerAction,
+        // Required to power off when safe mode is enabled in the config
+        #[clap(long)]
+        confirm: bool,
+    },
+    // Prints an Airpod's connection state as JSON, by address, name or alias.
+    // Exits with 0 when connected, 1 when disconnected and 2 if the device
+    // couldn't be found
+    #[clap(arg_required_else_help = true)]
+    Status {
+        device_id: String,
+    },
+    // Prints everything known about an Airpod as JSON, by address, name or alias
+    #[clap(arg_required_else_help = true)]
+    Info {
+        device_id: String,
+    },
+    // Prints a shell completion script, e.g. `completions zsh > _airpod-alfred-bluetooth`
+    #[clap(arg_required_else_help = true)]
+    Completions {
+        #[clap(arg_enum)]
+        shell: Shell,
     },
 }
 
@@ -50,25 +460,383 @@ fn main() {
         .filter_level(cli.verbose.log_level_filter())
         .init();
 
+    NOTIFY.store(cli.notify, Ordering::Relaxed);
+
     // Workflow saves the previously selected mac address into this env variable
-    let previous_address = match env::var("AIRPODS_MAC") {
-        Ok(val) => Some(val),
-        Err(_) => None,
+    let previous_address = env::var("AIRPODS_MAC").ok();
+
+    let mut config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => fail(format!("Failed to load config : {}", err)),
     };
 
-    let client = bluetooth::BluetoothClient::new();
+    // Flags win over the config file
+    let backend = cli.backend.or(config.backend).unwrap_or_default();
+    let client = bluetooth::BluetoothClient::with_options(BluetoothClientOptions {
+        blueutil_path: config.blueutil_path.clone(),
+        dry_run: cli.dry_run,
+        backend,
+        // Nothing actually changes in a dry run or with the fake backend so
+        // there's nothing to record
+        history_path: if cli.dry_run || backend == Backend::Fake {
+            None
+        } else {
+            History::default_path()
+        },
+        command_timeout: cli
+            .command_timeout
+            .or(config.blueutil_timeout)
+            .map(Duration::from_secs),
+        cache_ttl: cli
+            .cache_ttl
+            .or(config.cache_ttl)
+            .filter(|x| *x > 0)
+            .map(Duration::from_secs),
+        // The fake backend's devices would replace the real ones
+        cache_path: if backend == Backend::Fake {
+            None
+        } else {
+            DeviceCache::default_path()
+        },
+        audit_log_path: if cli.audit_log || config.audit_log {
+            bluetooth::default_audit_log_path()
+        } else {
+            None
+        },
+        // system_profiler can still list devices when blueutil is missing or broken
+        fallback_backend: if backend == Backend::Blueutil {
+            Some(Backend::Profiler)
+        } else {
+            None
+        },
+    });
+    let format = cli.format.or(config.format);
+    let message_format = format.unwrap_or(OutputFormat::Plain);
+
+    // Checked before anything else runs, e.g. the Windows backend can list
+    // devices but not connect them
+    if let Some(capability) = required_capability(&cli.command) {
+        if let Err(err) = client.capabilities().require(capability) {
+            fail(err);
+        }
+    }
 
     match cli.command {
         Commands::List {
             all_devices,
             device_list,
+            group,
+            last_used,
+            action_labels,
+            connected_subtitle,
+            disconnected_subtitle,
+            show_active_output,
+            sort,
+            sort_order,
+            connected,
+            disconnected,
+            recent,
+            profiles,
+            discover,
+            details,
+            exclude,
         } => {
+            if profiles {
+                println!(
+                    "{}",
+                    output::render_profiles(
+                        &config.profiles(),
+                        format.unwrap_or(OutputFormat::Alfred)
+                    )
+                );
+                return;
+            }
+
             let mut filter = match all_devices {
                 Some(all_devices) if all_devices => DeviceFilters::AllDevices,
-                _ => DeviceFilters::Regex {
-                    value: String::from("airpod"), // TODO - Figure out a better default
+                _ => config.default_list_filter(),
+            };
+            // Whether specific devices were asked for, rather than the default
+            let mut selected = false;
+
+            if let Some(device_list) = device_list {
+                if let Some(device_list) = utilities::device_list_from_cli_arg(&device_list) {
+                    filter = DeviceFilters::SpecificAddresses {
+                        addresses: device_list,
+                    };
+                    selected = true;
+                }
+            }
+
+            if let Some(group) = group {
+                match config.group_filter(&group) {
+                    Ok(group_filter) => filter = group_filter,
+                    Err(err) => fail(err),
+                }
+                selected = true;
+            }
+
+            let state = if connected {
+                Some(ConnectionState::Connected)
+            } else if disconnected {
+                Some(ConnectionState::Disconnected)
+            } else {
+                None
+            };
+
+            if let Some(state) = state {
+                let state_filter = DeviceFilters::ConnectionState(state);
+
+                filter = if selected {
+                    DeviceFilters::All(vec![filter, state_filter])
+                } else {
+                    state_filter
+                };
+            }
+
+            let excluded = [exclude, config.exclude.clone()].concat();
+            if !excluded.is_empty() {
+                filter =
+                    DeviceFilters::All(vec![filter, DeviceFilters::Exclude { values: excluded }]);
+            }
+
+            let result = if recent {
+                client.get_recent_device_list(filter).map(|x| (x, None))
+            } else {
+                let mut options = DeviceListOptions::new(filter, previous_address);
+                if let Some(sort) = sort.or(config.sort) {
+                    options = options.with_sort(sort);
+                }
+                if !sort_order.is_empty() {
+                    options = options.with_sort_order(sort_order);
+                }
+                if details {
+                    options = options.with_details();
+                }
+
+                client.get_device_list_or_fallback(options)
+            };
+
+            let (mut devices, degraded) = match result {
+                Ok(result) => result,
+                Err(err) => fail(err),
+            };
+            let warning = degraded.map(|err| {
+                format!(
+                    "{} isn't working, devices can't be connected from here : {}",
+                    backend.as_str(),
+                    err
+                )
+            });
+            let format = format.unwrap_or(OutputFormat::Alfred);
+            // Only Alfred's output has somewhere to show it among the devices
+            match &warning {
+                Some(warning) if format != OutputFormat::Alfred => eprintln!("{}", warning),
+                _ => {}
+            }
+
+            if show_active_output {
+                match audio::current_output_device() {
+                    Ok(Some(output_name)) => audio::mark_active_output(&mut devices, &output_name),
+                    Ok(None) => {}
+                    Err(err) => warn!("Failed to get the current audio output : {}", err),
+                }
+            }
+
+            let discovered = if discover {
+                let scanned = match discovery::scan_for_airpods(DISCOVERY_SCAN_DURATION) {
+                    Ok(discovered) => discovery::unpaired(discovered, &devices),
+                    Err(err) => {
+                        warn!("Failed to discover nearby AirPods : {}", err);
+                        vec![]
+                    }
+                };
+                let inquiry = if client.capabilities().supports(Capability::Discover) {
+                    client
+                        .discover(DISCOVERY_SCAN_DURATION)
+                        .unwrap_or_else(|err| {
+                            warn!("Failed to discover nearby devices : {}", err);
+                            vec![]
+                        })
+                } else {
+                    vec![]
+                };
+
+                discovery::with_inquiry_results(scanned, inquiry)
+            } else {
+                vec![]
+            };
+
+            // After marking the active output, which is matched by the real name
+            config.apply_aliases(&mut devices);
+
+            let rendered = output::render_devices_with_discovered(
+                devices,
+                &discovered,
+                format,
+                &AlfredOutputOptions {
+                    last_used_format: last_used,
+                    action_labels,
+                    connected_subtitle: connected_subtitle
+                        .or_else(|| config.alfred.connected_subtitle.clone()),
+                    disconnected_subtitle: disconnected_subtitle
+                        .or_else(|| config.alfred.disconnected_subtitle.clone()),
+                    warning,
                 },
+                SystemClock.now(),
+            );
+            println!("{}", rendered);
+        }
+        Commands::Connect {
+            device_ids,
+            last,
+            retries,
+            retry_delay,
+            stats_json,
+            timeout,
+            wait,
+            set_output,
+            power_on,
+            verify,
+        } => {
+            // Once up front, so every way of connecting below finds it on
+            if power_on || config.power_on {
+                if let Err(err) = client.ensure_powered() {
+                    fail(err);
+                }
+            }
+
+            let device_ids = if last {
+                vec![last_connected_address()]
+            } else {
+                device_ids
+            };
+
+            let device_ids = device_ids
+                .iter()
+                .map(|x| match resolve_device_id(&client, &config, x) {
+                    Ok(device_id) => device_id,
+                    Err(err) => fail(err),
+                })
+                .collect::<Vec<String>>();
+
+            let verify = verify || config.verify_connect;
+            let connect_options = |device_id: &str| {
+                let timeout =
+                    config.connect_timeout_for(device_id, timeout.map(Duration::from_secs));
+                let options = if wait {
+                    ConnectOptions::wait(timeout)
+                } else {
+                    ConnectOptions::default()
+                };
+
+                if verify {
+                    options.with_verify(timeout)
+                } else {
+                    options
+                }
+            };
+
+            if device_ids.len() > 1 {
+                if stats_json || retries > 0 || set_output {
+                    fail("--retries, --stats-json and --set-output only support connecting a single device");
+                }
+
+                let results = if wait || verify {
+                    device_ids
+                        .iter()
+                        .map(|x| {
+                            (
+                                x.clone(),
+                                client.connect_with_options(x, &connect_options(x)),
+                            )
+                        })
+                        .collect()
+                } else {
+                    client.connect_many(&device_ids)
+                };
+
+                report_connect_results(results, message_format);
+                return;
+            }
+
+            let device_id = device_ids[0].clone();
+
+            if stats_json || retries > 0 {
+                let timeout =
+                    config.connect_timeout_for(&device_id, timeout.map(Duration::from_secs));
+                let retry_policy = RetryPolicy::new(retries, Duration::from_millis(retry_delay));
+                let stats = client.connect_with_stats(&device_id, &retry_policy, timeout);
+
+                if stats_json {
+                    println!("{}", utilities::build_connect_stats_output(&stats).dump());
+                } else if stats.succeeded {
+                    print_message("Connected to device", message_format);
+                }
+
+                if let Some(err) = stats.error {
+                    print_error(&err);
+                    process::exit(ExitCode::ConnectFailed.code());
+                }
+            } else if wait || verify {
+                match client.connect_with_options(&device_id, &connect_options(&device_id)) {
+                    Ok(_) => print_message("Connected to device", message_format),
+                    Err(err) => fail(err),
+                }
+            } else {
+                match client.connect_to_device(&device_id) {
+                    Ok(_) => print_message("Connected to device", message_format),
+                    Err(err) => fail(err),
+                }
+            }
+
+            if set_output {
+                set_audio_output(&client, &device_id);
+            }
+        }
+        Commands::Disconnect {
+            device_id,
+            confirm,
+            wait,
+            verify,
+            timeout,
+        } => {
+            if let Err(err) = config.check_destructive_action("disconnect", confirm) {
+                fail(err);
+            }
+
+            let device_id = match resolve_device_id(&client, &config, &device_id) {
+                Ok(device_id) => device_id,
+                Err(err) => fail(err),
+            };
+
+            let timeout = config.connect_timeout_for(&device_id, timeout.map(Duration::from_secs));
+            let options = if wait {
+                ConnectOptions::wait(timeout)
+            } else {
+                ConnectOptions::default()
             };
+            let options = if verify {
+                options.with_verify(timeout)
+            } else {
+                options
+            };
+
+            match client.disconnect_with_options(&device_id, &options) {
+                Ok(_) => print_message("Disconnected from device", message_format),
+                Err(err) => fail(err),
+            }
+        }
+        Commands::DisconnectAll {
+            device_list,
+            group,
+            confirm,
+        } => {
+            if let Err(err) = config.check_destructive_action("disconnect", confirm) {
+                fail(err);
+            }
+
+            let mut filter = DeviceFilters::AllDevices;
 
             if let Some(device_list) = device_list {
                 if let Some(device_list) = utilities::device_list_from_cli_arg(&device_list) {
@@ -78,27 +846,703 @@ fn main() {
                 }
             }
 
-            let devices = client.get_device_list(DeviceListOptions::new(filter, previous_address));
+            if let Some(group) = group {
+                match config.group_filter(&group) {
+                    Ok(group_filter) => filter = group_filter,
+                    Err(err) => fail(err),
+                }
+            }
+
+            let results = match client.disconnect_all(filter) {
+                Ok(results) => results,
+                Err(err) => fail(err),
+            };
+
+            if results.is_empty() {
+                print_message("No connected devices", message_format);
+            }
+
+            let mut failed = false;
+            for result in results {
+                match result.error {
+                    None => print_message(
+                        &format!("Disconnected from {}", result.device.name),
+                        message_format,
+                    ),
+                    Some(err) => {
+                        print_error(&format!(
+                            "Failed to disconnect from {} : {}",
+                            result.device.name, err
+                        ));
+                        failed = true;
+                    }
+                }
+            }
 
-            utilities::print_alfred_output(devices);
+            if failed {
+                process::exit(ExitCode::Failure.code());
+            }
         }
-        Commands::Connect { device_id } => match client.connect_to_device(&device_id) {
-            Ok(_) => println!("Connected to device"),
-            Err(err) => eprintln!("{}", err),
+        Commands::Switch {
+            a,
+            b,
+            set_output,
+            confirm,
+        } => {
+            let a = match resolve_device_id(&client, &config, &a) {
+                Ok(a) => a,
+                Err(err) => fail(err),
+            };
+            let b = match resolve_device_id(&client, &config, &b) {
+                Ok(b) => b,
+                Err(err) => fail(err),
+            };
+
+            // Whichever of the two is connected gets disconnected
+            for address in [&a, &b] {
+                if let Err(err) = check_disconnect(&client, &config, address, confirm) {
+                    fail(err);
+                }
+            }
+
+            match client.switch_devices(&a, &b) {
+                Ok(address) => {
+                    print_message(&format!("Connected to {}", address), message_format);
+
+                    if set_output {
+                        set_audio_output(&client, &address);
+                    }
+                }
+                Err(err) => fail(err),
+            }
+        }
+        Commands::Toggle {
+            device_id,
+            ensure_connected,
+            retries,
+            retry_delay,
+            timeout,
+            set_output,
+            confirm,
+        } => {
+            let device_id = match resolve_device_id(&client, &config, &device_id) {
+                Ok(device_id) => device_id,
+                Err(err) => fail(err),
+            };
+
+            if !ensure_connected {
+                if let Err(err) = check_disconnect(&client, &config, &device_id, confirm) {
+                    fail(err);
+                }
+            }
+
+            let result = if retries > 0 {
+                let timeout =
+                    config.connect_timeout_for(&device_id, timeout.map(Duration::from_secs));
+                let retry_policy = RetryPolicy::new(retries, Duration::from_millis(retry_delay));
+
+                client.toggle_with_retries(&device_id, ensure_connected, &retry_policy, timeout)
+            } else if ensure_connected {
+                client.ensure_connected(&device_id)
+            } else {
+                client.toggle_connected_status(&device_id)
+            };
+
+            match result {
+                Ok(connected) => {
+                    if connected {
+                        print_message("connected", message_format);
+
+                        if set_output {
+                            set_audio_output(&client, &device_id);
+                        }
+                    } else {
+                        print_message("disconnected", message_format);
+                    }
+                }
+                Err(err) => fail(err),
+            }
+        }
+        Commands::Stats {
+            device_id,
+            runs,
+            timeout,
+        } => {
+            let device_id = match resolve_device_id(&client, &config, &device_id) {
+                Ok(device_id) => device_id,
+                Err(err) => fail(err),
+            };
+            let timeout = config.connect_timeout_for(&device_id, timeout.map(Duration::from_secs));
+
+            let report = stats::measure_latency(&client, &device_id, runs, timeout);
+            println!(
+                "{}",
+                output::render_latency_report(&report, format.unwrap_or(OutputFormat::Table))
+            );
+
+            if report.connect.successes() < runs || report.disconnect.successes() < runs {
+                process::exit(ExitCode::Failure.code());
+            }
+        }
+        Commands::Keepalive {
+            device_id,
+            interval,
+        } => {
+            let device_id = match resolve_device_id(&client, &config, &device_id) {
+                Ok(device_id) => device_id,
+                Err(err) => fail(err),
+            };
+            let timeout = config.connect_timeout_for(&device_id, None);
+
+            if let Err(err) = client.keepalive(&device_id, Duration::from_secs(interval), timeout) {
+                fail(err);
+            }
+        }
+        Commands::Daemon {
+            idle_timeout,
+            interval,
+            print_plist,
+            install_launch_agent,
+            confirm,
+        } => {
+            if let Err(err) = config.check_destructive_action("disconnect idle devices", confirm) {
+                fail(err);
+            }
+
+            if !print_plist && !install_launch_agent {
+                daemon::run_idle_disconnect(
+                    &client,
+                    &SystemClock,
+                    Duration::from_secs(idle_timeout * 60),
+                    Duration::from_secs(interval),
+                );
+                return;
+            }
+
+            let program = match env::current_exe() {
+                Ok(program) => program,
+                Err(err) => fail(format!("Failed to determine the executable path : {}", err)),
+            };
+            let mut args = vec![
+                String::from("daemon"),
+                String::from("--idle-timeout"),
+                idle_timeout.to_string(),
+                String::from("--interval"),
+                interval.to_string(),
+            ];
+            // The installed daemon would be refused otherwise
+            if confirm {
+                args.push(String::from("--confirm"));
+            }
+            let plist = daemon::launchd_plist(&program, &args);
+
+            if print_plist {
+                print!("{}", plist);
+                return;
+            }
+
+            let path = match daemon::launch_agent_path() {
+                Some(path) => path,
+                None => fail("Could not determine the launch agent path"),
+            };
+
+            match daemon::install_launch_agent(&path, &plist) {
+                Ok(_) => println!(
+                    "Installed {}, start it with `launchctl load -w {}`",
+                    path.display(),
+                    path.display()
+                ),
+                Err(err) => fail(format!("Failed to install {} : {}", path.display(), err)),
+            }
+        }
+        Commands::Watch { interval } => watch::run_watch(
+            &client,
+            &SystemClock,
+            Duration::from_secs(interval),
+            |event| println!("{}", utilities::build_event_output(event).dump()),
+        ),
+        Commands::ConnectRecent { group, set_output } => {
+            let filter = match group {
+                Some(group) => match config.group_filter(&group) {
+                    Ok(filter) => filter,
+                    Err(err) => fail(err),
+                },
+                None => DeviceFilters::AllDevices,
+            };
+
+            match client.connect_most_recent(filter) {
+                Ok(device) => {
+                    print_message(&format!("Connected to {}", device.name), message_format);
+
+                    if set_output {
+                        set_audio_output(&client, &device.address);
+                    }
+                }
+                Err(err) => fail(err),
+            }
+        }
+        Commands::History {
+            device_id,
+            failures,
+            limit,
+        } => {
+            let entries = load_history();
+
+            let address = device_id.map(|x| match resolve_device_id(&client, &config, &x) {
+                Ok(address) => address,
+                Err(err) => fail(err),
+            });
+            let entries = history::query(
+                &entries,
+                &HistoryQuery {
+                    address,
+                    failures_only: failures,
+                    limit: Some(limit),
+                },
+            );
+
+            println!(
+                "{}",
+                output::render_history(&entries, format.unwrap_or(OutputFormat::Plain))
+            );
+        }
+        Commands::Capabilities => println!(
+            "{}",
+            output::render_capabilities(&client.capabilities(), message_format)
+        ),
+        Commands::Raw => match client.raw_device_list() {
+            Ok(listed) => {
+                println!("{}", listed.output.trim_end());
+                for failure in &listed.diagnostics.failures {
+                    eprintln!("Couldn't parse {}", failure);
+                }
+            }
+            Err(err) => fail(err),
         },
-        Commands::Disconnect { device_id } => match client.disconnect_from_device(&device_id) {
-            Ok(_) => println!("Disconnected from device"),
-            Err(err) => eprintln!("{}", err),
+        Commands::Doctor => match client.raw_device_list() {
+            Ok(listed) if listed.diagnostics.is_empty() => print_message(
+                &format!("Parsed all {} devices", listed.devices.len()),
+                message_format,
+            ),
+            Ok(listed) => {
+                for failure in &listed.diagnostics.failures {
+                    println!("Couldn't parse {}", failure);
+                }
+                process::exit(ExitCode::Failure.code());
+            }
+            Err(err) => fail(err),
         },
-        Commands::Toggle { device_id } => match client.toggle_connected_status(&device_id) {
-            Ok(connected) => {
-                if connected {
-                    println!("connected");
-                } else {
-                    println!("disconnected");
+        Commands::ClearCache => {
+            let data_dir = match config::data_dir() {
+                Some(data_dir) => data_dir,
+                None => fail("Could not determine the data directory"),
+            };
+
+            match cache::clear_data_files(&data_dir) {
+                Ok(deleted) if deleted.is_empty() => {
+                    print_message("Nothing to delete", message_format)
                 }
+                Ok(deleted) => {
+                    for path in deleted {
+                        print_message(&format!("Deleted {}", path.display()), message_format);
+                    }
+                }
+                Err(err) => fail(err),
+            }
+        }
+        Commands::Pair { device_id, pin } => {
+            // Unpaired devices can't be looked up by name
+            let address = match Address::parse(&device_id) {
+                Ok(address) => address,
+                Err(err) => fail(err),
+            };
+
+            match client.pair_device(address.as_str(), pin) {
+                Ok(_) => print_message("Paired with device", message_format),
+                Err(err) => fail(err),
+            }
+        }
+        Commands::Unpair { device_id, confirm } => {
+            if let Err(err) = config.check_destructive_action("unpair", confirm) {
+                fail(err);
+            }
+
+            let address = match resolve_device_id(&client, &config, &device_id) {
+                Ok(address) => address,
+                Err(err) => fail(err),
+            };
+
+            match client.unpair_device(&address) {
+                Ok(_) => print_message("Unpaired device", message_format),
+                Err(err) => fail(err),
+            }
+        }
+        Commands::Favourite(FavouriteCommands::List) => {
+            let mut devices =
+                match client.get_device_list(DeviceListOptions::new_default_all_devices()) {
+                    Ok(devices) => devices,
+                    Err(err) => fail(err),
+                };
+            devices.retain(|x| x.favourite);
+
+            println!(
+                "{}",
+                output::render_devices(
+                    devices,
+                    format.unwrap_or(OutputFormat::Alfred),
+                    &Default::default(),
+                    SystemClock.now(),
+                )
+            );
+        }
+        Commands::Favourite(FavouriteCommands::Add { device_id }) => {
+            match set_favourite(&client, &config, &device_id, true) {
+                Ok(_) => print_message("Added device to favourites", message_format),
+                Err(err) => fail(err),
+            }
+        }
+        Commands::Favourite(FavouriteCommands::Remove { device_id }) => {
+            match set_favourite(&client, &config, &device_id, false) {
+                Ok(_) => print_message("Removed device from favourites", message_format),
+                Err(err) => fail(err),
+            }
+        }
+        Commands::Alias(AliasCommands::Set { alias, device_id }) => {
+            let address = match client.resolve_device_id(&device_id) {
+                Ok(address) => address,
+                Err(err) => fail(err),
+            };
+
+            config.set_alias(&alias, &address);
+            match config.save() {
+                Ok(_) => print_message(
+                    &format!("Set alias '{}' for {}", alias, address),
+                    message_format,
+                ),
+                Err(err) => fail(format!("Failed to save config : {}", err)),
+            }
+        }
+        Commands::Alias(AliasCommands::Remove { alias }) => {
+            if !config.remove_alias(&alias) {
+                fail(format!("Unknown alias : '{}'", alias));
+            }
+
+            match config.save() {
+                Ok(_) => print_message(&format!("Removed alias '{}'", alias), message_format),
+                Err(err) => fail(format!("Failed to save config : {}", err)),
+            }
+        }
+        Commands::Alias(AliasCommands::List) => {
+            let mut aliases = config.aliases.iter().collect::<Vec<_>>();
+            aliases.sort();
+
+            for (alias, address) in aliases {
+                println!("{} : {}", alias, address);
             }
-            Err(err) => eprintln!("{}", err),
+        }
+        Commands::Profile(ProfileCommands::Connect { name }) => {
+            let addresses = match config.resolve_group(&name) {
+                Ok(addresses) => addresses,
+                Err(err) => fail(err),
+            };
+
+            report_connect_results(client.connect_many(&addresses), message_format);
+        }
+        Commands::Profile(ProfileCommands::List) => println!(
+            "{}",
+            output::render_profiles(&config.profiles(), format.unwrap_or(OutputFormat::Plain))
+        ),
+        Commands::Config(ConfigCommands::Init { force }) => {
+            let path = match config::config_path() {
+                Some(path) => path,
+                None => fail("Could not determine the config file path"),
+            };
+
+            if path.exists() && !force {
+                fail(format!(
+                    "{} already exists, pass --force to overwrite it",
+                    path.display()
+                ));
+            }
+
+            match Config::default().save_to(&path) {
+                Ok(_) => print_message(&format!("Created {}", path.display()), message_format),
+                Err(err) => fail(format!("Failed to save config : {}", err)),
+            }
+        }
+        Commands::Config(ConfigCommands::Show) => {
+            if let Some(path) = config::config_path() {
+                println!("# {}", path.display());
+            }
+
+            match config.to_toml() {
+                Ok(contents) => print!("{}", contents),
+                Err(err) => fail(err),
+            }
+        }
+        Commands::Config(ConfigCommands::Get { key }) => match config.get_value(&key) {
+            Ok(Some(toml::Value::String(value))) => println!("{}", value),
+            Ok(Some(value)) => println!("{}", value),
+            Ok(None) => fail(format!("'{}' isn't set", key)),
+            Err(err) => fail(err),
         },
+        Commands::Config(ConfigCommands::Set { key, value }) => {
+            if let Err(err) = config.set_value(&key, &value) {
+                fail(err);
+            }
+
+            match config.save() {
+                Ok(_) => print_message(&format!("Set {}", key), message_format),
+                Err(err) => fail(format!("Failed to save config : {}", err)),
+            }
+        }
+        Commands::Power { action, confirm } => {
+            let result = match action {
+                PowerAction::On => client.set_power(true).map(|_| true),
+                PowerAction::Off => {
+                    if let Err(err) = config.check_destructive_action("power off", confirm) {
+                        fail(err);
+                    }
+
+                    client.set_power(false).map(|_| false)
+                }
+                PowerAction::Toggle => {
+                    // Only toggling off is destructive
+                    if client.is_powered().unwrap_or(false) {
+                        if let Err(err) = config.check_destructive_action("power off", confirm) {
+                            fail(err);
+                        }
+                    }
+
+                    client.toggle_power()
+                }
+                PowerAction::Status => client.is_powered(),
+            };
+
+            match result {
+                Ok(true) => print_message("on", message_format),
+                Ok(false) => print_message("off", message_format),
+                Err(err) => fail(err),
+            }
+        }
+        Commands::Status { device_id } => {
+            let address = match resolve_device_id(&client, &config, &device_id) {
+                Ok(address) => address,
+                Err(err) => fail(err),
+            };
+
+            let device = match client.get_device(&address) {
+                Ok(device) => device,
+                Err(err) => fail(err),
+            };
+
+            match format {
+                None | Some(OutputFormat::Json) => {
+                    println!("{}", utilities::build_status_output(&device).dump())
+                }
+                Some(format) => {
+                    println!(
+                        "{}",
+                        output::render_device(&device, format, SystemClock.now())
+                    )
+                }
+            }
+
+            if !device.connected {
+                process::exit(ExitCode::Failure.code());
+            }
+        }
+        Commands::Noise { mode, device_id } => {
+            let device = match device_id {
+                Some(device_id) => {
+                    let address = match resolve_device_id(&client, &config, &device_id) {
+                        Ok(address) => address,
+                        Err(err) => fail(err),
+                    };
+
+                    match client.get_device(&address) {
+                        Ok(device) => device,
+                        Err(err) => fail(err),
+                    }
+                }
+                None => {
+                    let filter = config.default_list_filter();
+
+                    match client.get_device_list(DeviceListOptions::new(filter, None)) {
+                        Ok(devices) => match devices.into_iter().find(|x| x.connected) {
+                            Some(device) => device,
+                            None => fail("No connected Airpods found"),
+                        },
+                        Err(err) => fail(err),
+                    }
+                }
+            };
+
+            let bridge = NoiseBridge::from_shortcut(config.noise_shortcut.clone());
+            match noise::set_noise_mode(&bridge, &device.name, mode) {
+                Ok(_) => print_message(
+                    &format!("Set {} to {}", device.name, mode.label()),
+                    message_format,
+                ),
+                Err(err) => fail(err),
+            }
+        }
+        Commands::Info { device_id } => {
+            let address = match resolve_device_id(&client, &config, &device_id) {
+                Ok(address) => address,
+                Err(err) => fail(err),
+            };
+
+            match client.get_device(&address) {
+                Ok(device) => println!(
+                    "{}",
+                    output::render_device(
+                        &device,
+                        format.unwrap_or(OutputFormat::Json),
+                        SystemClock.now()
+                    )
+                ),
+                Err(err) => fail(err),
+            }
+        }
+        Commands::Completions { shell } => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+
+            clap_complete::generate(shell, &mut command, name, &mut io::stdout());
+        }
+    }
+}
+
+// Resolves an alias from the config, a device name or an address to an address.
+fn resolve_device_id(
+    client: &bluetooth::BluetoothClient,
+    config: &Config,
+    device_id: &str,
+) -> Result<String, Box<dyn Error>> {
+    // Aliases are edited by hand, so the address may not be one
+    if let Some(address) = config.resolve_alias(device_id) {
+        return Ok(Address::parse(address)?.to_string());
+    }
+
+    Ok(client.resolve_device_id(device_id)?)
+}
+
+// The backend capability a command can't run without, if any.
+fn required_capability(command: &Commands) -> Option<Capability> {
+    match command {
+        Commands::Connect { .. }
+        | Commands::Disconnect { .. }
+        | Commands::DisconnectAll { .. }
+        | Commands::Switch { .. }
+        | Commands::Toggle { .. }
+        | Commands::Stats { .. }
+        | Commands::Keepalive { .. }
+        | Commands::ConnectRecent { .. } => Some(Capability::Connect),
+        Commands::Pair { .. } | Commands::Unpair { .. } => Some(Capability::Pair),
+        _ => None,
+    }
+}
+
+// Safe mode only stands in the way when the device would be disconnected, so
+// connecting with toggle or switch still works without --confirm.
+fn check_disconnect(
+    client: &bluetooth::BluetoothClient,
+    config: &Config,
+    address: &str,
+    confirm: bool,
+) -> Result<(), Box<dyn Error>> {
+    if config.safe_mode && !confirm && client.is_device_connected(address)? {
+        config.check_destructive_action("disconnect", confirm)?;
+    }
+
+    Ok(())
+}
+
+fn set_favourite(
+    client: &bluetooth::BluetoothClient,
+    config: &Config,
+    device_id: &str,
+    favourite: bool,
+) -> Result<(), Box<dyn Error>> {
+    let address = resolve_device_id(client, config, device_id)?;
+
+    Ok(client.set_favourite(&address, favourite)?)
+}
+
+// Prints the error and exits with the exit code for its kind.
+fn fail<E: Into<Box<dyn Error>>>(err: E) -> ! {
+    let err = err.into();
+    print_error(&err.to_string());
+
+    process::exit(ExitCode::from_error(err.as_ref()).code())
+}
+
+// Prints a message, also posting it as a notification with --notify.
+fn print_message(message: &str, format: OutputFormat) {
+    output::print_message(message, format);
+    notify(message);
+}
+
+fn print_error(message: &str) {
+    eprintln!("{}", message);
+    notify(message);
+}
+
+fn notify(message: &str) {
+    if NOTIFY.load(Ordering::Relaxed) {
+        if let Err(err) = notify::post_notification(notify::NOTIFICATION_TITLE, message) {
+            warn!("Failed to post a notification : {}", err);
+        }
+    }
+}
+
+fn load_history() -> Vec<HistoryEntry> {
+    let history = match History::default_path() {
+        Some(path) => History::new(path),
+        None => fail("Could not determine the data directory"),
+    };
+
+    match history.load() {
+        Ok(entries) => entries,
+        Err(err) => fail(format!("Failed to load history : {}", err)),
+    }
+}
+
+fn last_connected_address() -> String {
+    match history::last_connected(&load_history()) {
+        Some(entry) => entry.address.clone(),
+        None => fail("No device has been connected yet"),
+    }
+}
+
+// Makes a newly connected device the system audio output. The output is looked
+// up by the device's real name, not its alias.
+fn set_audio_output(client: &bluetooth::BluetoothClient, address: &str) {
+    let device = match client.get_device(address) {
+        Ok(device) => device,
+        Err(err) => fail(err),
+    };
+
+    if let Err(err) = audio::set_output_device(&device.name, AUDIO_OUTPUT_TIMEOUT) {
+        fail(err);
+    }
+}
+
+// Prints how connecting each device went, exiting if any of them failed.
+fn report_connect_results(results: Vec<ConnectResult>, message_format: OutputFormat) {
+    let mut failed = false;
+    for (address, result) in results {
+        match result {
+            Ok(_) => print_message(&format!("Connected to {}", address), message_format),
+            Err(err) => {
+                print_error(&format!("Failed to connect to {} : {}", address, err));
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        process::exit(ExitCode::ConnectFailed.code());
     }
 }
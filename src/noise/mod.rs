@@ -0,0 +1,172 @@
+use std::{
+    error::Error,
+    fmt,
+    io::Write,
+    process::{Command, Stdio},
+    str,
+};
+
+use clap::ArgEnum;
+
+// AirPods listening modes, as labelled in Control Center.
+#[derive(Debug, Clone, Copy, PartialEq, ArgEnum)]
+pub enum NoiseMode {
+    Anc,
+    Transparency,
+    Adaptive,
+    Off,
+}
+
+impl NoiseMode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            NoiseMode::Anc => "Noise Cancellation",
+            NoiseMode::Transparency => "Transparency",
+            NoiseMode::Adaptive => "Adaptive",
+            NoiseMode::Off => "Off",
+        }
+    }
+}
+
+// Clicks the mode in Control Center's Sound module. Needs the calling app to be
+// allowed to control the computer in the Accessibility settings.
+const CONTROL_CENTER_SCRIPT: &str = r#"on run argv
+    set deviceName to item 1 of argv
+    set modeLabel to item 2 of argv
+    tell application "System Events" to tell process "ControlCenter"
+        click (first menu bar item of menu bar 1 whose description is "Sound")
+        delay 0.5
+        set soundWindow to window 1
+        set deviceItem to (first checkbox of scroll area 1 of group 1 of soundWindow whose title is deviceName)
+        if value of deviceItem is 0 then
+            click deviceItem
+            delay 0.5
+        end if
+        click (first checkbox of scroll area 1 of group 1 of soundWindow whose title is modeLabel)
+        key code 53
+    end tell
+end run"#;
+
+// How the mode change is applied. A Shortcut is more reliable than scripting
+// Control Center, whose layout changes between macOS releases, but has to be
+// created by the user.
+#[derive(Debug, PartialEq)]
+pub enum NoiseBridge {
+    ControlCenter,
+    // Name of a Shortcut that receives the mode label as its input
+    Shortcut(String),
+}
+
+impl NoiseBridge {
+    pub fn from_shortcut(shortcut: Option<String>) -> Self {
+        match shortcut {
+            Some(name) => NoiseBridge::Shortcut(name),
+            None => NoiseBridge::ControlCenter,
+        }
+    }
+
+    // The command, its arguments and what to write to its stdin.
+    pub fn command(
+        &self,
+        device_name: &str,
+        mode: NoiseMode,
+    ) -> (&'static str, Vec<String>, String) {
+        match self {
+            NoiseBridge::ControlCenter => (
+                "osascript",
+                vec![
+                    String::from("-"),
+                    device_name.to_string(),
+                    mode.label().to_string(),
+                ],
+                CONTROL_CENTER_SCRIPT.to_string(),
+            ),
+            NoiseBridge::Shortcut(name) => (
+                "shortcuts",
+                vec![
+                    String::from("run"),
+                    name.clone(),
+                    String::from("--input-path"),
+                    String::from("-"),
+                ],
+                mode.label().to_string(),
+            ),
+        }
+    }
+}
+
+pub fn set_noise_mode(
+    bridge: &NoiseBridge,
+    device_name: &str,
+    mode: NoiseMode,
+) -> Result<(), Box<dyn Error>> {
+    let (command, args, input) = bridge.command(device_name, mode);
+
+    let mut child = Command::new(command)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes())?;
+    }
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(Box::new(NoiseError::new(&format!(
+            "Failed to set the noise mode to {} : {}",
+            mode.label(),
+            str::from_utf8(&output.stderr).unwrap_or_default().trim()
+        ))));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct NoiseError {
+    details: String,
+}
+
+impl NoiseError {
+    fn new(msg: &str) -> NoiseError {
+        NoiseError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for NoiseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for NoiseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_center_bridge_passes_device_and_label_as_arguments() {
+        let (command, args, input) =
+            NoiseBridge::ControlCenter.command("AirPods Pro", NoiseMode::Anc);
+
+        assert_eq!(command, "osascript");
+        assert_eq!(args, vec!["-", "AirPods Pro", "Noise Cancellation"]);
+        assert_eq!(input, CONTROL_CENTER_SCRIPT);
+    }
+
+    #[test]
+    fn shortcut_bridge_passes_label_as_input() {
+        let bridge = NoiseBridge::from_shortcut(Some(String::from("AirPods Noise")));
+        let (command, args, input) = bridge.command("AirPods Pro", NoiseMode::Transparency);
+
+        assert_eq!(command, "shortcuts");
+        assert_eq!(args, vec!["run", "AirPods Noise", "--input-path", "-"]);
+        assert_eq!(input, "Transparency");
+    }
+}
@@ -0,0 +1,47 @@
+use std::{error::Error, process::Command, str};
+
+use log::warn;
+
+pub const NOTIFICATION_TITLE: &str = "Airpods";
+
+// Posts a Notification Center banner through osascript, since Alfred runs the
+// workflow without showing its output.
+pub fn post_notification(title: &str, message: &str) -> Result<(), Box<dyn Error>> {
+    let output = Command::new("osascript")
+        .args(["-e", &notification_script(title, message)])
+        .output()?;
+
+    if !output.status.success() {
+        warn!(
+            "osascript failed to post a notification : {}",
+            str::from_utf8(&output.stderr).unwrap_or_default().trim()
+        );
+    }
+
+    Ok(())
+}
+
+pub fn notification_script(title: &str, message: &str) -> String {
+    format!(
+        "display notification \"{}\" with title \"{}\"",
+        escape_applescript(message),
+        escape_applescript(title)
+    )
+}
+
+fn escape_applescript(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notification_script_escapes_quotes() {
+        assert_eq!(
+            notification_script("Airpods", r#"Connected to "Work" \ Pods"#),
+            r#"display notification "Connected to \"Work\" \\ Pods" with title "Airpods""#
+        );
+    }
+}
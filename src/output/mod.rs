@@ -0,0 +1,575 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use clap::ArgEnum;
+use json::{self, object};
+use serde::{Deserialize, Serialize};
+
+use super::bluetooth::{Capabilities, Capability, DeviceInfo};
+use super::discovery::DiscoveredDevice;
+use super::history::HistoryEntry;
+use super::stats::{LatencyReport, OperationStats};
+use super::utilities::{self, AlfredOutputOptions, TimeFormat};
+
+#[derive(Debug, Clone, Copy, PartialEq, ArgEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    // Alfred Script Filter JSON
+    Alfred,
+    Json,
+    // Aligned columns with a header, for people
+    Table,
+    // Tab separated lines without a header, for scripts
+    Plain,
+}
+
+pub fn render_devices(
+    devices: Vec<DeviceInfo>,
+    format: OutputFormat,
+    alfred_options: &AlfredOutputOptions,
+    now: DateTime<Utc>,
+) -> String {
+    render_devices_with_discovered(devices, &[], format, alfred_options, now)
+}
+
+// Renders the devices followed by nearby devices that were discovered but aren't
+// paired. Scanned AirPods are identified by their CoreBluetooth UUID instead of
+// an address.
+pub fn render_devices_with_discovered(
+    devices: Vec<DeviceInfo>,
+    discovered: &[DiscoveredDevice],
+    format: OutputFormat,
+    alfred_options: &AlfredOutputOptions,
+    now: DateTime<Utc>,
+) -> String {
+    match format {
+        OutputFormat::Alfred => {
+            let mut output = utilities::build_alfred_output(devices, alfred_options, now);
+            for device in discovered {
+                output["items"]
+                    .push(utilities::build_alfred_discovered_item(device))
+                    .unwrap();
+            }
+
+            output.dump()
+        }
+        OutputFormat::Json => {
+            let mut data = json::JsonValue::new_array();
+            for device in &devices {
+                let mut item = utilities::build_info_output(device);
+                if let Some(format) = alfred_options.last_used_format {
+                    item["last_used"] = last_used_label(device, format, now).into();
+                }
+
+                data.push(item).unwrap();
+            }
+            for device in discovered {
+                data.push(object! {
+                    identifier: device.identifier.clone(),
+                    name: device.display_name(),
+                    model: device.model,
+                    rssi: device.rssi,
+                    address: device.address.clone(),
+                    paired: false,
+                })
+                .unwrap();
+            }
+
+            data.dump()
+        }
+        OutputFormat::Table => {
+            render_table(&devices, discovered, alfred_options.last_used_format, now)
+        }
+        OutputFormat::Plain => devices
+            .iter()
+            .map(|x| render_plain_line(x, alfred_options.last_used_format, now))
+            .chain(
+                discovered
+                    .iter()
+                    .map(|x| format!("{}\t{}\tnearby", x.display_name(), x.identifier)),
+            )
+            .collect::<Vec<String>>()
+            .join("\n"),
+    }
+}
+
+pub fn render_profiles(profiles: &[(&str, &[String])], format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Alfred => utilities::build_alfred_profiles_output(profiles).dump(),
+        OutputFormat::Json => {
+            let mut data = json::JsonValue::new_object();
+            for (name, addresses) in profiles {
+                data[*name] = addresses.to_vec().into();
+            }
+
+            data.dump()
+        }
+        OutputFormat::Table | OutputFormat::Plain => profiles
+            .iter()
+            .map(|(name, addresses)| format!("{}\t{}", name, addresses.join(",")))
+            .collect::<Vec<String>>()
+            .join("\n"),
+    }
+}
+
+// Alfred has no capabilities view so it gets the same JSON as the json format.
+pub fn render_capabilities(capabilities: &Capabilities, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Alfred | OutputFormat::Json => {
+            let mut data = object! { backend: capabilities.backend.as_str() };
+            for capability in Capability::ALL {
+                data[capability.as_str()] = capabilities.supports(*capability).into();
+            }
+
+            data.dump()
+        }
+        OutputFormat::Table => render_columns(
+            ["OPERATION", "SUPPORTED"],
+            Capability::ALL
+                .iter()
+                .map(|x| {
+                    [
+                        x.as_str().to_string(),
+                        if capabilities.supports(*x) {
+                            "yes"
+                        } else {
+                            "no"
+                        }
+                        .to_string(),
+                    ]
+                })
+                .collect(),
+        ),
+        OutputFormat::Plain => Capability::ALL
+            .iter()
+            .map(|x| format!("{}\t{}", x.as_str(), capabilities.supports(*x)))
+            .collect::<Vec<String>>()
+            .join("\n"),
+    }
+}
+
+// Alfred has no history view so it gets the same JSON as the json format.
+pub fn render_history(entries: &[&HistoryEntry], format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Alfred | OutputFormat::Json => {
+            serde_json::to_string(entries).unwrap_or_else(|_| String::from("[]"))
+        }
+        OutputFormat::Table | OutputFormat::Plain => entries
+            .iter()
+            .map(|x| {
+                format!(
+                    "{}\t{}\t{}\t{}",
+                    x.timestamp.to_rfc3339(),
+                    x.action.as_str(),
+                    x.address,
+                    match &x.error {
+                        Some(err) => format!("failed : {}", err),
+                        None => String::from("ok"),
+                    }
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n"),
+    }
+}
+
+pub fn render_latency_report(report: &LatencyReport, format: OutputFormat) -> String {
+    let operations = [
+        ("connect", &report.connect),
+        ("disconnect", &report.disconnect),
+    ];
+
+    match format {
+        OutputFormat::Alfred | OutputFormat::Json => {
+            utilities::build_latency_report_output(report).dump()
+        }
+        OutputFormat::Table => render_columns(
+            ["OPERATION", "SUCCEEDED", "MIN", "AVG", "MAX"],
+            operations
+                .iter()
+                .map(|(name, stats)| latency_row(name, stats))
+                .collect(),
+        ),
+        OutputFormat::Plain => operations
+            .iter()
+            .map(|(name, stats)| latency_row(name, stats).join("\t"))
+            .collect::<Vec<String>>()
+            .join("\n"),
+    }
+}
+
+fn latency_row(name: &str, stats: &OperationStats) -> [String; 5] {
+    let format_duration =
+        |x: Option<Duration>| x.map_or(String::from("-"), |x| format!("{}ms", x.as_millis()));
+
+    [
+        name.to_string(),
+        format!("{}/{}", stats.successes(), stats.attempts),
+        format_duration(stats.min()),
+        format_duration(stats.avg()),
+        format_duration(stats.max()),
+    ]
+}
+
+pub fn render_device(device: &DeviceInfo, format: OutputFormat, now: DateTime<Utc>) -> String {
+    match format {
+        OutputFormat::Json => utilities::build_info_output(device).pretty(2),
+        _ => render_devices(vec![device.clone()], format, &Default::default(), now),
+    }
+}
+
+// Messages are printed as is, apart from JSON where they're wrapped in an object.
+pub fn render_message(message: &str, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Json => object! { message: message }.dump(),
+        _ => message.to_string(),
+    }
+}
+
+pub fn print_message(message: &str, format: OutputFormat) {
+    println!("{}", render_message(message, format));
+}
+
+// When the device was last used in the --last-used format, None if it's unknown.
+fn last_used_label(device: &DeviceInfo, format: TimeFormat, now: DateTime<Utc>) -> Option<String> {
+    device
+        .last_used
+        .map(|x| utilities::format_last_used(x, now, format))
+}
+
+// With a last used format there's a fourth column, empty when it isn't known.
+fn render_plain_line(
+    device: &DeviceInfo,
+    last_used_format: Option<TimeFormat>,
+    now: DateTime<Utc>,
+) -> String {
+    let line = format!(
+        "{}\t{}\t{}",
+        device.name,
+        device.address,
+        connection_label(device)
+    );
+
+    match last_used_format {
+        Some(format) => format!(
+            "{}\t{}",
+            line,
+            last_used_label(device, format, now).unwrap_or_default()
+        ),
+        None => line,
+    }
+}
+
+fn render_table(
+    devices: &[DeviceInfo],
+    discovered: &[DiscoveredDevice],
+    last_used_format: Option<TimeFormat>,
+    now: DateTime<Utc>,
+) -> String {
+    let rows = devices
+        .iter()
+        .map(|x| {
+            [
+                x.name.clone(),
+                x.address.clone(),
+                connection_label(x).to_string(),
+                if x.favourite { "yes" } else { "no" }.to_string(),
+                x.battery.map_or(String::from("-"), |x| format!("{}%", x)),
+            ]
+        })
+        .chain(discovered.iter().map(|x| {
+            [
+                x.display_name().to_string(),
+                x.identifier.clone(),
+                String::from("nearby"),
+                String::from("no"),
+                String::from("-"),
+            ]
+        }))
+        .collect::<Vec<_>>();
+
+    let format = match last_used_format {
+        Some(format) => format,
+        None => return render_columns(["NAME", "ADDRESS", "STATE", "FAVOURITE", "BATTERY"], rows),
+    };
+
+    // Discovered devices have never been used
+    let last_used = devices
+        .iter()
+        .map(|x| last_used_label(x, format, now).unwrap_or_else(|| String::from("-")))
+        .chain(discovered.iter().map(|_| String::from("-")));
+    let rows = rows
+        .into_iter()
+        .zip(last_used)
+        .map(|([name, address, state, favourite, battery], last_used)| {
+            [name, address, state, favourite, battery, last_used]
+        })
+        .collect();
+
+    render_columns(
+        [
+            "NAME",
+            "ADDRESS",
+            "STATE",
+            "FAVOURITE",
+            "BATTERY",
+            "LAST USED",
+        ],
+        rows,
+    )
+}
+
+// Pads every column to its widest value, with the header as the first row.
+fn render_columns<const N: usize>(header: [&str; N], rows: Vec<[String; N]>) -> String {
+    let widths = (0..header.len())
+        .map(|column| {
+            rows.iter()
+                .map(|x| x[column].chars().count())
+                .chain(std::iter::once(header[column].len()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<usize>>();
+
+    std::iter::once(header.map(String::from))
+        .chain(rows)
+        .map(|row| {
+            row.iter()
+                .zip(&widths)
+                .map(|(value, width)| {
+                    format!("{}{}", value, " ".repeat(width - value.chars().count()))
+                })
+                .collect::<Vec<String>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn connection_label(device: &DeviceInfo) -> &'static str {
+    if device.connected {
+        "connected"
+    } else {
+        "disconnected"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::super::bluetooth::Backend;
+    use super::super::history::HistoryAction;
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2022, 8, 1, 12, 0, 0).unwrap()
+    }
+
+    fn devices() -> Vec<DeviceInfo> {
+        vec![
+            DeviceInfo {
+                name: String::from("AirPods Pro"),
+                address: String::from("80-3b-5c-c2-b1-7f"),
+                connected: true,
+                favourite: true,
+                battery: Some(80),
+                ..Default::default()
+            },
+            DeviceInfo {
+                name: String::from("Keyboard"),
+                address: String::from("5c-2e-fg-da-a3-43"),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn render_devices_as_table() {
+        assert_eq!(
+            render_devices(devices(), OutputFormat::Table, &Default::default(), now()),
+            "NAME         ADDRESS            STATE         FAVOURITE  BATTERY
+AirPods Pro  80-3b-5c-c2-b1-7f  connected     yes        80%
+Keyboard     5c-2e-fg-da-a3-43  disconnected  no         -"
+        );
+    }
+
+    #[test]
+    fn render_devices_as_plain_lines() {
+        assert_eq!(
+            render_devices(devices(), OutputFormat::Plain, &Default::default(), now()),
+            "AirPods Pro\t80-3b-5c-c2-b1-7f\tconnected\nKeyboard\t5c-2e-fg-da-a3-43\tdisconnected"
+        );
+    }
+
+    #[test]
+    fn render_devices_as_json() {
+        let output = render_devices(devices(), OutputFormat::Json, &Default::default(), now());
+        let parsed = json::parse(&output).unwrap();
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0]["name"], "AirPods Pro");
+        assert_eq!(parsed[1]["connected"], false);
+    }
+
+    #[test]
+    fn render_devices_with_last_used_in_non_alfred_formats() {
+        let mut devices = devices();
+        devices[0].last_used = Some(now() - chrono::Duration::minutes(5));
+        let options = AlfredOutputOptions {
+            last_used_format: Some(TimeFormat::Relative),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            render_devices(devices.clone(), OutputFormat::Plain, &options, now()),
+            "AirPods Pro\t80-3b-5c-c2-b1-7f\tconnected\tused 5 minutes ago\nKeyboard\t5c-2e-fg-da-a3-43\tdisconnected\t"
+        );
+        assert_eq!(
+            render_devices(devices.clone(), OutputFormat::Table, &options, now()),
+            "NAME         ADDRESS            STATE         FAVOURITE  BATTERY  LAST USED
+AirPods Pro  80-3b-5c-c2-b1-7f  connected     yes        80%      used 5 minutes ago
+Keyboard     5c-2e-fg-da-a3-43  disconnected  no         -        -"
+        );
+
+        let parsed = json::parse(&render_devices(
+            devices,
+            OutputFormat::Json,
+            &options,
+            now(),
+        ))
+        .unwrap();
+        assert_eq!(parsed[0]["last_used"], "used 5 minutes ago");
+        assert!(parsed[1]["last_used"].is_null());
+    }
+
+    #[test]
+    fn render_devices_as_alfred() {
+        let output = render_devices(devices(), OutputFormat::Alfred, &Default::default(), now());
+        let parsed = json::parse(&output).unwrap();
+
+        assert_eq!(parsed["items"][0]["title"], "AirPods Pro (Connected)");
+    }
+
+    #[test]
+    fn render_capabilities_lists_every_operation() {
+        let capabilities = Capabilities {
+            pair: false,
+            ..Capabilities::all(Backend::Fake)
+        };
+
+        let parsed = json::parse(&render_capabilities(&capabilities, OutputFormat::Json)).unwrap();
+        assert_eq!(parsed["backend"], "fake");
+        assert_eq!(parsed["connect"], true);
+        assert_eq!(parsed["pair"], false);
+
+        assert_eq!(
+            render_capabilities(&capabilities, OutputFormat::Plain),
+            "connect\ttrue\npair\tfalse\nfavourite\ttrue\nset-power\ttrue\nrecent-devices\ttrue\ndiscover\ttrue"
+        );
+    }
+
+    #[test]
+    fn render_devices_appends_discovered_devices() {
+        let discovered = [DiscoveredDevice {
+            identifier: String::from("0C6A1B2E-7E3F-4F7A-9D1C-2B3A4C5D6E7F"),
+            name: None,
+            rssi: -50,
+            model: "AirPods Max",
+            address: None,
+        }];
+
+        let output = render_devices_with_discovered(
+            devices(),
+            &discovered,
+            OutputFormat::Plain,
+            &Default::default(),
+            now(),
+        );
+        assert_eq!(
+            output.lines().last(),
+            Some("AirPods Max\t0C6A1B2E-7E3F-4F7A-9D1C-2B3A4C5D6E7F\tnearby")
+        );
+
+        let output = render_devices_with_discovered(
+            devices(),
+            &discovered,
+            OutputFormat::Alfred,
+            &Default::default(),
+            now(),
+        );
+        let parsed = json::parse(&output).unwrap();
+        assert_eq!(parsed["items"].len(), 3);
+        assert_eq!(parsed["items"][2]["variables"]["action"], "pair");
+    }
+
+    #[test]
+    fn render_profiles_as_json_and_plain() {
+        let work = vec![String::from("address-1"), String::from("address-2")];
+        let profiles = [("work", work.as_slice())];
+
+        assert_eq!(
+            render_profiles(&profiles, OutputFormat::Json),
+            r#"{"work":["address-1","address-2"]}"#
+        );
+        assert_eq!(
+            render_profiles(&profiles, OutputFormat::Plain),
+            "work\taddress-1,address-2"
+        );
+    }
+
+    #[test]
+    fn render_history_as_plain_and_json() {
+        let entry = HistoryEntry {
+            timestamp: now(),
+            action: HistoryAction::Connect,
+            address: String::from("80-3b-5c-c2-b1-7f"),
+            succeeded: false,
+            error: Some(String::from("Failed to connect")),
+        };
+
+        assert_eq!(
+            render_history(&[&entry], OutputFormat::Plain),
+            "2022-08-01T12:00:00+00:00\tconnect\t80-3b-5c-c2-b1-7f\tfailed : Failed to connect"
+        );
+
+        let parsed = json::parse(&render_history(&[&entry], OutputFormat::Json)).unwrap();
+        assert_eq!(parsed[0]["action"], "connect");
+        assert_eq!(parsed[0]["succeeded"], false);
+    }
+
+    #[test]
+    fn render_latency_report_as_table() {
+        let report = LatencyReport {
+            connect: OperationStats {
+                attempts: 2,
+                durations: vec![Duration::from_millis(1500), Duration::from_millis(2500)],
+            },
+            disconnect: OperationStats {
+                attempts: 2,
+                durations: vec![],
+            },
+        };
+
+        assert_eq!(
+            render_latency_report(&report, OutputFormat::Table),
+            "OPERATION   SUCCEEDED  MIN     AVG     MAX
+connect     2/2        1500ms  2000ms  2500ms
+disconnect  0/2        -       -       -"
+        );
+    }
+
+    #[test]
+    fn render_message_wraps_json() {
+        assert_eq!(
+            render_message("Connected to device", OutputFormat::Json),
+            r#"{"message":"Connected to device"}"#
+        );
+        assert_eq!(
+            render_message("Connected to device", OutputFormat::Plain),
+            "Connected to device"
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::warn;
+
+use super::bluetooth::{BluetoothClient, BluetoothError};
+
+const DISCONNECT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// How long each successful run of an operation took, out of every attempt.
+#[derive(Debug, Default, PartialEq)]
+pub struct OperationStats {
+    pub attempts: u32,
+    pub durations: Vec<Duration>,
+}
+
+impl OperationStats {
+    pub fn record(&mut self, result: Result<Duration, BluetoothError>) {
+        self.attempts += 1;
+
+        match result {
+            Ok(duration) => self.durations.push(duration),
+            Err(err) => warn!("Attempt {} failed : {}", self.attempts, err),
+        }
+    }
+
+    pub fn successes(&self) -> u32 {
+        self.durations.len() as u32
+    }
+
+    pub fn success_rate(&self) -> f64 {
+        if self.attempts == 0 {
+            return 0.0;
+        }
+
+        self.successes() as f64 / self.attempts as f64
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.durations.iter().min().copied()
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.durations.iter().max().copied()
+    }
+
+    pub fn avg(&self) -> Option<Duration> {
+        if self.durations.is_empty() {
+            return None;
+        }
+
+        Some(self.durations.iter().sum::<Duration>() / self.durations.len() as u32)
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct LatencyReport {
+    pub connect: OperationStats,
+    pub disconnect: OperationStats,
+}
+
+// Connects and disconnects the device `runs` times, timing how long each takes
+// to be verified. Leaves the device disconnected.
+pub fn measure_latency(
+    client: &BluetoothClient,
+    address: &str,
+    runs: u32,
+    timeout: Duration,
+) -> LatencyReport {
+    let mut report = LatencyReport::default();
+
+    for _ in 0..runs {
+        let started = Instant::now();
+        report.connect.record(
+            client
+                .connect_and_verify(address, timeout)
+                .map(|_| started.elapsed()),
+        );
+
+        let started = Instant::now();
+        report
+            .disconnect
+            .record(disconnect_and_wait(client, address, timeout).map(|_| started.elapsed()));
+    }
+
+    report
+}
+
+fn disconnect_and_wait(
+    client: &BluetoothClient,
+    address: &str,
+    timeout: Duration,
+) -> Result<(), BluetoothError> {
+    client.disconnect_from_device(address)?;
+
+    let deadline = Instant::now() + timeout;
+    while client.is_device_connected(address)? {
+        if Instant::now() >= deadline {
+            return Err(BluetoothError::Timeout(format!(
+                "Device '{}' did not disconnect",
+                address
+            )));
+        }
+
+        thread::sleep(DISCONNECT_POLL_INTERVAL);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operation_stats_summarises_successful_attempts() {
+        let mut stats = OperationStats::default();
+        stats.record(Ok(Duration::from_millis(1000)));
+        stats.record(Err(BluetoothError::Other(String::from("failed"))));
+        stats.record(Ok(Duration::from_millis(2000)));
+        stats.record(Ok(Duration::from_millis(3000)));
+
+        assert_eq!(stats.attempts, 4);
+        assert_eq!(stats.successes(), 3);
+        assert_eq!(stats.success_rate(), 0.75);
+        assert_eq!(stats.min(), Some(Duration::from_millis(1000)));
+        assert_eq!(stats.avg(), Some(Duration::from_millis(2000)));
+        assert_eq!(stats.max(), Some(Duration::from_millis(3000)));
+    }
+
+    #[test]
+    fn operation_stats_without_successes_has_no_latency() {
+        let mut stats = OperationStats::default();
+        stats.record(Err(BluetoothError::Other(String::from("failed"))));
+
+        assert_eq!(stats.success_rate(), 0.0);
+        assert_eq!(stats.avg(), None);
+        assert_eq!(stats.min(), None);
+    }
+}
@@ -1,39 +1,312 @@
-use super::bluetooth::DeviceInfo;
+use super::bluetooth::{ConnectStats, DeviceEvent, DeviceInfo};
+use super::discovery::{DiscoveredDevice, BLUETOOTH_SETTINGS_URL};
+use super::stats::{LatencyReport, OperationStats};
+use chrono::{DateTime, Utc};
+use clap::ArgEnum;
 use json::{self, object};
 
-pub fn print_alfred_output(devices: Vec<DeviceInfo>) {
+#[derive(Debug, Clone, Copy, PartialEq, ArgEnum)]
+pub enum TimeFormat {
+    Absolute,
+    Relative,
+}
+
+#[derive(Debug, Default)]
+pub struct AlfredOutputOptions {
+    // When set, the subtitle includes when the device was last used, and the
+    // other list formats get a last used column or field
+    pub last_used_format: Option<TimeFormat>,
+    // When set, the subtitle states what pressing enter will do and the MAC
+    // address moves to the alt modifier
+    pub action_labels: bool,
+    // Subtitle templates used instead of the default subtitle, see render_subtitle
+    pub connected_subtitle: Option<String>,
+    pub disconnected_subtitle: Option<String>,
+    // Shown in an item above the devices, e.g. when the list is degraded
+    pub warning: Option<String>,
+}
+
+pub fn build_alfred_output(
+    devices: Vec<DeviceInfo>,
+    options: &AlfredOutputOptions,
+    now: DateTime<Utc>,
+) -> json::JsonValue {
     let mut data = json::JsonValue::new_array();
 
+    if let Some(warning) = &options.warning {
+        data.push(build_alfred_warning_item(warning))
+            .expect("Error generating output for Alfred");
+    }
+
     for device in devices {
         let mut title = format!("{} (Connected)", device.name);
         if !device.connected {
-            title = device.name;
+            title = device.name.clone();
+        }
+        if device.active_output {
+            title = format!("🔊 {}", title);
         }
 
-        data.push(object! {
+        let mac = format!("MAC:{}", device.address);
+        let mut subtitle = if options.action_labels {
+            action_label(device.connected).to_string()
+        } else {
+            mac.clone()
+        };
+        if let (Some(time_format), Some(last_used)) = (options.last_used_format, device.last_used) {
+            subtitle = format!(
+                "{} · {}",
+                subtitle,
+                format_last_used(last_used, now, time_format)
+            );
+        }
+
+        let template = if device.connected {
+            &options.connected_subtitle
+        } else {
+            &options.disconnected_subtitle
+        };
+        if let Some(template) = template {
+            subtitle = render_subtitle(template, &device, options, now);
+        }
+
+        let mut item = object! {
             type: "default",
             title: title,
-            subtitle: format!("MAC:{}", device.address),
-            arg: device.address,
+            subtitle: subtitle,
+            arg: device.address.clone(),
+        };
+
+        if options.action_labels {
+            item["mods"] = object! {
+                alt: {
+                    subtitle: mac,
+                    arg: device.address,
+                }
+            };
+        }
+
+        // Bad data can produce identical rows, which Alfred would show twice
+        if data.members().any(|x| *x == item) {
+            continue;
+        }
+
+        data.push(item).expect("Error generating output for Alfred");
+    }
+
+    object! {
+        items: data
+    }
+}
+
+// Can't be actioned, it's only there to be read.
+pub fn build_alfred_warning_item(warning: &str) -> json::JsonValue {
+    object! {
+        type: "default",
+        title: "⚠️ Limited device list",
+        subtitle: warning,
+        valid: false,
+    }
+}
+
+// Alfred items for profiles, passing the profile name on to `profile connect`.
+pub fn build_alfred_profiles_output(profiles: &[(&str, &[String])]) -> json::JsonValue {
+    let mut data = json::JsonValue::new_array();
+
+    for (name, addresses) in profiles {
+        let plural = if addresses.len() == 1 { "" } else { "s" };
+
+        data.push(object! {
+            type: "default",
+            title: *name,
+            subtitle: format!("↵ Connect {} device{}", addresses.len(), plural),
+            arg: *name,
         })
         .expect("Error generating output for Alfred");
     }
 
-    let items = object! {
+    object! {
         items: data
+    }
+}
+
+// Substitutes {name}, {address}, {battery}, {rssi}, {last_used}, {favourite},
+// {paired} and {action} in the template. Unknown values render as an empty
+// string, as does {favourite} for devices that aren't favourites. {battery} shows
+// each bud and the case when they're known, {rssi} is only known while connected.
+pub fn render_subtitle(
+    template: &str,
+    device: &DeviceInfo,
+    options: &AlfredOutputOptions,
+    now: DateTime<Utc>,
+) -> String {
+    let battery = match &device.battery_levels {
+        Some(levels) if levels.has_buds() => levels.to_string(),
+        _ => device.battery.map_or(String::new(), |x| format!("{}%", x)),
     };
+    let rssi = device.rssi.map_or(String::new(), |x| format!("{} dBm", x));
+    let last_used = device.last_used.map_or(String::new(), |x| {
+        format_last_used(
+            x,
+            now,
+            options.last_used_format.unwrap_or(TimeFormat::Relative),
+        )
+    });
 
-    println!("{}", items.dump());
+    template
+        .replace("{name}", &device.name)
+        .replace("{address}", &device.address)
+        .replace("{battery}", &battery)
+        .replace("{rssi}", &rssi)
+        .replace("{last_used}", &last_used)
+        .replace("{favourite}", if device.favourite { "★" } else { "" })
+        .replace(
+            "{paired}",
+            if device.paired {
+                "Paired"
+            } else {
+                "Not paired"
+            },
+        )
+        .replace("{action}", action_label(device.connected))
+}
+
+// Describes the action toggling the device will perform.
+pub fn action_label(connected: bool) -> &'static str {
+    if connected {
+        "↵ Disconnect"
+    } else {
+        "↵ Connect"
+    }
+}
+
+pub fn build_status_output(device: &DeviceInfo) -> json::JsonValue {
+    object! {
+        connected: device.connected,
+        name: device.name.clone(),
+        address: device.address.clone(),
+    }
+}
+
+// Everything known about a device, for the info command.
+pub fn build_info_output(device: &DeviceInfo) -> json::JsonValue {
+    object! {
+        name: device.name.clone(),
+        address: device.address.clone(),
+        connected: device.connected,
+        paired: device.paired,
+        favourite: device.favourite,
+        recent_access_date: device.last_used.map(|x| x.to_rfc3339()),
+        battery: device.battery,
+        rssi: device.rssi,
+    }
+}
+
+pub fn build_connect_stats_output(stats: &ConnectStats) -> json::JsonValue {
+    object! {
+        attempts: stats.attempts,
+        succeeded: stats.succeeded,
+        total_ms: stats.total_ms as u64,
+        final_state: stats.final_state.map_or("unknown", |state| state.as_str()),
+    }
+}
+
+// Scanned AirPods can't be paired by address as CoreBluetooth doesn't expose it,
+// so the item opens the Bluetooth settings where they can be paired. Devices
+// found by an inquiry pass their address on to `pair` instead. The action
+// variable lets the workflow route the items differently.
+pub fn build_alfred_discovered_item(device: &DiscoveredDevice) -> json::JsonValue {
+    if let Some(address) = &device.address {
+        return object! {
+            type: "default",
+            title: format!("{} (Nearby)", device.display_name()),
+            subtitle: "↵ Pair",
+            arg: address.clone(),
+            variables: {
+                action: "pair-device",
+            },
+        };
+    }
+
+    object! {
+        type: "default",
+        title: format!("{} (Nearby)", device.display_name()),
+        subtitle: "↵ Pair in Bluetooth settings",
+        arg: BLUETOOTH_SETTINGS_URL,
+        variables: {
+            action: "pair",
+        },
+    }
+}
+
+pub fn build_latency_report_output(report: &LatencyReport) -> json::JsonValue {
+    let build_operation = |stats: &OperationStats| {
+        object! {
+            attempts: stats.attempts,
+            successes: stats.successes(),
+            success_rate: stats.success_rate(),
+            min_ms: stats.min().map(|x| x.as_millis() as u64),
+            avg_ms: stats.avg().map(|x| x.as_millis() as u64),
+            max_ms: stats.max().map(|x| x.as_millis() as u64),
+        }
+    };
+
+    object! {
+        connect: build_operation(&report.connect),
+        disconnect: build_operation(&report.disconnect),
+    }
+}
+
+// A single line of the watch command's NDJSON output.
+pub fn build_event_output(event: &DeviceEvent) -> json::JsonValue {
+    object! {
+        event: event.state.as_str(),
+        name: event.name.clone(),
+        address: event.address.clone(),
+        timestamp: event.timestamp.to_rfc3339(),
+    }
+}
+
+pub fn format_last_used(
+    last_used: DateTime<Utc>,
+    now: DateTime<Utc>,
+    format: TimeFormat,
+) -> String {
+    match format {
+        TimeFormat::Absolute => format!("last used {}", last_used.format("%Y-%m-%d %H:%M:%S UTC")),
+        TimeFormat::Relative => format!("used {}", format_relative(last_used, now)),
+    }
+}
+
+// Humanizes how long ago `dt` was relative to `now`, e.g. "5 minutes ago".
+pub fn format_relative(dt: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let elapsed = now.signed_duration_since(dt);
+
+    let (count, unit) = if elapsed.num_minutes() < 1 {
+        return String::from("just now");
+    } else if elapsed.num_hours() < 1 {
+        (elapsed.num_minutes(), "minute")
+    } else if elapsed.num_days() < 1 {
+        (elapsed.num_hours(), "hour")
+    } else {
+        (elapsed.num_days(), "day")
+    };
+
+    if count == 1 {
+        format!("1 {} ago", unit)
+    } else {
+        format!("{} {}s ago", count, unit)
+    }
 }
 
 pub fn device_list_from_cli_arg(device_list: &str) -> Option<Vec<String>> {
-    if device_list.len() == 0 {
+    if device_list.is_empty() {
         return None;
     }
 
     let results = device_list
         .split(",")
-        .map(|x| x.to_string())
+        .map(|x| x.trim().to_string())
         .collect::<Vec<String>>();
 
     match results.len() {
@@ -41,3 +314,465 @@ pub fn device_list_from_cli_arg(device_list: &str) -> Option<Vec<String>> {
         _ => Some(results),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+    use crate::battery::BatteryInfo;
+    use crate::bluetooth::ConnectionState;
+    use crate::clock::{Clock, FixedClock};
+
+    fn reference_time() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2022, 8, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn format_relative_just_now() {
+        let now = reference_time();
+
+        assert_eq!(format_relative(now, now), "just now");
+        assert_eq!(
+            format_relative(now - Duration::seconds(59), now),
+            "just now"
+        );
+        // Timestamps slightly in the future (clock skew) are treated as now
+        assert_eq!(format_relative(now + Duration::seconds(5), now), "just now");
+    }
+
+    #[test]
+    fn format_relative_minutes() {
+        let now = reference_time();
+
+        assert_eq!(
+            format_relative(now - Duration::minutes(1), now),
+            "1 minute ago"
+        );
+        assert_eq!(
+            format_relative(now - Duration::minutes(5), now),
+            "5 minutes ago"
+        );
+        assert_eq!(
+            format_relative(now - Duration::minutes(59), now),
+            "59 minutes ago"
+        );
+    }
+
+    #[test]
+    fn format_relative_hours() {
+        let now = reference_time();
+
+        assert_eq!(format_relative(now - Duration::hours(1), now), "1 hour ago");
+        assert_eq!(
+            format_relative(now - Duration::minutes(150), now),
+            "2 hours ago"
+        );
+    }
+
+    #[test]
+    fn format_relative_days() {
+        let now = reference_time();
+
+        assert_eq!(format_relative(now - Duration::days(1), now), "1 day ago");
+        assert_eq!(
+            format_relative(now - Duration::days(12), now),
+            "12 days ago"
+        );
+    }
+
+    #[test]
+    fn build_alfred_output_includes_last_used_when_requested() {
+        let now = reference_time();
+        let device = || DeviceInfo {
+            name: String::from("AirPods Pro"),
+            address: String::from("5c-2e-fg-da-a3-43"),
+            connected: false,
+            last_used: Some(now - Duration::minutes(5)),
+            ..Default::default()
+        };
+
+        let output = build_alfred_output(vec![device()], &AlfredOutputOptions::default(), now);
+        assert_eq!(output["items"][0]["subtitle"], "MAC:5c-2e-fg-da-a3-43");
+
+        let relative = AlfredOutputOptions {
+            last_used_format: Some(TimeFormat::Relative),
+            ..Default::default()
+        };
+        let output = build_alfred_output(vec![device()], &relative, now);
+        assert_eq!(
+            output["items"][0]["subtitle"],
+            "MAC:5c-2e-fg-da-a3-43 · used 5 minutes ago"
+        );
+
+        let absolute = AlfredOutputOptions {
+            last_used_format: Some(TimeFormat::Absolute),
+            ..Default::default()
+        };
+        let output = build_alfred_output(vec![device()], &absolute, now);
+        assert_eq!(
+            output["items"][0]["subtitle"],
+            "MAC:5c-2e-fg-da-a3-43 · last used 2022-08-01 11:55:00 UTC"
+        );
+    }
+
+    #[test]
+    fn format_relative_at_fixed_clock_time() {
+        let clock = FixedClock(reference_time());
+
+        assert_eq!(
+            format_relative(reference_time() - Duration::hours(2), clock.now()),
+            "2 hours ago"
+        );
+        assert_eq!(
+            format_last_used(
+                reference_time() - Duration::days(3),
+                clock.now(),
+                TimeFormat::Relative
+            ),
+            "used 3 days ago"
+        );
+    }
+
+    #[test]
+    fn build_alfred_output_action_labels_match_connection_state() {
+        let devices = vec![
+            DeviceInfo {
+                name: String::from("AirPods Max"),
+                address: String::from("80-3b-5c-c2-b1-7f"),
+                connected: true,
+                ..Default::default()
+            },
+            DeviceInfo {
+                name: String::from("AirPods Pro"),
+                address: String::from("5c-2e-fg-da-a3-43"),
+                connected: false,
+                ..Default::default()
+            },
+        ];
+        let options = AlfredOutputOptions {
+            action_labels: true,
+            ..Default::default()
+        };
+
+        let output = build_alfred_output(devices, &options, reference_time());
+
+        assert_eq!(output["items"][0]["subtitle"], "↵ Disconnect");
+        assert_eq!(
+            output["items"][0]["mods"]["alt"]["subtitle"],
+            "MAC:80-3b-5c-c2-b1-7f"
+        );
+        assert_eq!(output["items"][1]["subtitle"], "↵ Connect");
+        assert_eq!(output["items"][1]["arg"], "5c-2e-fg-da-a3-43");
+    }
+
+    #[test]
+    fn build_alfred_output_puts_the_warning_first() {
+        let options = AlfredOutputOptions {
+            warning: Some(String::from("blueutil isn't working")),
+            ..Default::default()
+        };
+
+        let device = DeviceInfo {
+            name: String::from("AirPods Pro"),
+            address: String::from("5c-2e-fg-da-a3-43"),
+            ..Default::default()
+        };
+
+        let output = build_alfred_output(vec![device], &options, reference_time());
+
+        assert_eq!(output["items"].len(), 2);
+        assert_eq!(output["items"][0]["subtitle"], "blueutil isn't working");
+        assert_eq!(output["items"][0]["valid"], false);
+        assert_eq!(output["items"][1]["arg"], "5c-2e-fg-da-a3-43");
+    }
+
+    #[test]
+    fn build_alfred_output_drops_duplicate_items() {
+        let device = || DeviceInfo {
+            name: String::from("AirPods Pro"),
+            address: String::from("5c-2e-fg-da-a3-43"),
+            connected: true,
+            ..Default::default()
+        };
+
+        let output = build_alfred_output(
+            vec![device(), device()],
+            &AlfredOutputOptions::default(),
+            reference_time(),
+        );
+
+        assert_eq!(output["items"].len(), 1);
+        assert_eq!(output["items"][0]["title"], "AirPods Pro (Connected)");
+    }
+
+    #[test]
+    fn build_alfred_output_keeps_distinct_items() {
+        let output = build_alfred_output(
+            vec![
+                DeviceInfo {
+                    name: String::from("AirPods Pro"),
+                    address: String::from("5c-2e-fg-da-a3-43"),
+                    ..Default::default()
+                },
+                DeviceInfo {
+                    name: String::from("AirPods Pro"),
+                    address: String::from("80-3b-5c-c2-b1-7f"),
+                    ..Default::default()
+                },
+            ],
+            &AlfredOutputOptions::default(),
+            reference_time(),
+        );
+
+        assert_eq!(output["items"].len(), 2);
+    }
+
+    #[test]
+    fn build_alfred_output_uses_subtitle_template_per_state() {
+        let devices = vec![
+            DeviceInfo {
+                name: String::from("AirPods Max"),
+                address: String::from("80-3b-5c-c2-b1-7f"),
+                connected: true,
+                battery: Some(80),
+                ..Default::default()
+            },
+            DeviceInfo {
+                name: String::from("AirPods Pro"),
+                address: String::from("5c-2e-fg-da-a3-43"),
+                connected: false,
+                ..Default::default()
+            },
+        ];
+        let options = AlfredOutputOptions {
+            connected_subtitle: Some(String::from("{action} · Battery {battery}")),
+            disconnected_subtitle: Some(String::from("MAC:{address}{battery}")),
+            ..Default::default()
+        };
+
+        let output = build_alfred_output(devices, &options, reference_time());
+
+        assert_eq!(output["items"][0]["subtitle"], "↵ Disconnect · Battery 80%");
+        assert_eq!(output["items"][1]["subtitle"], "MAC:5c-2e-fg-da-a3-43");
+    }
+
+    #[test]
+    fn render_subtitle_shows_each_bud_and_the_case() {
+        let device = DeviceInfo {
+            connected: true,
+            battery: Some(75),
+            battery_levels: Some(BatteryInfo::from_buds(Some(80), Some(75), Some(60))),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            render_subtitle(
+                "Battery {battery}",
+                &device,
+                &Default::default(),
+                reference_time()
+            ),
+            "Battery L 80% · R 75% · Case 60%"
+        );
+    }
+
+    #[test]
+    fn build_alfred_output_template_only_applies_to_its_state() {
+        let devices = vec![DeviceInfo {
+            name: String::from("AirPods Pro"),
+            address: String::from("5c-2e-fg-da-a3-43"),
+            connected: false,
+            last_used: Some(reference_time() - Duration::hours(3)),
+            ..Default::default()
+        }];
+        let options = AlfredOutputOptions {
+            connected_subtitle: Some(String::from("{battery}")),
+            ..Default::default()
+        };
+
+        let output = build_alfred_output(devices, &options, reference_time());
+        assert_eq!(output["items"][0]["subtitle"], "MAC:5c-2e-fg-da-a3-43");
+
+        let template = "{name} {last_used}";
+        let device = DeviceInfo {
+            name: String::from("AirPods Pro"),
+            last_used: Some(reference_time() - Duration::hours(3)),
+            ..Default::default()
+        };
+        assert_eq!(
+            render_subtitle(template, &device, &options, reference_time()),
+            "AirPods Pro used 3 hours ago"
+        );
+    }
+
+    #[test]
+    fn render_subtitle_substitutes_favourite_and_paired() {
+        let template = "{favourite}{name} · {paired}";
+        let mut device = DeviceInfo {
+            name: String::from("AirPods Pro"),
+            favourite: true,
+            paired: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            render_subtitle(template, &device, &Default::default(), reference_time()),
+            "★AirPods Pro · Paired"
+        );
+
+        device.favourite = false;
+        device.paired = false;
+        assert_eq!(
+            render_subtitle(template, &device, &Default::default(), reference_time()),
+            "AirPods Pro · Not paired"
+        );
+    }
+
+    #[test]
+    fn render_subtitle_substitutes_rssi() {
+        let template = "{name} {rssi}";
+        let mut device = DeviceInfo {
+            name: String::from("AirPods Pro"),
+            connected: true,
+            rssi: Some(-56),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            render_subtitle(template, &device, &Default::default(), reference_time()),
+            "AirPods Pro -56 dBm"
+        );
+
+        device.rssi = None;
+        assert_eq!(
+            render_subtitle(template, &device, &Default::default(), reference_time()),
+            "AirPods Pro "
+        );
+    }
+
+    #[test]
+    fn build_alfred_output_badges_active_output() {
+        let devices = vec![
+            DeviceInfo {
+                name: String::from("AirPods Max"),
+                address: String::from("80-3b-5c-c2-b1-7f"),
+                connected: true,
+                active_output: true,
+                ..Default::default()
+            },
+            DeviceInfo {
+                name: String::from("AirPods Pro"),
+                address: String::from("5c-2e-fg-da-a3-43"),
+                connected: true,
+                ..Default::default()
+            },
+        ];
+
+        let output =
+            build_alfred_output(devices, &AlfredOutputOptions::default(), reference_time());
+
+        assert_eq!(output["items"][0]["title"], "🔊 AirPods Max (Connected)");
+        assert_eq!(output["items"][1]["title"], "AirPods Pro (Connected)");
+    }
+
+    #[test]
+    fn device_list_from_cli_arg_trims_addresses() {
+        assert_eq!(
+            device_list_from_cli_arg("80:3B:5C:C2:B1:7F, a4-c6-f0-1d-52-e9"),
+            Some(vec![
+                String::from("80:3B:5C:C2:B1:7F"),
+                String::from("a4-c6-f0-1d-52-e9"),
+            ])
+        );
+        assert_eq!(device_list_from_cli_arg(""), None);
+    }
+
+    #[test]
+    fn build_status_output_shape() {
+        let device = DeviceInfo {
+            name: String::from("AirPods Max"),
+            address: String::from("80-3b-5c-c2-b1-7f"),
+            connected: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            build_status_output(&device).dump(),
+            r#"{"connected":true,"name":"AirPods Max","address":"80-3b-5c-c2-b1-7f"}"#
+        );
+    }
+
+    #[test]
+    fn build_info_output_shape() {
+        let device = DeviceInfo {
+            name: String::from("AirPods Max"),
+            address: String::from("80-3b-5c-c2-b1-7f"),
+            connected: true,
+            paired: true,
+            last_used: Some(reference_time()),
+            rssi: Some(-56),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            build_info_output(&device).dump(),
+            r#"{"name":"AirPods Max","address":"80-3b-5c-c2-b1-7f","connected":true,"paired":true,"favourite":false,"recent_access_date":"2022-08-01T12:00:00+00:00","battery":null,"rssi":-56}"#
+        );
+    }
+
+    #[test]
+    fn build_connect_stats_output_shape() {
+        let stats = ConnectStats {
+            attempts: 2,
+            succeeded: true,
+            total_ms: 1500,
+            final_state: Some(ConnectionState::Connected),
+            error: None,
+        };
+
+        assert_eq!(
+            build_connect_stats_output(&stats).dump(),
+            r#"{"attempts":2,"succeeded":true,"total_ms":1500,"final_state":"connected"}"#
+        );
+
+        let stats = ConnectStats {
+            attempts: 3,
+            succeeded: false,
+            total_ms: 10,
+            final_state: None,
+            error: Some(String::from("failed")),
+        };
+
+        assert_eq!(
+            build_connect_stats_output(&stats).dump(),
+            r#"{"attempts":3,"succeeded":false,"total_ms":10,"final_state":"unknown"}"#
+        );
+    }
+
+    #[test]
+    fn build_alfred_profiles_output_shape() {
+        let work = vec![String::from("address-1"), String::from("address-2")];
+        let home = vec![String::from("address-3")];
+
+        assert_eq!(
+            build_alfred_profiles_output(&[("home", &home), ("work", &work)]).dump(),
+            r#"{"items":[{"type":"default","title":"home","subtitle":"↵ Connect 1 device","arg":"home"},{"type":"default","title":"work","subtitle":"↵ Connect 2 devices","arg":"work"}]}"#
+        );
+    }
+
+    #[test]
+    fn build_event_output_shape() {
+        let event = DeviceEvent {
+            name: String::from("AirPods"),
+            address: String::from("80-3b-5c-c2-b1-7f"),
+            state: ConnectionState::Disconnected,
+            timestamp: Utc.with_ymd_and_hms(2022, 8, 1, 12, 0, 0).unwrap(),
+        };
+
+        assert_eq!(
+            build_event_output(&event).dump(),
+            r#"{"event":"disconnected","name":"AirPods","address":"80-3b-5c-c2-b1-7f","timestamp":"2022-08-01T12:00:00+00:00"}"#
+        );
+    }
+}
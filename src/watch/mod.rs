@@ -0,0 +1,20 @@
+use std::{ops::ControlFlow, time::Duration};
+
+use super::bluetooth::{BluetoothClient, DeviceEvent};
+use super::clock::Clock;
+
+// Polls the device list every poll interval, calling `on_event` for every
+// connection change. Runs until the process is killed.
+pub fn run_watch<F>(
+    client: &BluetoothClient,
+    clock: &dyn Clock,
+    poll_interval: Duration,
+    mut on_event: F,
+) where
+    F: FnMut(&DeviceEvent),
+{
+    client.subscribe(clock, poll_interval, |event| {
+        on_event(event);
+        ControlFlow::Continue(())
+    })
+}
@@ -0,0 +1,208 @@
+use std::{
+    env, fs, process,
+    process::Command,
+    time::{Duration, Instant},
+};
+
+use airpod_alfred_connector::bluetooth::{
+    BluetoothClient, DeviceFilters, DeviceInfo, DeviceListOptions, ErrorKind, FakeClient,
+    FakeFailure, FakeOperation, RetryPolicy,
+};
+use airpod_alfred_connector::output::{self, OutputFormat};
+use chrono::{TimeZone, Utc};
+
+const AIRPODS_MAX: &str = "a4-c6-f0-1d-52-e9";
+
+fn fake_client() -> FakeClient {
+    FakeClient::new(Utc.with_ymd_and_hms(2022, 8, 1, 12, 0, 0).unwrap())
+}
+
+fn connected(client: &BluetoothClient, address: &str) -> bool {
+    client.get_device(address).unwrap().connected
+}
+
+#[test]
+fn toggling_shows_up_in_the_alfred_list() {
+    let client = BluetoothClient::with_client(Box::new(fake_client()));
+
+    let address = client.resolve_device_id("AirPods Max").unwrap();
+    assert!(client.toggle_connected_status(&address).unwrap());
+
+    let devices = client
+        .get_device_list(DeviceListOptions::new(DeviceFilters::AllDevices, None))
+        .unwrap();
+    let rendered = output::render_devices(
+        devices,
+        OutputFormat::Alfred,
+        &Default::default(),
+        Utc::now(),
+    );
+    let items = json::parse(&rendered).unwrap()["items"].clone();
+
+    assert!(items
+        .members()
+        .any(|x| x["arg"] == AIRPODS_MAX && x["title"] == "AirPods Max (Connected)"));
+}
+
+#[test]
+fn verified_connects_catch_ignored_connects() {
+    let fake = fake_client();
+    fake.fail_next(FakeOperation::Connect, FakeFailure::Ignored);
+    let client = BluetoothClient::with_client(Box::new(fake));
+
+    let err = client
+        .connect_and_verify(AIRPODS_MAX, Duration::ZERO)
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectFailed);
+
+    // The scripted failure is used up
+    client
+        .connect_and_verify(AIRPODS_MAX, Duration::ZERO)
+        .unwrap();
+    assert!(connected(&client, AIRPODS_MAX));
+}
+
+#[test]
+fn verified_disconnects_retry_an_ignored_disconnect() {
+    let fake = fake_client();
+    fake.set_connected(AIRPODS_MAX, true).unwrap();
+    fake.fail_next(FakeOperation::Disconnect, FakeFailure::Ignored);
+    let client = BluetoothClient::with_client(Box::new(fake));
+
+    client
+        .disconnect_and_verify(AIRPODS_MAX, Duration::ZERO)
+        .unwrap();
+    assert!(!connected(&client, AIRPODS_MAX));
+}
+
+#[test]
+fn retries_get_past_connect_errors() {
+    let fake = fake_client();
+    fake.fail_next(
+        FakeOperation::Connect,
+        FakeFailure::Error(ErrorKind::Timeout),
+    );
+    let client = BluetoothClient::with_client(Box::new(fake));
+
+    let stats = client.connect_with_stats(
+        AIRPODS_MAX,
+        &RetryPolicy::new(1, Duration::ZERO),
+        Duration::ZERO,
+    );
+    assert_eq!(stats.attempts, 2);
+    assert!(stats.succeeded);
+}
+
+#[test]
+fn listing_errors_reach_the_caller() {
+    let fake = fake_client();
+    fake.fail_next(FakeOperation::List, FakeFailure::Error(ErrorKind::Timeout));
+    let client = BluetoothClient::with_client(Box::new(fake));
+
+    let err = client
+        .get_device_list(DeviceListOptions::new_default_all_devices())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Timeout);
+}
+
+#[test]
+fn connect_many_pays_the_latency_for_each_device() {
+    let fake = fake_client().with_latency(Duration::from_millis(20));
+    fake.add_device(DeviceInfo {
+        name: String::from("Studio Buds"),
+        address: String::from("10-94-bb-5a-3c-21"),
+        paired: true,
+        ..Default::default()
+    });
+    let client = BluetoothClient::with_client(Box::new(fake));
+
+    let started = Instant::now();
+    let results =
+        client.connect_many(&[String::from(AIRPODS_MAX), String::from("10-94-bb-5a-3c-21")]);
+
+    // The fake connects one at a time, so each connect pays the latency
+    assert!(started.elapsed() >= Duration::from_millis(40));
+    assert!(results.iter().all(|(_, result)| result.is_ok()));
+    assert!(connected(&client, "10-94-bb-5a-3c-21"));
+}
+
+#[test]
+fn cli_lists_and_connects_with_the_fake_backend() {
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_airpod_alfred_connector"))
+            .args(["--backend", "fake"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["list", "--format", "plain"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .any(|x| x == "AirPods Max\ta4-c6-f0-1d-52-e9\tdisconnected"));
+
+    let output = run(&["connect", "AirPods Max", "--verify"]);
+    assert!(output.status.success());
+
+    let output = run(&["connect", "Nobody's AirPods"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn cli_safe_mode_refuses_disconnects_without_confirm() {
+    let config = env::temp_dir().join(format!("safe-mode-{}.toml", process::id()));
+    fs::write(&config, "safe_mode = true\n").unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_airpod_alfred_connector"))
+            .env("AIRPOD_ALFRED_CONFIG", &config)
+            .args(["--backend", "fake"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let refused = |output: std::process::Output| {
+        !output.status.success()
+            && String::from_utf8_lossy(&output.stderr).contains("Safe mode is enabled")
+    };
+
+    // AirPods Pro starts out connected, AirPods Max disconnected
+    assert!(refused(run(&["toggle", "AirPods Pro"])));
+    assert!(refused(run(&["switch", "AirPods Pro", "AirPods Max"])));
+    assert!(refused(run(&["daemon", "--print-plist"])));
+
+    assert!(run(&["toggle", "AirPods Max"]).status.success());
+    assert!(run(&["toggle", "AirPods Pro", "--confirm"])
+        .status
+        .success());
+    assert!(run(&["switch", "AirPods Pro", "AirPods Max", "--confirm"])
+        .status
+        .success());
+
+    fs::remove_file(config).unwrap();
+}
+
+#[test]
+fn cli_info_and_status_resolve_aliases() {
+    let config = env::temp_dir().join(format!("aliases-{}.toml", process::id()));
+    fs::write(&config, "[aliases]\npro = \"80-3b-5c-c2-b1-7f\"\n").unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_airpod_alfred_connector"))
+            .env("AIRPOD_ALFRED_CONFIG", &config)
+            .args(["--backend", "fake"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    for command in ["info", "status"] {
+        let output = run(&[command, "pro"]);
+        assert!(output.status.success());
+        assert_eq!(
+            json::parse(&String::from_utf8_lossy(&output.stdout)).unwrap()["name"],
+            "AirPods Pro"
+        );
+    }
+
+    fs::remove_file(config).unwrap();
+}